@@ -7,13 +7,22 @@ use std::{sync::Arc, time::Duration};
 
 #[cfg(feature = "light-client")]
 use {
-    crate::{error::KeyLoadingError, BitcoinLight, PrivateKey},
-    std::path::PathBuf,
+    crate::{
+        error::KeyLoadingError,
+        keystore::{self, Keystore, WIF_CONTENT, WIF_PASSWORD_ENV},
+        BitcoinLight, PrivateKey,
+    },
+    std::path::{Path, PathBuf},
 };
 
 #[cfg(feature = "light-client")]
-fn get_private_key_from_file(file_path: &PathBuf) -> Result<PrivateKey, KeyLoadingError> {
-    let data = std::fs::read(file_path)?;
+fn get_private_key_from_file(file_path: &Path, password_file: Option<&Path>) -> Result<PrivateKey, KeyLoadingError> {
+    let mut data = std::fs::read(file_path)?;
+    if let Some(keystore) = Keystore::parse(&data) {
+        keystore.expect_content(WIF_CONTENT)?;
+        let password = keystore::read_password(password_file, WIF_PASSWORD_ENV, "Bitcoin key password: ")?;
+        data = keystore.decrypt(&password)?;
+    }
     let wif = String::from_utf8(data)?;
     Ok(PrivateKey::from_wif(wif.trim())?)
 }
@@ -55,6 +64,12 @@ pub struct BitcoinOpts {
     ))]
     #[cfg(feature = "light-client")]
     pub bitcoin_wif: Option<PathBuf>,
+
+    /// File containing the password of an encrypted `--bitcoin-wif` file. If unset,
+    /// the password is read from BITCOIN_WIF_PASSWORD or prompted for.
+    #[cfg_attr(feature = "light-client", clap(long, requires = "bitcoin_wif", value_parser))]
+    #[cfg(feature = "light-client")]
+    pub bitcoin_wif_password_file: Option<PathBuf>,
 }
 
 impl BitcoinOpts {
@@ -76,7 +91,10 @@ impl BitcoinOpts {
     fn new_light_client(&self) -> Result<BitcoinLight, Error> {
         Ok(BitcoinLight::new(
             self.electrs_url.clone(),
-            get_private_key_from_file(self.bitcoin_wif.as_ref().expect("Private key not set"))?,
+            get_private_key_from_file(
+                self.bitcoin_wif.as_ref().expect("Private key not set"),
+                self.bitcoin_wif_password_file.as_deref(),
+            )?,
         )?)
     }
 
@@ -109,6 +127,54 @@ impl BitcoinOpts {
         }
     }
 
+    /// Connects to Bitcoin Core to query its network, without creating or loading a wallet.
+    pub async fn get_network(&self) -> Result<Network, Error> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "light-client")] {
+                if self.light {
+                    return Ok(self.new_light_client()?.network());
+                }
+            }
+        }
+        let bitcoin_core = self
+            .new_client_builder(None)
+            .build_and_connect(Duration::from_millis(self.bitcoin_connection_timeout_ms))
+            .await?;
+        Ok(bitcoin_core.network())
+    }
+
+    /// Whether to run in light client mode.
+    pub fn is_light(&self) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "light-client")] {
+                self.light
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Connects to an existing wallet without creating it, e.g. to read its history while
+    /// the vault is not running.
+    pub async fn new_existing_wallet_client(
+        &self,
+        wallet_name: Option<String>,
+    ) -> Result<Arc<dyn BitcoinCoreApi + Send + Sync>, Error> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "light-client")] {
+                if self.light {
+                    return Ok(Arc::new(self.new_light_client()?));
+                }
+            }
+        }
+        let bitcoin_core = self
+            .new_client_builder(wallet_name)
+            .build_and_connect(Duration::from_millis(self.bitcoin_connection_timeout_ms))
+            .await?;
+        bitcoin_core.load_wallet().await?;
+        Ok(Arc::new(bitcoin_core))
+    }
+
     pub fn new_client_with_network(
         &self,
         wallet_name: Option<String>,
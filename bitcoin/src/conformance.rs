@@ -0,0 +1,216 @@
+//! Backend-agnostic conformance checks for implementations of [`BitcoinCoreApi`].
+//!
+//! Every backend the clients can run against must pass these checks before it ships. The
+//! checks only use the trait, so they encode the behavior the vault relies on rather than
+//! the behavior of a particular backend. Anything the trait cannot do by itself, such as
+//! producing blocks, is provided through a [`Harness`].
+
+use crate::{
+    deserialize, verify_tx_inclusion, Address, BitcoinCoreApi, Error, Hash, Network, Payload, SatPerVbyte, Transaction,
+    TransactionExt, TransactionMetadata, Txid, WPubkeyHash, H256,
+};
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoin::util::address::WitnessVersion;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+type DynBitcoinCoreApi = Arc<dyn BitcoinCoreApi + Send + Sync>;
+
+/// Amount of every payment made by the checks.
+pub const PAYMENT_AMOUNT: u64 = 10_000;
+/// Fee rate of every payment made by the checks.
+pub const PAYMENT_FEE_RATE: SatPerVbyte = SatPerVbyte(2);
+
+/// Operations the checks need which are not part of [`BitcoinCoreApi`].
+#[async_trait]
+pub trait Harness: Send + Sync {
+    /// Produce `count` blocks on top of the current best block, including all
+    /// transactions in the mempool.
+    async fn mine_blocks(&self, count: u32) -> Result<(), Error>;
+}
+
+/// A payment made through the backend under test.
+pub struct Payment {
+    pub recipient: Address,
+    pub request_id: H256,
+    pub metadata: TransactionMetadata,
+}
+
+/// Run every check against the given backend.
+pub async fn check_all(backend: &DynBitcoinCoreApi, harness: &impl Harness) -> Result<(), Error> {
+    let payment = make_payment(backend, harness).await?;
+    check_payment_with_op_return(backend, &payment).await?;
+    check_proof(backend, &payment).await?;
+    check_confirmations(backend, harness, &payment).await?;
+    check_deposit_key(backend).await?;
+    check_fee_bump(backend, harness).await?;
+    Ok(())
+}
+
+/// Pay to a fresh address with an OP_RETURN, and wait for the payment to be included in a block.
+pub async fn make_payment(backend: &DynBitcoinCoreApi, harness: &impl Harness) -> Result<Payment, Error> {
+    let (recipient, request_id) = unique_recipient(backend.network());
+    let txid = backend
+        .create_and_send_transaction(recipient.clone(), PAYMENT_AMOUNT, PAYMENT_FEE_RATE, Some(request_id))
+        .await?;
+    harness.mine_blocks(1).await?;
+    let metadata = backend.wait_for_transaction_metadata(txid, 1).await?;
+    assert_eq!(metadata.txid, txid, "metadata is for another transaction");
+
+    Ok(Payment {
+        recipient,
+        request_id,
+        metadata,
+    })
+}
+
+/// The included transaction pays the requested amount and carries the request id.
+pub async fn check_payment_with_op_return(backend: &DynBitcoinCoreApi, payment: &Payment) -> Result<(), Error> {
+    let metadata = &payment.metadata;
+    let transaction = backend
+        .get_transaction(&metadata.txid, Some(metadata.block_hash))
+        .await?;
+    assert_payment(&transaction, payment);
+
+    let raw_tx = backend.get_raw_tx(&metadata.txid, &metadata.block_hash).await?;
+    assert_eq!(raw_tx, metadata.raw_tx, "raw transaction differs from the metadata");
+    let transaction: Transaction = deserialize(&raw_tx)?;
+    assert_eq!(transaction.txid(), metadata.txid, "raw transaction has another txid");
+    Ok(())
+}
+
+/// The proofs returned by the backend prove inclusion in the block it reported.
+pub async fn check_proof(backend: &DynBitcoinCoreApi, payment: &Payment) -> Result<(), Error> {
+    let metadata = &payment.metadata;
+    let header = backend.get_block_header(&metadata.block_hash).await?;
+    verify_tx_inclusion(&metadata.txid, &header, &metadata.raw_tx, &metadata.proof)?;
+
+    let proof = backend.get_proof(metadata.txid, &metadata.block_hash).await?;
+    verify_tx_inclusion(&metadata.txid, &header, &metadata.raw_tx, &proof)?;
+    Ok(())
+}
+
+/// Heights, hashes and confirmations agree with each other, also after more blocks are produced.
+pub async fn check_confirmations(
+    backend: &DynBitcoinCoreApi,
+    harness: &impl Harness,
+    payment: &Payment,
+) -> Result<(), Error> {
+    let metadata = &payment.metadata;
+    assert_eq!(
+        backend.get_block_hash(metadata.block_height).await?,
+        metadata.block_hash,
+        "block hash at the reported height differs"
+    );
+    assert!(backend.get_block_count().await? >= metadata.block_height as u64);
+
+    let block = backend.wait_for_block(metadata.block_height, 1).await?;
+    assert_eq!(block.block_hash(), metadata.block_hash, "waited for the wrong block");
+    assert!(block.txdata.iter().any(|x| x.txid() == metadata.txid));
+
+    harness.mine_blocks(2).await?;
+    let block = backend.wait_for_block(metadata.block_height, 3).await?;
+    assert_eq!(block.block_hash(), metadata.block_hash, "waited for the wrong block");
+    let best_height = backend.get_block_count().await? as u32;
+    assert_eq!(
+        backend.get_best_block_hash().await?,
+        backend.get_block_hash(best_height).await?,
+        "best block differs from the block at the best height"
+    );
+
+    let confirmed = backend.wait_for_transaction_metadata(metadata.txid, 3).await?;
+    assert_eq!(confirmed.block_hash, metadata.block_hash);
+    assert_eq!(confirmed.block_height, metadata.block_height);
+    Ok(())
+}
+
+/// Public keys can be used for deposits, and registering a deposit key twice is harmless.
+pub async fn check_deposit_key(backend: &DynBitcoinCoreApi) -> Result<(), Error> {
+    let public_key = backend.get_new_public_key().await?;
+    assert!(public_key.compressed, "deposit keys must be compressed");
+
+    let secret_key = H256::from_low_u64_be(unique_nonce()).to_fixed_bytes().to_vec();
+    backend.add_new_deposit_key(public_key, secret_key.clone()).await?;
+    backend.add_new_deposit_key(public_key, secret_key).await?;
+    Ok(())
+}
+
+/// Transactions in the mempool can be bumped without losing the payment, confirmed
+/// transactions can not.
+pub async fn check_fee_bump(backend: &DynBitcoinCoreApi, harness: &impl Harness) -> Result<(), Error> {
+    let (recipient, request_id) = unique_recipient(backend.network());
+    let txid = backend
+        .create_and_send_transaction(recipient.clone(), PAYMENT_AMOUNT, PAYMENT_FEE_RATE, Some(request_id))
+        .await?;
+
+    if backend.is_in_mempool(txid).await? {
+        let fee_rate = backend.fee_rate(txid).await?;
+        assert!(fee_rate >= PAYMENT_FEE_RATE, "fee rate is lower than requested");
+
+        let bumped_fee_rate = SatPerVbyte(fee_rate.0 * 2);
+        let bumped_txid = backend.bump_fee(&txid, recipient.clone(), bumped_fee_rate).await?;
+        assert_ne!(bumped_txid, txid);
+        assert!(backend.is_in_mempool(bumped_txid).await?);
+        assert!(
+            !backend.is_in_mempool(txid).await?,
+            "replaced transaction is still in the mempool"
+        );
+        assert!(backend.fee_rate(bumped_txid).await? >= bumped_fee_rate);
+
+        harness.mine_blocks(1).await?;
+        let metadata = backend.wait_for_transaction_metadata(bumped_txid, 1).await?;
+        let transaction: Transaction = deserialize(&metadata.raw_tx)?;
+        assert_payment(
+            &transaction,
+            &Payment {
+                recipient,
+                request_id,
+                metadata,
+            },
+        );
+    } else {
+        // the backend included the transaction right away
+        harness.mine_blocks(1).await?;
+        backend.wait_for_transaction_metadata(txid, 1).await?;
+        assert!(backend
+            .bump_fee(&txid, recipient, SatPerVbyte(PAYMENT_FEE_RATE.0 * 2))
+            .await
+            .is_err());
+    }
+    Ok(())
+}
+
+fn assert_payment(transaction: &Transaction, payment: &Payment) {
+    assert_eq!(
+        transaction.get_payment_amount_to(payment.recipient.payload.clone()),
+        Some(PAYMENT_AMOUNT),
+        "transaction does not pay the recipient"
+    );
+    assert_eq!(
+        transaction.get_op_return(),
+        Some(payment.request_id),
+        "transaction does not carry the request id"
+    );
+}
+
+fn unique_nonce() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_nanos() as u64
+}
+
+/// A request id and an address that no backend wallet owns, unique to each call.
+fn unique_recipient(network: Network) -> (Address, H256) {
+    let request_id = H256::from_low_u64_be(unique_nonce());
+    let recipient = Address {
+        payload: Payload::WitnessProgram {
+            version: WitnessVersion::V0,
+            program: WPubkeyHash::hash(request_id.as_bytes()).to_vec(),
+        },
+        network,
+    };
+    (recipient, request_id)
+}
@@ -36,3 +36,10 @@ pub enum Error {
     #[error("ParseIntError: {0}")]
     ParseIntError(#[from] ParseIntError),
 }
+
+impl Error {
+    /// True if electrs does not know the requested item (yet).
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::ReqwestError(err) if err.status() == Some(reqwest::StatusCode::NOT_FOUND))
+    }
+}
@@ -4,13 +4,16 @@ pub use error::Error;
 
 use crate::{
     deserialize, opcodes, serialize, Address, Block, BlockHash, BlockHeader, Builder as ScriptBuilder, FromHex,
-    Network, OutPoint, Script, SignedAmount, ToHex, Transaction, Txid, H256,
+    Network, OutPoint, Script, SignedAmount, ToHex, Transaction, TxOut, Txid, H256,
 };
 use esplora_btc_api::models::{Transaction as ElectrsTransaction, Utxo as ElectrsUtxo};
-use futures::future::{join_all, try_join};
+use futures::{
+    future::{self, join_all, try_join},
+    stream::{self, Stream, TryStreamExt},
+};
 use reqwest::{Client, Url};
 use sha2::{Digest, Sha256};
-use std::str::FromStr;
+use std::{pin::Pin, str::FromStr};
 
 const ELECTRS_TRANSACTIONS_PER_PAGE: usize = 25;
 
@@ -39,6 +42,37 @@ pub struct TxInfo {
     pub fee: SignedAmount,
 }
 
+/// A page of the transaction history of an address or script hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HistoryPage {
+    /// Unconfirmed transactions, esplora returns them all at once.
+    Mempool,
+    /// Confirmed transactions older than the given transaction, or the newest if empty.
+    Chain { last_seen_txid: String },
+}
+
+impl HistoryPage {
+    fn path(&self, base_path: &str) -> String {
+        match self {
+            HistoryPage::Mempool => format!("{base_path}/txs/mempool"),
+            HistoryPage::Chain { last_seen_txid } => format!("{base_path}/txs/chain/{last_seen_txid}"),
+        }
+    }
+
+    fn next(&self, page_size: usize, last_txid: Option<String>) -> Option<Self> {
+        match (self, last_txid) {
+            (HistoryPage::Mempool, _) => Some(HistoryPage::Chain {
+                last_seen_txid: Default::default(),
+            }),
+            (HistoryPage::Chain { .. }, Some(last_seen_txid)) if page_size >= ELECTRS_TRANSACTIONS_PER_PAGE => {
+                Some(HistoryPage::Chain { last_seen_txid })
+            }
+            // no further pages
+            (HistoryPage::Chain { .. }, _) => None,
+        }
+    }
+}
+
 // NOTE: the `esplora_btc_api` OpenAPI lib build cannot decode plain strings
 // (using `serde_json::from_str`) and it doesn't support paged api calls
 #[derive(Clone)]
@@ -93,22 +127,51 @@ impl ElectrsClient {
         )?)
     }
 
-    pub(crate) async fn get_address_tx_history_full(&self, address: &str) -> Result<Vec<ElectrsTransaction>, Error> {
-        let mut last_seen_txid = Default::default();
-        let mut ret = Vec::<ElectrsTransaction>::new();
-        loop {
-            let mut transactions: Vec<ElectrsTransaction> = self
-                .get_and_decode(&format!("/address/{address}/txs/chain/{last_seen_txid}"))
-                .await?;
-            let page_size = transactions.len();
-            last_seen_txid = transactions.last().map_or(Default::default(), |tx| tx.txid.clone());
-            ret.append(&mut transactions);
-            if page_size < ELECTRS_TRANSACTIONS_PER_PAGE {
-                // no further pages
-                break;
+    /// Stream the history of an address or script hash, e.g. `/address/:address`. Mempool
+    /// transactions come first, followed by confirmed transactions from newest to oldest.
+    /// Pages are only fetched while the stream is polled, so consumers can stop early.
+    pub(crate) fn stream_tx_history<'a>(
+        &'a self,
+        base_path: String,
+    ) -> Pin<Box<dyn Stream<Item = Result<ElectrsTransaction, Error>> + Send + 'a>> {
+        let pages = stream::try_unfold(Some(HistoryPage::Mempool), move |page| {
+            let base_path = base_path.clone();
+            async move {
+                let page = match page {
+                    Some(page) => page,
+                    None => return Ok(None),
+                };
+                let transactions: Vec<ElectrsTransaction> = self.get_and_decode(&page.path(&base_path)).await?;
+                let next_page = page.next(transactions.len(), transactions.last().map(|tx| tx.txid.clone()));
+                Ok::<_, Error>(Some((stream::iter(transactions.into_iter().map(Ok)), next_page)))
             }
-        }
-        Ok(ret)
+        });
+        Box::pin(pages.try_flatten())
+    }
+
+    pub(crate) fn stream_address_tx_history<'a>(
+        &'a self,
+        address: &Address,
+    ) -> Pin<Box<dyn Stream<Item = Result<ElectrsTransaction, Error>> + Send + 'a>> {
+        self.stream_tx_history(format!("/address/{address}"))
+    }
+
+    /// Get the confirmed transactions of the address, with the hash and height of their blocks.
+    pub(crate) async fn get_address_tx_history(
+        &self,
+        address: &Address,
+    ) -> Result<Vec<(Txid, (BlockHash, u32))>, Error> {
+        self.stream_address_tx_history(address)
+            .try_filter_map(|tx| async move {
+                let block = match tx.status.map(|status| (status.block_height, status.block_hash)) {
+                    Some((Some(height), Some(hash))) => (BlockHash::from_str(&hash)?, height as u32),
+                    // not confirmed yet
+                    _ => return Ok(None),
+                };
+                Ok::<_, Error>(Some((Txid::from_str(&tx.txid)?, block)))
+            })
+            .try_collect()
+            .await
     }
 
     pub(crate) async fn get_blocks_tip_height(&self) -> Result<u32, Error> {
@@ -167,7 +230,8 @@ impl ElectrsClient {
             _ => return Err(Error::InvalidAddress),
         };
         Ok(TxInfo {
-            confirmations: tip.saturating_sub(height),
+            // same as bitcoin core, the block itself counts as a confirmation
+            confirmations: tip.saturating_sub(height) + 1,
             height,
             hash: BlockHash::from_str(&hash)?,
             fee: SignedAmount::from_sat(tx.fee.unwrap_or_default() as i64),
@@ -191,6 +255,11 @@ impl ElectrsClient {
             .collect::<Result<Vec<_>, Error>>()
     }
 
+    pub(crate) async fn get_prev_out(&self, outpoint: OutPoint) -> Result<TxOut, Error> {
+        let tx: Transaction = deserialize(&self.get_raw_tx(&outpoint.txid).await?)?;
+        tx.output.get(outpoint.vout as usize).cloned().ok_or(Error::NoPrevOut)
+    }
+
     pub(crate) async fn get_script_pubkey(&self, outpoint: OutPoint) -> Result<Script, Error> {
         let tx: ElectrsTransaction = self
             .get_and_decode(&format!("/tx/{txid}", txid = outpoint.txid))
@@ -233,21 +302,16 @@ impl ElectrsClient {
             hasher.result().as_slice().to_vec()
         };
 
-        // TODO: page this using last_seen_txid
-        let txs: Vec<ElectrsTransaction> = self
-            .get_and_decode(&format!(
-                "/scripthash/{scripthash}/txs",
-                scripthash = script_hash.to_hex()
-            ))
+        // only confirmed transactions can be proven, stop at the first one
+        let tx = self
+            .stream_tx_history(format!("/scripthash/{scripthash}", scripthash = script_hash.to_hex()))
+            .try_filter(|tx| future::ready(matches!(&tx.status, Some(status) if status.confirmed)))
+            .try_next()
             .await?;
-        log::info!("Found {} transactions", txs.len());
 
-        // for now, use the first tx - should probably return
-        // an error if there are more that one
-        if let Some(tx) = txs.first().cloned() {
+        if let Some(tx) = tx {
             let txid = Txid::from_str(&tx.txid)?;
             log::info!("Fetching merkle proof");
-            // TODO: return error if not confirmed
             let raw_merkle_proof = self.get_raw_tx_merkle_proof(&txid).await?;
 
             log::info!("Fetching transaction");
@@ -287,6 +351,25 @@ mod tests {
         assert!(txs.iter().any(|tx| { &tx.txid == expected_txid }));
     }
 
+    #[test]
+    fn test_history_pages() {
+        let first_chain_page = HistoryPage::Chain {
+            last_seen_txid: Default::default(),
+        };
+        assert_eq!(HistoryPage::Mempool.next(0, None), Some(first_chain_page.clone()));
+        assert_eq!(first_chain_page.path("/address/foo"), "/address/foo/txs/chain/");
+        assert_eq!(
+            first_chain_page.next(ELECTRS_TRANSACTIONS_PER_PAGE, Some("bar".to_string())),
+            Some(HistoryPage::Chain {
+                last_seen_txid: "bar".to_string()
+            })
+        );
+        assert_eq!(
+            first_chain_page.next(ELECTRS_TRANSACTIONS_PER_PAGE - 1, Some("bar".to_string())),
+            None
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_find_esplora_mainnet() {
         let script_hex = "6a24aa21a9ed932d00baa7d428106db4f785d398d60d0b9c1369c38448717db4a8f36d2512e3";
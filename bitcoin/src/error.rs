@@ -1,4 +1,5 @@
 use crate::{BitcoinError, BitcoinLightError, ElectrsError};
+use base64::DecodeError as Base64DecodeError;
 use bitcoincore_rpc::{
     bitcoin::{
         consensus::encode::Error as BitcoinEncodeError,
@@ -12,7 +13,7 @@ use hex::FromHexError;
 use serde_json::Error as SerdeJsonError;
 use std::{io::Error as IoError, num::TryFromIntError, string::FromUtf8Error};
 use thiserror::Error;
-use tokio::time::error::Elapsed;
+use tokio::{task::JoinError, time::error::Elapsed};
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
@@ -23,6 +24,34 @@ pub enum KeyLoadingError {
     FromUtf8Error(#[from] FromUtf8Error),
     #[error("KeyError: {0}")]
     KeyError(#[from] KeyError),
+    #[error("KeystoreError: {0}")]
+    KeystoreError(#[from] KeystoreError),
+}
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("IoError: {0}")]
+    IoError(#[from] IoError),
+    #[error("SerdeJsonError: {0}")]
+    SerdeJsonError(#[from] SerdeJsonError),
+    #[error("Base64DecodeError: {0}")]
+    Base64DecodeError(#[from] Base64DecodeError),
+    #[error("Unsupported keystore encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("Unexpected keystore content: {0}")]
+    UnexpectedContent(String),
+    #[error("Invalid keystore length")]
+    InvalidLength,
+    #[error("Invalid scrypt parameters")]
+    InvalidScryptParams,
+    #[error("Failed to encrypt keystore")]
+    EncryptionFailed,
+    #[error("Failed to decrypt keystore, the password may be wrong")]
+    DecryptionFailed,
+    #[error("No password given, set {0} or run in a terminal to be prompted")]
+    NoPassword(String),
+    #[error("Passwords do not match")]
+    PasswordMismatch,
 }
 
 #[derive(Error, Debug)]
@@ -72,8 +101,16 @@ pub enum Error {
     ArithmeticError,
     #[error("MissingBitcoinFeeInfo")]
     MissingBitcoinFeeInfo,
+    #[error("JoinError: {0}")]
+    JoinError(#[from] JoinError),
     #[error("FailedToConstructWalletName")]
     FailedToConstructWalletName,
+    #[error("Transaction does not match the requested txid")]
+    InvalidTransaction,
+    #[error("Merkle proof does not match the locally known block header")]
+    InvalidMerkleProof,
+    #[error("The fee of the funded transaction of {0} vbytes exceeds the maximum fee")]
+    FeeExceedsMax(u64),
 }
 
 impl Error {
@@ -122,6 +159,19 @@ impl Error {
         )
     }
 
+    /// True if bitcoin core no longer has the data of the requested block. This is reported
+    /// as "Block not available" by `getrawtransaction`, but as "Can't read block from disk"
+    /// by `gettxoutproof`.
+    pub fn is_block_pruned(&self) -> bool {
+        matches!(self,
+            Error::BitcoinError(BitcoinError::JsonRpc(JsonRpcError::Rpc(err)))
+                if (BitcoinRpcError::from(err.clone()) == BitcoinRpcError::RpcMiscError
+                    && err.message.contains("not available"))
+                    || (BitcoinRpcError::from(err.clone()) == BitcoinRpcError::RpcInternalError
+                        && err.message.contains("Can't read block from disk"))
+        )
+    }
+
     pub fn is_invalid_parameter(&self) -> bool {
         matches!(self,
             Error::BitcoinError(BitcoinError::JsonRpc(JsonRpcError::Rpc(err)))
@@ -220,3 +270,33 @@ impl From<RpcError> for BitcoinRpcError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: BitcoinRpcError, message: &str) -> Error {
+        Error::BitcoinError(BitcoinError::JsonRpc(JsonRpcError::Rpc(RpcError {
+            code: code as i32,
+            message: message.to_string(),
+            data: None,
+        })))
+    }
+
+    #[test]
+    fn test_is_block_pruned() {
+        // getrawtransaction
+        assert!(rpc_error(BitcoinRpcError::RpcMiscError, "Block not available").is_block_pruned());
+        // gettxoutproof
+        assert!(rpc_error(BitcoinRpcError::RpcInternalError, "Can't read block from disk").is_block_pruned());
+
+        assert!(!rpc_error(
+            BitcoinRpcError::RpcInvalidAddressOrKey,
+            "No such mempool or blockchain transaction"
+        )
+        .is_block_pruned());
+        assert!(!rpc_error(BitcoinRpcError::RpcInternalError, "Work queue depth exceeded").is_block_pruned());
+        assert!(!rpc_error(BitcoinRpcError::RpcMiscError, "Transaction not yet in block").is_block_pruned());
+        assert!(!Error::InvalidTransaction.is_block_pruned());
+    }
+}
@@ -6,10 +6,19 @@ use bitcoincore_rpc::{
 };
 use futures::{prelude::*, stream::StreamExt};
 use log::trace;
-use std::{iter, sync::Arc};
+use std::{
+    iter,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::task::JoinHandle;
 
 type DynBitcoinCoreApi = Arc<dyn BitcoinCoreApi + Send + Sync>;
 
+/// Maximum number of blocks that are fetched ahead of the consumer of a block stream.
+const PREFETCH_WINDOW: usize = 8;
+
 /// Stream over transactions, starting with this in the mempool and continuing with
 /// transactions from previous in-chain block. The stream ends after the block at
 /// `stop_height` has been returned.
@@ -66,49 +75,66 @@ pub async fn reverse_stream_blocks(
     rpc: &DynBitcoinCoreApi,
     stop_height: u32,
 ) -> impl Stream<Item = Result<Block, Error>> + Unpin + '_ {
-    struct StreamState<B> {
-        height: Option<u32>,
-        prev_block: Option<Block>,
-        rpc: B,
-        stop_height: u32,
+    struct StreamState<S> {
+        blocks: S,
+        rpc: DynBitcoinCoreApi,
+        expected_hash: Option<BlockHash>,
     }
 
+    // blocks are fetched by height so that they can be prefetched in parallel
+    let blocks = stream::once(get_best_block_info(rpc))
+        .map(move |best_block_info| match best_block_info {
+            Ok((best_height, best_hash)) => {
+                let rpc = rpc.clone();
+                stream::iter((stop_height..=best_height).rev())
+                    .map(move |height| {
+                        let rpc = rpc.clone();
+                        spawn_fetch(async move {
+                            let hash = if height == best_height {
+                                best_hash
+                            } else {
+                                rpc.get_block_hash(height).await?
+                            };
+                            Ok((hash, rpc.get_block(&hash).await?))
+                        })
+                    })
+                    .buffered(PREFETCH_WINDOW)
+                    .left_stream()
+            }
+            Err(e) => stream::once(future::ready(Err(e))).right_stream(),
+        })
+        .flatten();
+
     let state = StreamState {
-        height: None,
-        prev_block: None,
-        rpc,
-        stop_height,
+        blocks: Box::pin(blocks),
+        rpc: rpc.clone(),
+        expected_hash: None,
     };
 
     Box::pin(
-        stream::unfold(state, |mut state| async {
-            // get height and hash of the block we potentially are about to fetch
-            let (next_height, next_hash) = match (&state.height, &state.prev_block) {
-                (Some(height), Some(block)) => (height.saturating_sub(1), block.header.prev_blockhash),
-                _ => match get_best_block_info(state.rpc).await {
-                    Ok((height, hash)) => (height, hash),
-                    Err(e) => return Some((Err(e), state)), // abort
+        stream::unfold(state, |mut state| async move {
+            let result = match state.blocks.next().await? {
+                Ok((hash, block)) => match state.expected_hash {
+                    // the chain was reorganized since we started, so keep following the
+                    // chain of the best block at the start rather than the prefetched one
+                    Some(expected_hash) if expected_hash != hash => state.rpc.get_block(&expected_hash).await,
+                    _ => Ok(block),
                 },
+                Err(e) => Err(e),
             };
 
-            let result = if next_height < state.stop_height {
-                return None;
-            } else {
-                match state.rpc.get_block(&next_hash).await {
-                    Ok(block) => {
-                        state.height = Some(next_height);
-                        state.prev_block = Some(block.clone());
-                        Ok(block)
-                    }
-                    Err(Error::BitcoinError(BitcoinError::JsonRpc(JsonRpcError::Rpc(err))))
-                        if BitcoinRpcError::from(err.clone()) == BitcoinRpcError::RpcMiscError =>
-                    {
-                        return None; // pruned block
-                    }
-                    Err(e) => Err(e),
+            match result {
+                Ok(block) => {
+                    state.expected_hash = Some(block.header.prev_blockhash);
+                    Some((Ok(block), state))
                 }
-            };
-            Some((result, state))
+                Err(Error::BitcoinError(BitcoinError::JsonRpc(JsonRpcError::Rpc(err))))
+                    if BitcoinRpcError::from(err.clone()) == BitcoinRpcError::RpcMiscError =>
+                {
+                    None // pruned block
+                }
+                Err(e) => Some((Err(e), state)),
+            }
         })
         .fuse(),
     )
@@ -154,26 +180,48 @@ pub async fn stream_blocks(
     from_height: u32,
     num_confirmations: u32,
 ) -> impl Stream<Item = Result<Block, Error>> + Unpin {
-    struct StreamState<B> {
-        rpc: B,
+    type BlockStream = Pin<Box<dyn Stream<Item = Result<Block, Error>> + Send>>;
+
+    struct StreamState {
+        rpc: DynBitcoinCoreApi,
         next_height: u32,
+        blocks: Option<BlockStream>,
+    }
+
+    fn prefetch_blocks(rpc: &DynBitcoinCoreApi, from_height: u32, num_confirmations: u32) -> BlockStream {
+        let rpc = rpc.clone();
+        Box::pin(
+            stream::iter(from_height..)
+                .map(move |height| {
+                    let rpc = rpc.clone();
+                    spawn_fetch(async move { rpc.wait_for_block(height, num_confirmations).await })
+                })
+                .buffered(PREFETCH_WINDOW),
+        )
     }
 
     let state = StreamState {
         rpc,
         next_height: from_height,
+        blocks: None,
     };
 
     Box::pin(
         stream::unfold(state, move |mut state| async move {
             // FIXME: if Bitcoin Core forks, this may skip a block
             let height = state.next_height;
-            match state.rpc.wait_for_block(height, num_confirmations).await {
+            let mut blocks = state
+                .blocks
+                .take()
+                .unwrap_or_else(|| prefetch_blocks(&state.rpc, height, num_confirmations));
+            match blocks.next().await? {
                 Ok(block) => {
                     trace!("found block {} at height {}", block.block_hash(), height);
                     state.next_height += 1;
+                    state.blocks = Some(blocks);
                     Some((Ok(block), state))
                 }
+                // the prefetched blocks are discarded, so that this height is retried
                 Err(e) => Some((Err(e), state)),
             }
         })
@@ -181,6 +229,33 @@ pub async fn stream_blocks(
     )
 }
 
+/// Handle to a fetch running on its own task, which is aborted when the handle is dropped.
+struct SpawnedFetch<T>(JoinHandle<Result<T, Error>>);
+
+impl<T> Future for SpawnedFetch<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map(|result| result?)
+    }
+}
+
+impl<T> Drop for SpawnedFetch<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run a fetch on a separate task, such that the blocking rpc calls of multiple
+/// fetches can make progress in parallel.
+fn spawn_fetch<T, F>(future: F) -> SpawnedFetch<T>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+{
+    SpawnedFetch(tokio::task::spawn(future))
+}
+
 /// small helper function for getting the block info of the best block. This simplifies
 /// error handling a little bit
 async fn get_best_block_info(rpc: &DynBitcoinCoreApi) -> Result<(u32, BlockHash), Error> {
@@ -206,6 +281,7 @@ mod tests {
             async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, Error>;
             fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, Error>;
             fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, Error>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, Error>;
             async fn get_block_count(&self) -> Result<u64, Error>;
             async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error>;
             async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, Error>;
@@ -239,6 +315,14 @@ mod tests {
                 fee_rate: SatPerVbyte,
                 request_id: Option<H256>,
             ) -> Result<Txid, Error>;
+            async fn create_and_send_transaction_with_max_fee(
+                &self,
+                address: Address,
+                sat: u64,
+                fee_rate: SatPerVbyte,
+                max_fee: u64,
+                request_id: Option<H256>,
+            ) -> Result<Txid, Error>;
             async fn send_to_address(
                 &self,
                 address: Address,
@@ -299,6 +383,18 @@ mod tests {
         }
     }
 
+    /// expect the hashes of the blocks from `best_height` down to `stop_height` to be
+    /// requested once, where the best block has `dummy_hash(1)`
+    fn expect_block_hashes(bitcoin: &mut MockBitcoin, best_height: u32, stop_height: u32) {
+        for height in stop_height..=best_height {
+            bitcoin
+                .expect_get_block_hash()
+                .withf(move |&x| x == height)
+                .times(1)
+                .returning(move |_| Ok(dummy_hash((best_height - height + 1) as u8)));
+        }
+    }
+
     #[tokio::test]
     async fn test_transaction_iterator_succeeds() {
         // we abuse version number within the transaction to check whether the sequence is correct
@@ -320,10 +416,7 @@ mod tests {
             .times(1)
             .returning(|_| Ok(dummy_block(vec![3, 4, 5], dummy_hash(3))));
         bitcoin.expect_get_block_count().times(1).returning(|| Ok(21));
-        bitcoin
-            .expect_get_block_hash()
-            .times(1)
-            .returning(|_| Ok(dummy_hash(1)));
+        expect_block_hashes(&mut bitcoin, 21, 20);
 
         let btc_rpc: DynBitcoinCoreApi = Arc::new(bitcoin);
         let mut iter = reverse_stream_transactions(&btc_rpc, 20).await.unwrap();
@@ -367,10 +460,7 @@ mod tests {
             .times(1)
             .returning(|_| Ok(dummy_block(vec![], dummy_hash(5))));
         bitcoin.expect_get_block_count().times(1).returning(|| Ok(23));
-        bitcoin
-            .expect_get_block_hash()
-            .times(1)
-            .returning(|_| Ok(dummy_hash(1)));
+        expect_block_hashes(&mut bitcoin, 23, 20);
 
         let btc_rpc: DynBitcoinCoreApi = Arc::new(bitcoin);
         let mut iter = reverse_stream_transactions(&btc_rpc, 20).await.unwrap();
@@ -449,4 +539,62 @@ mod tests {
         assert_eq!(iter.next().await.unwrap().unwrap().version, 1);
         assert!(iter.next().await.is_none());
     }
+
+    #[tokio::test]
+    async fn test_transaction_iterator_follows_initial_chain_after_reorg() {
+        let mut bitcoin = MockBitcoin::default();
+        bitcoin
+            .expect_get_mempool_transactions()
+            .times(1)
+            .returning(|| Ok(Box::new(vec![].into_iter())));
+        bitcoin.expect_get_block_count().times(1).returning(|| Ok(21));
+        bitcoin
+            .expect_get_block_hash()
+            .withf(|&x| x == 21)
+            .times(1)
+            .returning(|_| Ok(dummy_hash(1)));
+        // block 20 was replaced by a fork after the best block was determined
+        bitcoin
+            .expect_get_block_hash()
+            .withf(|&x| x == 20)
+            .times(1)
+            .returning(|_| Ok(dummy_hash(9)));
+        bitcoin
+            .expect_get_block()
+            .withf(|&x| x == dummy_hash(1))
+            .times(1)
+            .returning(|_| Ok(dummy_block(vec![1], dummy_hash(2))));
+        bitcoin
+            .expect_get_block()
+            .withf(|&x| x == dummy_hash(9))
+            .times(1)
+            .returning(|_| Ok(dummy_block(vec![9], dummy_hash(3))));
+        bitcoin
+            .expect_get_block()
+            .withf(|&x| x == dummy_hash(2))
+            .times(1)
+            .returning(|_| Ok(dummy_block(vec![2], dummy_hash(3))));
+
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(bitcoin);
+        let mut iter = reverse_stream_transactions(&btc_rpc, 20).await.unwrap();
+
+        assert_eq!(iter.next().await.unwrap().unwrap().version, 1);
+        assert_eq!(iter.next().await.unwrap().unwrap().version, 2);
+        assert!(iter.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_blocks_yields_prefetched_blocks_in_order() {
+        let mut bitcoin = MockBitcoin::default();
+        bitcoin
+            .expect_wait_for_block()
+            .returning(|height, _| Ok(dummy_block(vec![height as i32], dummy_hash(0))));
+
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(bitcoin);
+        let mut iter = stream_blocks(btc_rpc, 10, 1).await;
+
+        for height in 10..30 {
+            assert_eq!(iter.next().await.unwrap().unwrap().txdata[0].version, height);
+        }
+    }
 }
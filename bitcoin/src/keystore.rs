@@ -0,0 +1,215 @@
+//! Passphrase protected key files. The encryption is that of the polkadot-js JSON keystore
+//! (scrypt + xsalsa20-poly1305), so that accounts exported from polkadot-js can be loaded
+//! directly. Our own key files use the same envelope with a different `content` type.
+
+use crate::error::KeystoreError;
+use serde::{Deserialize, Serialize};
+use std::{convert::TryInto, path::Path};
+use xsalsa20poly1305::{
+    aead::{rand_core::RngCore, AeadInPlace, KeyInit, OsRng},
+    Key, Nonce, Tag, XSalsa20Poly1305,
+};
+
+const KDF: &str = "scrypt";
+const CIPHER: &str = "xsalsa20-poly1305";
+const VERSION: &str = "3";
+
+const SALT_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+const TAG_LENGTH: usize = 16;
+const KEY_LENGTH: usize = 32;
+// the scrypt parameters are stored as three u32s after the salt
+const PARAMS_LENGTH: usize = SALT_LENGTH + 12;
+
+/// Content type of an encrypted WIF encoded bitcoin private key.
+pub const WIF_CONTENT: &[&str] = &["wif"];
+/// Environment variable holding the password of an encrypted `--bitcoin-wif` file.
+pub const WIF_PASSWORD_ENV: &str = "BITCOIN_WIF_PASSWORD";
+
+// defaults of polkadot-js
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// An encrypted key file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keystore {
+    /// Base64 encoding of the scrypt salt and parameters, the nonce and the ciphertext.
+    pub encoded: String,
+    pub encoding: KeystoreEncoding,
+    /// Only set in polkadot-js keystores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Only set in polkadot-js keystores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeystoreEncoding {
+    /// Type of the encrypted content, e.g. `["pkcs8", "sr25519"]`.
+    pub content: Vec<String>,
+    #[serde(rename = "type")]
+    pub kind: Vec<String>,
+    pub version: String,
+}
+
+impl Keystore {
+    /// Parses `data` as a keystore, or returns `None` if it is not one, e.g. because it
+    /// is a plaintext key file.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+
+    pub fn encrypt(content: &[&str], plaintext: &[u8], password: &str) -> Result<Self, KeystoreError> {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+
+        let params =
+            scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P).map_err(|_| KeystoreError::InvalidScryptParams)?;
+        let key = derive_key(password, &salt, &params)?;
+        let mut ciphertext = plaintext.to_vec();
+        let tag = XSalsa20Poly1305::new(Key::from_slice(&key))
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", &mut ciphertext)
+            .map_err(|_| KeystoreError::EncryptionFailed)?;
+
+        let mut encoded = salt.to_vec();
+        encoded.extend_from_slice(&(1u32 << SCRYPT_LOG_N).to_le_bytes());
+        encoded.extend_from_slice(&SCRYPT_P.to_le_bytes());
+        encoded.extend_from_slice(&SCRYPT_R.to_le_bytes());
+        encoded.extend_from_slice(&nonce);
+        // secretbox places the tag in front of the ciphertext
+        encoded.extend_from_slice(&tag);
+        encoded.extend_from_slice(&ciphertext);
+
+        Ok(Self {
+            encoded: base64::encode(encoded),
+            encoding: KeystoreEncoding {
+                content: content.iter().map(|x| x.to_string()).collect(),
+                kind: vec![KDF.to_string(), CIPHER.to_string()],
+                version: VERSION.to_string(),
+            },
+            address: None,
+            meta: None,
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Vec<u8>, KeystoreError> {
+        if self.encoding.version != VERSION
+            || !self.encoding.kind.iter().any(|x| x == KDF)
+            || !self.encoding.kind.iter().any(|x| x == CIPHER)
+        {
+            return Err(KeystoreError::UnsupportedEncoding(format!(
+                "version {} with {}",
+                self.encoding.version,
+                self.encoding.kind.join(", ")
+            )));
+        }
+
+        let encoded = base64::decode(&self.encoded)?;
+        if encoded.len() < PARAMS_LENGTH + NONCE_LENGTH + TAG_LENGTH {
+            return Err(KeystoreError::InvalidLength);
+        }
+        let (salt, rest) = encoded.split_at(SALT_LENGTH);
+        let (params, rest) = rest.split_at(PARAMS_LENGTH - SALT_LENGTH);
+        let (nonce, rest) = rest.split_at(NONCE_LENGTH);
+        let (tag, ciphertext) = rest.split_at(TAG_LENGTH);
+
+        let read_u32 = |i: usize| u32::from_le_bytes(params[i * 4..(i + 1) * 4].try_into().unwrap());
+        let (n, p, r) = (read_u32(0), read_u32(1), read_u32(2));
+        if !n.is_power_of_two() {
+            return Err(KeystoreError::InvalidScryptParams);
+        }
+        let params =
+            scrypt::Params::new(n.trailing_zeros() as u8, r, p).map_err(|_| KeystoreError::InvalidScryptParams)?;
+        let key = derive_key(password, salt, &params)?;
+
+        let mut plaintext = ciphertext.to_vec();
+        XSalsa20Poly1305::new(Key::from_slice(&key))
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), b"", &mut plaintext, Tag::from_slice(tag))
+            .map_err(|_| KeystoreError::DecryptionFailed)?;
+        Ok(plaintext)
+    }
+
+    /// Checks that the content type is `content`, so that e.g. a bitcoin key is not
+    /// mistaken for a parachain key.
+    pub fn expect_content(&self, content: &[&str]) -> Result<(), KeystoreError> {
+        if self
+            .encoding
+            .content
+            .iter()
+            .map(String::as_str)
+            .eq(content.iter().copied())
+        {
+            Ok(())
+        } else {
+            Err(KeystoreError::UnexpectedContent(self.encoding.content.join(", ")))
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, KeystoreError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8], params: &scrypt::Params) -> Result<[u8; KEY_LENGTH], KeystoreError> {
+    let mut key = [0u8; KEY_LENGTH];
+    scrypt::scrypt(password.as_bytes(), salt, params, &mut key).map_err(|_| KeystoreError::InvalidScryptParams)?;
+    Ok(key)
+}
+
+/// Reads the password of a key file from `password_file` if given, else from the
+/// environment variable `env_var`, else by prompting on the terminal.
+pub fn read_password(password_file: Option<&Path>, env_var: &str, prompt: &str) -> Result<String, KeystoreError> {
+    if let Some(password) = password_from_file_or_env(password_file, env_var)? {
+        return Ok(password);
+    }
+    rpassword::read_password_from_tty(Some(prompt)).map_err(|_| KeystoreError::NoPassword(env_var.to_string()))
+}
+
+/// Like [`read_password`], but a password entered on the terminal has to be repeated.
+pub fn read_new_password(password_file: Option<&Path>, env_var: &str, prompt: &str) -> Result<String, KeystoreError> {
+    if let Some(password) = password_from_file_or_env(password_file, env_var)? {
+        return Ok(password);
+    }
+    let password = read_password(None, env_var, prompt)?;
+    let repeated = read_password(None, env_var, "Repeat password: ")?;
+    if password != repeated {
+        return Err(KeystoreError::PasswordMismatch);
+    }
+    Ok(password)
+}
+
+fn password_from_file_or_env(password_file: Option<&Path>, env_var: &str) -> Result<Option<String>, KeystoreError> {
+    if let Some(path) = password_file {
+        let password = std::fs::read_to_string(path)?;
+        return Ok(Some(password.trim_end_matches(&['\r', '\n'][..]).to_string()));
+    }
+    Ok(std::env::var(env_var).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let keystore = Keystore::encrypt(WIF_CONTENT, b"secret", "password").unwrap();
+        let keystore = Keystore::parse(keystore.to_json().unwrap().as_bytes()).unwrap();
+        assert!(keystore.expect_content(WIF_CONTENT).is_ok());
+        assert!(keystore.expect_content(&["pkcs8", "sr25519"]).is_err());
+        assert_eq!(keystore.decrypt("password").unwrap(), b"secret");
+        assert!(matches!(
+            keystore.decrypt("wrong password"),
+            Err(KeystoreError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_is_not_a_keystore() {
+        assert!(Keystore::parse(br#"{ "MyUser1": "//Alice" }"#).is_none());
+        assert!(Keystore::parse(b"cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").is_none());
+    }
+}
@@ -2,10 +2,14 @@
 #![feature(int_roundings)]
 
 pub mod cli;
+pub mod keystore;
 pub mod light;
 
 pub use light::{BitcoinLight, Error as BitcoinLightError};
 
+#[cfg(feature = "testing-utils")]
+pub mod conformance;
+
 mod addr;
 mod electrs;
 mod error;
@@ -13,7 +17,6 @@ mod iter;
 
 use async_trait::async_trait;
 use backoff::{backoff::Backoff, future::retry, ExponentialBackoff};
-use bitcoincore_rpc::{bitcoin::consensus::encode::serialize_hex, bitcoincore_rpc_json::ScanningDetails};
 pub use bitcoincore_rpc::{
     bitcoin::{
         blockdata::{opcodes::all as opcodes, script::Builder},
@@ -31,8 +34,9 @@ pub use bitcoincore_rpc::{
             self, address::Payload, key, merkleblock::PartialMerkleTree, psbt, psbt::serialize::Serialize,
             uint::Uint256,
         },
-        Address, Amount, Block, BlockHeader, Network, OutPoint, PrivateKey, PubkeyHash, PublicKey, Script, ScriptHash,
-        SignedAmount, Transaction, TxIn, TxMerkleNode, TxOut, Txid, VarInt, WPubkeyHash, WScriptHash,
+        Address, Amount, Block, BlockHeader, Network, OutPoint, PackedLockTime, PrivateKey, PubkeyHash, PublicKey,
+        Script, ScriptHash, SignedAmount, Transaction, TxIn, TxMerkleNode, TxOut, Txid, VarInt, WPubkeyHash,
+        WScriptHash,
     },
     bitcoincore_rpc_json::{
         CreateRawTransactionInput, FundRawTransactionOptions, GetBlockchainInfoResult, GetTransactionResult,
@@ -42,13 +46,19 @@ pub use bitcoincore_rpc::{
     jsonrpc::{self, error::RpcError, Error as JsonRpcError},
     Auth, Client, Error as BitcoinError, RpcApi,
 };
+use bitcoincore_rpc::{
+    bitcoin::{consensus::encode::serialize_hex, util::merkleblock::MerkleBlock},
+    bitcoincore_rpc_json::ScanningDetails,
+};
 pub use electrs::{ElectrsClient, Error as ElectrsError};
-pub use error::{BitcoinRpcError, ConversionError, Error};
+pub use error::{BitcoinRpcError, ConversionError, Error, KeystoreError};
+use futures::TryStreamExt;
 pub use iter::{reverse_stream_transactions, stream_blocks, stream_in_chain_transactions};
 use log::{info, trace, warn};
 use serde_json::error::Category as SerdeJsonCategory;
 pub use sp_core::H256;
 use std::{
+    collections::{hash_map::Entry, HashMap},
     convert::TryInto,
     future::Future,
     sync::Arc,
@@ -123,6 +133,21 @@ pub struct TransactionMetadata {
     pub fee: Option<SignedAmount>,
 }
 
+/// A transaction affecting the balance of the wallet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletTransaction {
+    pub txid: Txid,
+    /// Hash and height of the including block, if the transaction is confirmed.
+    pub block: Option<(BlockHash, u32)>,
+    /// Net change of the wallet balance, excluding the fee.
+    pub amount: SignedAmount,
+    /// Fee paid by the wallet. Like in bitcoin core this is negative, and only set
+    /// if the wallet funded the transaction.
+    pub fee: Option<SignedAmount>,
+    /// Whether the transaction conflicts with the main chain, e.g. because it was replaced by fee.
+    pub replaced: bool,
+}
+
 #[async_trait]
 pub trait BitcoinCoreApi {
     fn network(&self) -> Network;
@@ -135,6 +160,10 @@ pub trait BitcoinCoreApi {
 
     fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, Error>;
 
+    /// List the transactions of the wallet. If `since_height` is set, only those confirmed at
+    /// or after that height and unconfirmed ones are listed.
+    async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, Error>;
+
     async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error>;
 
     async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, Error>;
@@ -181,6 +210,17 @@ pub trait BitcoinCoreApi {
         request_id: Option<H256>,
     ) -> Result<Txid, Error>;
 
+    /// Like `create_and_send_transaction`, but fails with `Error::FeeExceedsMax` without
+    /// sending the transaction if the fee of the funded transaction exceeds `max_fee` satoshis.
+    async fn create_and_send_transaction_with_max_fee(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        max_fee: u64,
+        request_id: Option<H256>,
+    ) -> Result<Txid, Error>;
+
     async fn send_to_address(
         &self,
         address: Address,
@@ -586,6 +626,29 @@ impl BitcoinCore {
         }
     }
 
+    /// Load the wallet on Bitcoin Core without creating it, so that it can be read while
+    /// the vault is not running. Fails with [`Error::WalletNotFound`] if it does not exist.
+    pub async fn load_wallet(&self) -> Result<(), Error> {
+        let wallet_name = self.wallet_name.as_ref().ok_or(Error::WalletNotFound)?;
+
+        self.with_retry_on_timeout(|| async {
+            if self.rpc.list_wallets()?.contains(wallet_name) {
+                // already loaded - nothing to do
+                Ok(())
+            } else if self.rpc.list_wallet_dir()?.contains(wallet_name) {
+                info!("Loading wallet {wallet_name}...");
+                let result = self.rpc.load_wallet(wallet_name)?;
+                if let Some(warning) = result.warning {
+                    warn!("Received error while loading wallet {wallet_name}: {warning}");
+                }
+                Ok(())
+            } else {
+                Err(Error::WalletNotFound)
+            }
+        })
+        .await
+    }
+
     pub async fn wallet_has_public_key(&self, public_key: PublicKey) -> Result<bool, Error> {
         self.with_wallet(|| async {
             let address = Address::p2wpkh(&public_key, self.network).map_err(ConversionError::from)?;
@@ -601,6 +664,24 @@ impl BitcoinCore {
             .await
     }
 
+    /// Fetch a transaction and its merkle proof from electrs, for use when bitcoin core has
+    /// pruned the block containing it. Both are verified against the header of `block_hash`
+    /// known to bitcoin core before they are returned.
+    async fn get_pruned_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error> {
+        info!("Block {block_hash} is pruned - fetching transaction {txid} from electrs");
+        let raw_tx = self.electrs_client.get_raw_tx(txid).await?;
+        verify_tx(txid, &raw_tx)?;
+        Ok(raw_tx)
+    }
+
+    async fn get_pruned_proof(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error> {
+        info!("Block {block_hash} is pruned - fetching the proof of transaction {txid} from electrs");
+        let raw_proof = self.electrs_client.get_raw_tx_merkle_proof(txid).await?;
+        let header = self.rpc.get_block_header(block_hash)?;
+        verify_merkle_proof(txid, &header, &raw_proof)?;
+        Ok(raw_proof)
+    }
+
     pub async fn wait_for_rescan(&self) -> Result<(), Error> {
         loop {
             let wallet_info = self.rpc.get_wallet_info()?;
@@ -615,6 +696,54 @@ impl BitcoinCore {
     }
 }
 
+/// Check that `raw_tx` is the transaction identified by `txid` and that `raw_proof` is a
+/// merkle proof of its inclusion in the block with the given `header`.
+fn verify_tx_inclusion(txid: &Txid, header: &BlockHeader, raw_tx: &[u8], raw_proof: &[u8]) -> Result<(), Error> {
+    verify_tx(txid, raw_tx)?;
+    verify_merkle_proof(txid, header, raw_proof)
+}
+
+/// Check that `raw_tx` is the transaction identified by `txid`.
+fn verify_tx(txid: &Txid, raw_tx: &[u8]) -> Result<(), Error> {
+    let transaction: Transaction = deserialize(raw_tx)?;
+    if &transaction.txid() != txid {
+        return Err(Error::InvalidTransaction);
+    }
+    Ok(())
+}
+
+/// Check that `raw_proof` is a merkle proof of the inclusion of `txid` in the block with
+/// the given `header`.
+fn verify_merkle_proof(txid: &Txid, header: &BlockHeader, raw_proof: &[u8]) -> Result<(), Error> {
+    let merkle_block: MerkleBlock = deserialize(raw_proof)?;
+    let mut matches = vec![];
+    let mut indexes = vec![];
+    let merkle_root = merkle_block
+        .txn
+        .extract_matches(&mut matches, &mut indexes)
+        .map_err(|_| Error::InvalidMerkleProof)?;
+
+    if &merkle_block.header != header || merkle_root != header.merkle_root || !matches.contains(txid) {
+        return Err(Error::InvalidMerkleProof);
+    }
+    Ok(())
+}
+
+/// Virtual size of a transaction, which is its weight divided by 4, rounded up.
+pub fn get_vsize(transaction: &Transaction) -> u64 {
+    (transaction.weight() as u64).saturating_add(3) / 4
+}
+
+/// Fails if the fee of a transaction funded at `fee_rate` exceeds `max_fee`. The wallet
+/// adds inputs until the fee rate is met, so the fee grows with the number of inputs.
+pub fn check_max_fee(transaction: &Transaction, fee_rate: SatPerVbyte, max_fee: u64) -> Result<(), Error> {
+    let vsize = get_vsize(transaction);
+    if fee_rate.0.saturating_mul(vsize) > max_fee {
+        return Err(Error::FeeExceedsMax(vsize));
+    }
+    Ok(())
+}
+
 /// true if the given indicates that the item was not found in the mempool
 fn err_not_in_mempool(err: &bitcoincore_rpc::Error) -> bool {
     matches!(
@@ -684,14 +813,67 @@ impl BitcoinCoreApi for BitcoinCore {
             .list_transactions(None, max_count.or(Some(DEFAULT_MAX_TX_COUNT)), None, None)?)
     }
 
+    /// List the transactions in the wallet, one entry per transaction, ordered by
+    /// block height with unconfirmed transactions last.
+    async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, Error> {
+        let entries = match since_height {
+            Some(height) if height > 0 => {
+                // listsinceblock excludes the given block
+                let tip = self.rpc.get_block_count()?;
+                let block_hash = self.rpc.get_block_hash(tip.min(height as u64 - 1))?;
+                self.rpc
+                    .list_since_block(Some(&block_hash), None, None, None)?
+                    .transactions
+            }
+            _ => self.list_transactions(None)?,
+        };
+
+        let mut transactions: Vec<WalletTransaction> = Vec::new();
+        let mut indices: HashMap<Txid, usize> = HashMap::new();
+        // bitcoin core returns one entry per output, merge them per transaction
+        for entry in entries {
+            let index = match indices.entry(entry.info.txid) {
+                Entry::Occupied(index) => *index.get(),
+                Entry::Vacant(index) => {
+                    transactions.push(WalletTransaction {
+                        txid: entry.info.txid,
+                        block: entry.info.blockhash.zip(entry.info.blockheight),
+                        amount: SignedAmount::ZERO,
+                        fee: None,
+                        replaced: entry.info.confirmations < 0,
+                    });
+                    *index.insert(transactions.len() - 1)
+                }
+            };
+            let transaction = &mut transactions[index];
+            transaction.amount = transaction
+                .amount
+                .checked_add(entry.detail.amount)
+                .ok_or(Error::ArithmeticError)?;
+            // the fee is repeated on every entry of the transaction
+            transaction.fee = transaction.fee.or(entry.detail.fee);
+        }
+        transactions.sort_by_key(|tx| tx.block.map(|(_, height)| height).unwrap_or(u32::MAX));
+        Ok(transactions)
+    }
+
     /// Get the raw transaction identified by `Txid` and stored
-    /// in the specified block.
+    /// in the specified block. If the block has been pruned, the
+    /// transaction is fetched from electrs instead.
     ///
     /// # Arguments
     /// * `txid` - transaction ID
     /// * `block_hash` - hash of the block tx is stored in
     async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error> {
-        Ok(serialize(&self.rpc.get_raw_transaction(txid, Some(block_hash))?))
+        match self
+            .rpc
+            .get_raw_transaction(txid, Some(block_hash))
+            .map_err(Error::from)
+        {
+            Ok(transaction) => Ok(serialize(&transaction)),
+            Err(err) if err.is_block_pruned() => self.get_pruned_raw_tx(txid, block_hash).await,
+            Err(err) => Err(err),
+        }
     }
 
     /// Get the raw transaction identified by `Txid` and stored
@@ -705,12 +887,21 @@ impl BitcoinCoreApi for BitcoinCore {
     }
 
     /// Get the merkle proof which can be used to validate transaction inclusion.
+    /// If the block has been pruned, the proof is fetched from electrs instead.
     ///
     /// # Arguments
     /// * `txid` - transaction ID
     /// * `block_hash` - hash of the block tx is stored in
     async fn get_proof(&self, txid: Txid, block_hash: &BlockHash) -> Result<Vec<u8>, Error> {
-        Ok(self.rpc.get_tx_out_proof(&[txid], Some(block_hash))?)
+        match self
+            .rpc
+            .get_tx_out_proof(&[txid], Some(block_hash))
+            .map_err(Error::from)
+        {
+            Ok(proof) => Ok(proof),
+            Err(err) if err.is_block_pruned() => self.get_pruned_proof(&txid, block_hash).await,
+            Err(err) => Err(err),
+        }
     }
 
     /// Get the block hash for a given height.
@@ -920,6 +1111,29 @@ impl BitcoinCoreApi for BitcoinCore {
         Ok(txid)
     }
 
+    /// Send an amount of Bitcoin to an address like `create_and_send_transaction`, unless
+    /// the fee of the funded transaction exceeds `max_fee`.
+    ///
+    /// # Arguments
+    /// * `address` - Bitcoin address to fund
+    /// * `sat` - number of Satoshis to transfer
+    /// * `fee_rate` - fee rate in sat/vbyte
+    /// * `max_fee` - maximum fee in Satoshis
+    /// * `request_id` - the issue/redeem/replace id for which this transfer is being made
+    async fn create_and_send_transaction_with_max_fee(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        max_fee: u64,
+        request_id: Option<H256>,
+    ) -> Result<Txid, Error> {
+        let tx = self.create_transaction(address, sat, fee_rate, request_id).await?;
+        check_max_fee(&tx.transaction, fee_rate, max_fee)?;
+        let txid = self.send_transaction(tx).await?;
+        Ok(txid)
+    }
+
     /// Send an amount of Bitcoin to an address and wait until it is included
     /// in the blockchain with the requested number of confirmations.
     ///
@@ -1001,24 +1215,23 @@ impl BitcoinCoreApi for BitcoinCore {
 
     async fn rescan_electrs_for_addresses(&self, addresses: Vec<Address>) -> Result<(), Error> {
         for address in addresses.into_iter() {
+            let mut transactions = self.electrs_client.stream_address_tx_history(&address);
             let address = address.to_string();
-            let all_transactions = self.electrs_client.get_address_tx_history_full(&address).await?;
-            // filter to only import
-            // a) payments in the blockchain (not in mempool), and
-            // b) payments TO the address (as bitcoin core will already know about transactions spending FROM it)
-            let confirmed_payments_to = all_transactions.into_iter().filter(|tx| {
-                if let Some(status) = &tx.status {
-                    if !status.confirmed {
-                        return false;
-                    }
-                };
-                tx.vout
+            while let Some(transaction) = transactions.try_next().await? {
+                // only import
+                // a) payments in the blockchain (not in mempool), and
+                // b) payments TO the address (as bitcoin core will already know about transactions spending FROM it)
+                let is_confirmed = matches!(&transaction.status, Some(status) if status.confirmed);
+                let is_payment_to = transaction
+                    .vout
                     .as_ref()
                     .unwrap_or(&vec![])
                     .iter()
-                    .any(|output| matches!(&output.scriptpubkey_address, Some(addr) if addr == &address))
-            });
-            for transaction in confirmed_payments_to {
+                    .any(|output| matches!(&output.scriptpubkey_address, Some(addr) if addr == &address));
+                if !is_confirmed || !is_payment_to {
+                    continue;
+                }
+
                 let (raw_tx, raw_merkle_proof) = futures::future::try_join(
                     self.electrs_client.get_tx_hex(&transaction.txid),
                     self.electrs_client.get_tx_merkle_block_proof(&transaction.txid),
@@ -1153,7 +1366,36 @@ impl TransactionExt for Transaction {
 
 #[cfg(test)]
 mod tests {
-    use bitcoincore_rpc::bitcoin::hashes::{hex::FromHex, sha256::Hash as Sha256Hash, Hash};
+    use super::*;
+    use bitcoincore_rpc::bitcoin::{
+        hashes::{hex::FromHex, sha256::Hash as Sha256Hash, Hash},
+        PackedLockTime,
+    };
+
+    fn dummy_tx(value: i32) -> Transaction {
+        Transaction {
+            version: value,
+            lock_time: PackedLockTime(1),
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    fn dummy_block(transactions: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            txdata: transactions,
+            header: BlockHeader {
+                version: 4,
+                bits: 0,
+                nonce: 0,
+                time: 0,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+            },
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
 
     #[test]
     fn test_op_return_hashing() {
@@ -1165,4 +1407,36 @@ mod tests {
 
         assert_eq!(expected, script_hash);
     }
+
+    #[test]
+    fn test_verify_tx_inclusion() {
+        let block = dummy_block(vec![dummy_tx(1), dummy_tx(2), dummy_tx(3)]);
+        let tx = &block.txdata[1];
+        let txid = tx.txid();
+        let raw_tx = serialize(tx);
+        let raw_proof = serialize(&MerkleBlock::from_block_with_predicate(&block, |x| x == &txid));
+
+        assert!(verify_tx_inclusion(&txid, &block.header, &raw_tx, &raw_proof).is_ok());
+
+        // transaction does not match the txid
+        assert!(matches!(
+            verify_tx_inclusion(&txid, &block.header, &serialize(&block.txdata[0]), &raw_proof),
+            Err(Error::InvalidTransaction)
+        ));
+
+        // proof does not match the locally known header
+        let other_block = dummy_block(vec![dummy_tx(4), dummy_tx(2)]);
+        assert!(matches!(
+            verify_tx_inclusion(&txid, &other_block.header, &raw_tx, &raw_proof),
+            Err(Error::InvalidMerkleProof)
+        ));
+
+        // proof is for another transaction in the same block
+        let other_txid = block.txdata[2].txid();
+        let other_proof = serialize(&MerkleBlock::from_block_with_predicate(&block, |x| x == &other_txid));
+        assert!(matches!(
+            verify_tx_inclusion(&txid, &block.header, &raw_tx, &other_proof),
+            Err(Error::InvalidMerkleProof)
+        ));
+    }
 }
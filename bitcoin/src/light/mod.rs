@@ -8,7 +8,7 @@ pub use error::Error;
 use async_trait::async_trait;
 use backoff::future::retry;
 use futures::future::{join_all, try_join, try_join_all};
-use std::{sync::Arc, time::Duration};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 use tokio::{sync::Mutex, time::sleep};
 
 const RETRY_DURATION: Duration = Duration::from_millis(1000);
@@ -89,18 +89,20 @@ impl BitcoinCoreApi for BitcoinLight {
             .await
             {
                 Ok((hash, best)) => {
-                    if best.saturating_sub(height) >= num_confirmations {
+                    // same as bitcoin core, the block itself counts as a confirmation
+                    if best.saturating_sub(height) + 1 >= num_confirmations {
                         return Ok(self.electrs.get_block(&hash).await?);
                     } else {
                         sleep(RETRY_DURATION).await;
                         continue;
                     }
                 }
-                _ => {
-                    // TODO: handle error
+                Err(err) if err.is_not_found() => {
+                    // block has not been produced yet
                     sleep(RETRY_DURATION).await;
                     continue;
                 }
+                Err(err) => return Err(err.into()),
             }
         }
     }
@@ -119,6 +121,67 @@ impl BitcoinCoreApi for BitcoinLight {
         Ok(Default::default())
     }
 
+    async fn list_wallet_transactions(
+        &self,
+        since_height: Option<u32>,
+    ) -> Result<Vec<WalletTransaction>, BitcoinError> {
+        // electrs only indexes confirmed transactions by address
+        let mut history = BTreeMap::new();
+        for address in self.wallet.addresses()? {
+            history.extend(
+                self.electrs
+                    .get_address_tx_history(&address)
+                    .await?
+                    .into_iter()
+                    .filter(|(_, (_, height))| *height >= since_height.unwrap_or_default()),
+            );
+        }
+
+        let mut transactions = try_join_all(history.into_iter().map(|(txid, block)| async move {
+            let tx = self.get_transaction(&txid, None).await?;
+
+            let (mut total_in, mut wallet_in) = (0u64, 0u64);
+            for input in tx.input.iter().filter(|input| !input.previous_output.is_null()) {
+                let prev_tx = self.get_transaction(&input.previous_output.txid, None).await?;
+                let prev_out = prev_tx
+                    .output
+                    .get(input.previous_output.vout as usize)
+                    .ok_or(Error::NoPrevOut)?;
+                total_in += prev_out.value;
+                if self.wallet.is_mine(&prev_out.script_pubkey) {
+                    wallet_in += prev_out.value;
+                }
+            }
+            let total_out = tx.output.iter().map(|tx_out| tx_out.value).sum::<u64>();
+            let wallet_out = tx
+                .output
+                .iter()
+                .filter(|tx_out| self.wallet.is_mine(&tx_out.script_pubkey))
+                .map(|tx_out| tx_out.value)
+                .sum::<u64>();
+
+            // the wallet only pays the fee if it funded the transaction
+            let fee = if wallet_in > 0 {
+                Some(SignedAmount::from_sat(-(total_in.saturating_sub(total_out) as i64)))
+            } else {
+                None
+            };
+            let amount =
+                SignedAmount::from_sat(wallet_out as i64 - wallet_in as i64) - fee.unwrap_or(SignedAmount::ZERO);
+
+            Ok::<_, BitcoinError>(WalletTransaction {
+                txid,
+                block: Some(block),
+                amount,
+                fee,
+                replaced: false,
+            })
+        }))
+        .await?;
+        transactions.sort_by_key(|tx| tx.block.map(|(_, height)| height));
+        Ok(transactions)
+    }
+
     async fn get_raw_tx(&self, txid: &Txid, _block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError> {
         Ok(self.electrs.get_raw_tx(txid).await?)
     }
@@ -227,8 +290,36 @@ impl BitcoinCoreApi for BitcoinLight {
         })
     }
 
-    async fn bump_fee(&self, _txid: &Txid, _address: Address, _fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError> {
-        todo!()
+    async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError> {
+        let lock = self.transaction_creation_lock.clone().lock_owned().await;
+        if !self.is_in_mempool(*txid).await? {
+            // confirmed transactions can't be replaced
+            return Err(BitcoinError::ConfirmationError);
+        }
+
+        // keep the inputs and recipients of the existing transaction, but fund it again
+        // to recompute the change
+        let mut existing_transaction = self.get_transaction(txid, None).await?;
+        let change_address = match existing_transaction.extract_return_to_self_address(&address.payload)? {
+            Some((idx, payload)) => {
+                existing_transaction.output.remove(idx);
+                Address {
+                    payload,
+                    network: self.network(),
+                }
+            }
+            None => self.get_change_address()?,
+        };
+
+        let mut psbt = self
+            .wallet
+            .fund_transaction(existing_transaction, change_address, fee_rate.0.saturating_mul(1000))
+            .await?;
+        self.wallet.sign_transaction(&mut psbt)?;
+        let signed_tx = psbt.extract_tx();
+
+        self.send_transaction(LockedTransaction::new(signed_tx, address.to_string(), Some(lock)))
+            .await
     }
 
     async fn create_and_send_transaction(
@@ -243,6 +334,20 @@ impl BitcoinCoreApi for BitcoinLight {
         Ok(txid)
     }
 
+    async fn create_and_send_transaction_with_max_fee(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        max_fee: u64,
+        request_id: Option<H256>,
+    ) -> Result<Txid, BitcoinError> {
+        let tx = self.create_transaction(address, sat, fee_rate, request_id).await?;
+        check_max_fee(&tx.transaction, fee_rate, max_fee)?;
+        let txid = self.send_transaction(tx).await?;
+        Ok(txid)
+    }
+
     async fn send_to_address(
         &self,
         address: Address,
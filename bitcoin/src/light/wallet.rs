@@ -1,7 +1,7 @@
 use bitcoincore_rpc::bitcoin::{
     blockdata::{constants::WITNESS_SCALE_FACTOR, transaction::NonStandardSighashType},
     util::sighash::SighashCache,
-    EcdsaSig, PackedLockTime, PublicKey, Witness,
+    EcdsaSig, PackedLockTime, PublicKey, Sequence, Witness,
 };
 
 use super::{electrs::ElectrsClient, error::Error};
@@ -14,7 +14,7 @@ use crate::{
     Address, Builder as ScriptBuilder, Network, OutPoint, PrivateKey, Script, Transaction, TxIn, TxOut, VarInt, H256,
 };
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
     sync::{Arc, RwLock},
 };
 
@@ -221,16 +221,32 @@ impl Wallet {
         Ok(*private_key)
     }
 
+    pub fn addresses(&self) -> Result<Vec<Address>, Error> {
+        Ok(self.key_store.read()?.keys().cloned().collect())
+    }
+
+    pub fn is_mine(&self, script_pubkey: &Script) -> bool {
+        self.get_priv_key(script_pubkey).is_ok()
+    }
+
     pub fn get_pub_key(&self, script_pubkey: &Script) -> Result<PublicKey, Error> {
         Ok(self.get_priv_key(script_pubkey)?.public_key(&self.secp))
     }
 
+    /// Add inputs and a change output to pay for the outputs of `tx`. Inputs that `tx`
+    /// already has are spent first, e.g. those of a transaction that it replaces.
     pub async fn fund_transaction(
         &self,
-        tx: Transaction,
+        mut tx: Transaction,
         change_address: Address,
         n_satoshis_per_k: u64,
     ) -> Result<PartiallySignedTransaction, Error> {
+        let mut coins = VecDeque::new();
+        for txin in std::mem::take(&mut tx.input) {
+            let prev_out = self.electrs.get_prev_out(txin.previous_output).await?;
+            coins.push_back((txin.previous_output, prev_out));
+        }
+
         let recipients_sum = tx.output.iter().map(|tx_out| tx_out.value).sum::<u64>();
 
         let m_effective_feerate = FeeRate { n_satoshis_per_k };
@@ -257,72 +273,90 @@ impl Wallet {
         let mut select_coins = SelectCoins::new(selection_target);
 
         // get available coins
-        let addresses = self.key_store.read()?.keys().cloned().collect::<Vec<_>>();
-        for address in addresses {
-            log::info!("Found address: {}", address);
-            // get utxos for address
-            let utxos = self.electrs.get_utxos_for_address(address).await?;
-            // TODO: stream this, no need to fetch
-            for utxo in utxos {
-                log::info!("Found utxo: {}", utxo.outpoint.txid);
-
-                let script_pubkey = self.electrs.get_script_pubkey(utxo.outpoint).await?;
-                let public_key = self.get_pub_key(&script_pubkey).expect("wallet has key");
-                let input_bytes = calculate_maximum_signed_input_size(utxo.outpoint, public_key);
-                let coin_output = CoinOutput {
-                    value: utxo.value,
-                    fee: m_effective_feerate.get_fee(input_bytes),
-                };
-
-                let effective_value = coin_output.get_effective_value();
-                select_coins.add(coin_output);
-                value_to_select = value_to_select.saturating_sub(effective_value);
-
-                psbt.unsigned_tx.input.push(TxIn {
-                    previous_output: utxo.outpoint,
-                    ..Default::default()
-                });
-
-                psbt.inputs.push(psbt::Input {
-                    witness_utxo: Some(TxOut {
-                        value: utxo.value,
-                        script_pubkey,
-                    }),
-                    ..Default::default()
-                });
-
-                if value_to_select == 0 {
-                    // add change output before computing maximum size
-                    let change_amount = select_coins.get_change(min_viable_change, change_fee);
-                    let mut n_change_pos_in_out = None;
-                    if change_amount > 0 {
-                        n_change_pos_in_out = Some(psbt.unsigned_tx.output.len());
-                        // add change output
-                        psbt.unsigned_tx.output.push(TxOut {
-                            value: change_amount,
-                            script_pubkey: change_address.script_pubkey(),
-                        });
+        let mut addresses = self.key_store.read()?.keys().cloned().collect::<Vec<_>>().into_iter();
+        loop {
+            let (outpoint, prev_out) = match coins.pop_front() {
+                Some(coin) => coin,
+                None => {
+                    let address = addresses.next().ok_or(Error::NotEnoughInputs)?;
+                    log::info!("Found address: {}", address);
+                    // get utxos for address
+                    for utxo in self.electrs.get_utxos_for_address(address).await? {
+                        if psbt
+                            .unsigned_tx
+                            .input
+                            .iter()
+                            .any(|txin| txin.previous_output == utxo.outpoint)
+                        {
+                            // already spent by the transaction
+                            continue;
+                        }
+                        log::info!("Found utxo: {}", utxo.outpoint.txid);
+                        let script_pubkey = self.electrs.get_script_pubkey(utxo.outpoint).await?;
+                        coins.push_back((
+                            utxo.outpoint,
+                            TxOut {
+                                value: utxo.value,
+                                script_pubkey,
+                            },
+                        ));
                     }
+                    continue;
+                }
+            };
 
-                    // https://github.com/bitcoin/bitcoin/blob/01e1627e25bc5477c40f51da03c3c31b609a85c9/src/wallet/spend.cpp#L945
-                    let n_bytes = calculate_maximum_signed_tx_size(&psbt, self);
-                    let fee_needed = m_effective_feerate.get_fee(n_bytes);
-                    let n_fee_ret = select_coins.get_selected_value() - recipients_sum - change_amount;
+            let public_key = self.get_pub_key(&prev_out.script_pubkey)?;
+            let input_bytes = calculate_maximum_signed_input_size(outpoint, public_key);
+            let coin_output = CoinOutput {
+                value: prev_out.value,
+                fee: m_effective_feerate.get_fee(input_bytes),
+            };
 
-                    if let Some(change_pos) = n_change_pos_in_out {
-                        if fee_needed < n_fee_ret {
-                            log::info!("Fee needed is less than expected");
-                            let mut change_output = &mut psbt.unsigned_tx.output[change_pos];
-                            change_output.value += n_fee_ret - fee_needed;
-                        }
-                    }
+            let effective_value = coin_output.get_effective_value();
+            select_coins.add(coin_output);
+            value_to_select = value_to_select.saturating_sub(effective_value);
+
+            psbt.unsigned_tx.input.push(TxIn {
+                previous_output: outpoint,
+                // signal replaceability, so that the fee can be bumped
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            });
+
+            psbt.inputs.push(psbt::Input {
+                witness_utxo: Some(prev_out),
+                ..Default::default()
+            });
+
+            if value_to_select == 0 {
+                // add change output before computing maximum size
+                let change_amount = select_coins.get_change(min_viable_change, change_fee);
+                let mut n_change_pos_in_out = None;
+                if change_amount > 0 {
+                    n_change_pos_in_out = Some(psbt.unsigned_tx.output.len());
+                    // add change output
+                    psbt.unsigned_tx.output.push(TxOut {
+                        value: change_amount,
+                        script_pubkey: change_address.script_pubkey(),
+                    });
+                }
+
+                // https://github.com/bitcoin/bitcoin/blob/01e1627e25bc5477c40f51da03c3c31b609a85c9/src/wallet/spend.cpp#L945
+                let n_bytes = calculate_maximum_signed_tx_size(&psbt, self);
+                let fee_needed = m_effective_feerate.get_fee(n_bytes);
+                let n_fee_ret = select_coins.get_selected_value() - recipients_sum - change_amount;
 
-                    return Ok(psbt);
+                if let Some(change_pos) = n_change_pos_in_out {
+                    if fee_needed < n_fee_ret {
+                        log::info!("Fee needed is less than expected");
+                        let mut change_output = &mut psbt.unsigned_tx.output[change_pos];
+                        change_output.value += n_fee_ret - fee_needed;
+                    }
                 }
+
+                return Ok(psbt);
             }
         }
-
-        Err(Error::NotEnoughInputs)
     }
 
     pub fn put_p2wpkh_key(&self, secret_key: SecretKey) -> Result<(), Error> {
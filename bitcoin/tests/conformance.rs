@@ -0,0 +1,85 @@
+#![cfg(all(
+    feature = "uses-bitcoind",
+    feature = "testing-utils",
+    feature = "regtest-manual-mining"
+))]
+
+use async_trait::async_trait;
+use bitcoin::{
+    conformance::{self, Harness},
+    Auth, BitcoinCore, BitcoinCoreApi, BitcoinCoreBuilder, Client, Error, Network, SatPerVbyte,
+};
+use std::{env::var, sync::Arc};
+
+mod esplora;
+
+type DynBitcoinCoreApi = Arc<dyn BitcoinCoreApi + Send + Sync>;
+
+fn new_bitcoin_core(wallet: Option<String>) -> Result<BitcoinCore, Error> {
+    BitcoinCoreBuilder::new(var("BITCOIN_RPC_URL").expect("BITCOIN_RPC_URL not set"))
+        .set_auth(Auth::UserPass(
+            var("BITCOIN_RPC_USER").expect("BITCOIN_RPC_USER not set"),
+            var("BITCOIN_RPC_PASS").expect("BITCOIN_RPC_PASS not set"),
+        ))
+        .set_wallet_name(wallet)
+        .build_with_network(Network::Regtest)
+}
+
+/// Produces blocks through the wallet of a bitcoind regtest node.
+struct BitcoindHarness(BitcoinCore);
+
+#[async_trait]
+impl Harness for BitcoindHarness {
+    async fn mine_blocks(&self, count: u32) -> Result<(), Error> {
+        for _ in 0..count {
+            self.0.mine_block()?;
+        }
+        Ok(())
+    }
+}
+
+async fn new_funded_harness(wallet: &str) -> Result<BitcoindHarness, Error> {
+    let btc_rpc = new_bitcoin_core(Some(wallet.to_string()))?;
+    btc_rpc.create_or_load_wallet().await?;
+    let harness = BitcoindHarness(btc_rpc);
+    // coinbase outputs can only be spent after 100 blocks
+    harness.mine_blocks(101).await?;
+    Ok(harness)
+}
+
+#[tokio::test]
+async fn bitcoin_core_passes_conformance_checks() -> Result<(), Error> {
+    let harness = new_funded_harness("Conformance").await?;
+    let backend: DynBitcoinCoreApi = Arc::new(new_bitcoin_core(Some("Conformance".to_string()))?);
+
+    conformance::check_all(&backend, &harness).await
+}
+
+#[tokio::test]
+async fn bitcoin_light_passes_conformance_checks() -> Result<(), Error> {
+    use bitcoin::{BitcoinLight, PrivateKey};
+
+    let harness = new_funded_harness("ConformanceFaucet").await?;
+    let electrs_url = esplora::spawn(Client::new(
+        &var("BITCOIN_RPC_URL").expect("BITCOIN_RPC_URL not set"),
+        Auth::UserPass(
+            var("BITCOIN_RPC_USER").expect("BITCOIN_RPC_USER not set"),
+            var("BITCOIN_RPC_PASS").expect("BITCOIN_RPC_PASS not set"),
+        ),
+    )?);
+
+    let private_key = PrivateKey::from_wif("cNfmpdkMyUwQGEZgqiqu1RPhhrjwGsp5VSJhEnFEfU533KwTnuYj")?;
+    let backend: DynBitcoinCoreApi = Arc::new(BitcoinLight::new(Some(electrs_url), private_key)?);
+
+    // the light client only spends from deposit keys
+    let public_key = backend.get_new_public_key().await?;
+    backend.add_new_deposit_key(public_key, vec![1; 32]).await?;
+    let address = backend.get_new_address().await?;
+    harness
+        .0
+        .create_and_send_transaction(address, 1_000_000, SatPerVbyte(1), None)
+        .await?;
+    harness.mine_blocks(1).await?;
+
+    conformance::check_all(&backend, &harness).await
+}
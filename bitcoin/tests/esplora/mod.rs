@@ -0,0 +1,332 @@
+//! A minimal in-process stand-in for the esplora REST API, backed by a bitcoind regtest node.
+//!
+//! It implements the subset of https://github.com/Blockstream/esplora/blob/master/API.md that
+//! the light client uses, so the light client can be tested without running electrs. Blocks
+//! are indexed when a request comes in, transactions in the mempool are read from bitcoind.
+
+use bitcoin::{serialize, Address, Block, BlockHash, Client, OutPoint, RpcApi, Script, ToHex, Transaction, Txid};
+use esplora_btc_api::models::{Status, Transaction as EsploraTransaction, Utxo, VOut};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use warp::{
+    http::StatusCode,
+    hyper::body::Bytes,
+    path::FullPath,
+    reply::{with_status, WithStatus},
+    Filter,
+};
+
+/// Number of confirmed transactions per page of the history of an address.
+const TRANSACTIONS_PER_PAGE: usize = 25;
+
+/// An error response.
+struct Failure(StatusCode, String);
+
+impl Failure {
+    fn not_found() -> Self {
+        Self(StatusCode::NOT_FOUND, "not found".to_string())
+    }
+}
+
+impl<E: Display> From<E> for Failure {
+    fn from(err: E) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+fn reply(result: Result<String, Failure>) -> WithStatus<String> {
+    match result {
+        Ok(body) => with_status(body, StatusCode::OK),
+        Err(Failure(status, message)) => with_status(message, status),
+    }
+}
+
+fn script_hash(script: &Script) -> String {
+    Sha256::digest(script.as_bytes()).to_hex()
+}
+
+/// The transactions of the best chain and the mempool.
+struct Index<'a> {
+    blocks: &'a [Block],
+    mempool: &'a [Transaction],
+    /// transactions by txid, with the height of their block if confirmed
+    transactions: HashMap<Txid, (&'a Transaction, Option<usize>)>,
+}
+
+impl<'a> Index<'a> {
+    fn new(blocks: &'a [Block], mempool: &'a [Transaction]) -> Self {
+        let confirmed = blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(height, block)| block.txdata.iter().map(move |tx| (tx.txid(), (tx, Some(height)))));
+        let unconfirmed = mempool.iter().map(|tx| (tx.txid(), (tx, None)));
+        Self {
+            blocks,
+            mempool,
+            transactions: confirmed.chain(unconfirmed).collect(),
+        }
+    }
+
+    fn transaction(&self, txid: &str) -> Result<(&'a Transaction, Option<usize>), Failure> {
+        let txid = Txid::from_str(txid)?;
+        self.transactions.get(&txid).copied().ok_or_else(Failure::not_found)
+    }
+
+    fn block(&self, hash: &str) -> Result<&'a Block, Failure> {
+        let hash = BlockHash::from_str(hash)?;
+        self.blocks
+            .iter()
+            .find(|block| block.block_hash() == hash)
+            .ok_or_else(Failure::not_found)
+    }
+
+    fn prev_out_scripts(&self, transaction: &Transaction) -> Vec<&'a Script> {
+        transaction
+            .input
+            .iter()
+            .filter_map(|input| {
+                let (prev_tx, _) = self.transactions.get(&input.previous_output.txid)?;
+                prev_tx
+                    .output
+                    .get(input.previous_output.vout as usize)
+                    .map(|prev_out| &prev_out.script_pubkey)
+            })
+            .collect()
+    }
+
+    fn fee(&self, transaction: &Transaction) -> Option<u64> {
+        if transaction.is_coin_base() {
+            return None;
+        }
+        let input_sum = transaction
+            .input
+            .iter()
+            .map(|input| {
+                let (prev_tx, _) = self.transactions.get(&input.previous_output.txid)?;
+                prev_tx
+                    .output
+                    .get(input.previous_output.vout as usize)
+                    .map(|prev_out| prev_out.value)
+            })
+            .sum::<Option<u64>>()?;
+        let output_sum = transaction.output.iter().map(|output| output.value).sum::<u64>();
+        Some(input_sum.saturating_sub(output_sum))
+    }
+
+    fn to_esplora(&self, transaction: &Transaction, height: Option<usize>) -> EsploraTransaction {
+        let mut status = Status::new(height.is_some());
+        if let Some(height) = height {
+            status.block_height = Some(height as f32);
+            status.block_hash = Some(self.blocks[height].block_hash().to_string());
+        }
+
+        let mut esplora_transaction =
+            EsploraTransaction::new(transaction.txid().to_string(), transaction.version as f32);
+        esplora_transaction.vout = Some(
+            transaction
+                .output
+                .iter()
+                .map(|output| {
+                    let mut vout = VOut::new();
+                    vout.scriptpubkey = Some(output.script_pubkey.to_hex());
+                    vout.value = Some(output.value as f32);
+                    vout
+                })
+                .collect(),
+        );
+        esplora_transaction.fee = self.fee(transaction).map(|fee| fee as f32);
+        esplora_transaction.status = Some(Box::new(status));
+        esplora_transaction
+    }
+
+    /// Transactions that pay to or spend from a script with the given hash, mempool first,
+    /// followed by confirmed transactions from newest to oldest.
+    fn history(&self, hash: &str) -> (Vec<&'a Transaction>, Vec<(&'a Transaction, usize)>) {
+        let touches = |transaction: &Transaction| {
+            transaction
+                .output
+                .iter()
+                .map(|output| &output.script_pubkey)
+                .chain(self.prev_out_scripts(transaction))
+                .any(|script| script_hash(script) == hash)
+        };
+        let unconfirmed = self.mempool.iter().filter(|tx| touches(tx)).collect();
+        let confirmed = self
+            .blocks
+            .iter()
+            .enumerate()
+            .rev()
+            .flat_map(|(height, block)| block.txdata.iter().rev().map(move |tx| (tx, height)))
+            .filter(|(tx, _)| touches(tx))
+            .collect();
+        (unconfirmed, confirmed)
+    }
+
+    fn history_page(&self, hash: &str, page: &[&str]) -> Result<Vec<EsploraTransaction>, Failure> {
+        let (unconfirmed, confirmed) = self.history(hash);
+        match page {
+            ["mempool"] => Ok(unconfirmed.into_iter().map(|tx| self.to_esplora(tx, None)).collect()),
+            ["chain", last_seen_txid] => {
+                let skip = match confirmed
+                    .iter()
+                    .position(|(tx, _)| tx.txid().to_string() == *last_seen_txid)
+                {
+                    Some(position) => position + 1,
+                    None if last_seen_txid.is_empty() => 0,
+                    None => return Err(Failure::not_found()),
+                };
+                Ok(confirmed
+                    .into_iter()
+                    .skip(skip)
+                    .take(TRANSACTIONS_PER_PAGE)
+                    .map(|(tx, height)| self.to_esplora(tx, Some(height)))
+                    .collect())
+            }
+            _ => Err(Failure::not_found()),
+        }
+    }
+
+    fn utxos(&self, script: &Script) -> Vec<Utxo> {
+        let spent: HashSet<_> = self
+            .transactions
+            .values()
+            .flat_map(|(tx, _)| tx.input.iter().map(|input| input.previous_output))
+            .collect();
+        self.transactions
+            .iter()
+            .flat_map(|(txid, (tx, _))| {
+                tx.output
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, output)| &output.script_pubkey == script)
+                    .map(move |(vout, output)| (OutPoint::new(*txid, vout as u32), output.value))
+            })
+            .filter(|(outpoint, _)| !spent.contains(outpoint))
+            .map(|(outpoint, value)| Utxo::new(outpoint.txid.to_string(), outpoint.vout as i64, value as i64))
+            .collect()
+    }
+}
+
+struct Esplora {
+    rpc: Client,
+    /// blocks of the best chain, by height
+    blocks: Mutex<Vec<Block>>,
+}
+
+impl Esplora {
+    /// Index the blocks that bitcoind added to the best chain since the last call.
+    fn sync(&self) -> Result<MutexGuard<'_, Vec<Block>>, Failure> {
+        let mut blocks = self.blocks.lock()?;
+        let best_height = self.rpc.get_block_count()? as usize;
+
+        // drop blocks that were reorged out
+        blocks.truncate(best_height + 1);
+        while let Some(block) = blocks.last() {
+            if self.rpc.get_block_hash(blocks.len() as u64 - 1)? == block.block_hash() {
+                break;
+            }
+            blocks.pop();
+        }
+
+        for height in blocks.len()..=best_height {
+            let hash = self.rpc.get_block_hash(height as u64)?;
+            blocks.push(self.rpc.get_block(&hash)?);
+        }
+        Ok(blocks)
+    }
+
+    fn mempool(&self) -> Result<Vec<Transaction>, Failure> {
+        Ok(self
+            .rpc
+            .get_raw_mempool()?
+            .iter()
+            // skip transactions that were included in the meantime
+            .filter_map(|txid| self.rpc.get_raw_transaction(txid, None).ok())
+            .collect())
+    }
+
+    fn get(&self, path: &str) -> Result<String, Failure> {
+        let blocks = self.sync()?;
+        let mempool = self.mempool()?;
+        let index = Index::new(&blocks, &mempool);
+
+        let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        match segments.as_slice() {
+            ["blocks", "tip", "height"] => Ok((blocks.len() - 1).to_string()),
+            ["blocks", "tip", "hash"] => Ok(blocks[blocks.len() - 1].block_hash().to_string()),
+            ["block-height", height] => blocks
+                .get(height.parse::<usize>()?)
+                .map(|block| block.block_hash().to_string())
+                .ok_or_else(Failure::not_found),
+            ["block", hash, "header"] => Ok(serialize(&index.block(hash)?.header).to_hex()),
+            ["block", hash, "txids"] => Ok(serde_json::to_string(
+                &index
+                    .block(hash)?
+                    .txdata
+                    .iter()
+                    .map(|tx| tx.txid().to_string())
+                    .collect::<Vec<_>>(),
+            )?),
+            ["mempool", "txids"] => Ok(serde_json::to_string(
+                &mempool.iter().map(|tx| tx.txid().to_string()).collect::<Vec<_>>(),
+            )?),
+            ["tx", txid] => {
+                let (transaction, height) = index.transaction(txid)?;
+                Ok(serde_json::to_string(&index.to_esplora(transaction, height))?)
+            }
+            ["tx", txid, "hex"] => Ok(serialize(index.transaction(txid)?.0).to_hex()),
+            ["tx", txid, "merkleblock-proof"] => {
+                let (transaction, height) = index.transaction(txid)?;
+                let block_hash = blocks[height.ok_or_else(Failure::not_found)?].block_hash();
+                Ok(self
+                    .rpc
+                    .get_tx_out_proof(&[transaction.txid()], Some(&block_hash))?
+                    .to_hex())
+            }
+            ["address", address, "utxo"] => Ok(serde_json::to_string(
+                &index.utxos(&Address::from_str(address)?.script_pubkey()),
+            )?),
+            ["address", address, "txs", page @ ..] => Ok(serde_json::to_string(
+                &index.history_page(&script_hash(&Address::from_str(address)?.script_pubkey()), page)?,
+            )?),
+            ["scripthash", hash, "txs", page @ ..] => Ok(serde_json::to_string(&index.history_page(hash, page)?)?),
+            _ => Err(Failure::not_found()),
+        }
+    }
+
+    fn post_tx(&self, body: &[u8]) -> Result<String, Failure> {
+        let raw_tx = std::str::from_utf8(body)?;
+        self.rpc
+            .send_raw_transaction(raw_tx)
+            .map(|txid| txid.to_string())
+            .map_err(|err| Failure(StatusCode::BAD_REQUEST, err.to_string()))
+    }
+}
+
+/// Serve the esplora API for the node behind `rpc` on a free local port, and return its url.
+pub fn spawn(rpc: Client) -> String {
+    let esplora = Arc::new(Esplora {
+        rpc,
+        blocks: Mutex::new(vec![]),
+    });
+
+    let get = {
+        let esplora = esplora.clone();
+        warp::get()
+            .and(warp::path::full())
+            .map(move |path: FullPath| reply(esplora.get(path.as_str())))
+    };
+    let post = warp::post()
+        .and(warp::path!("tx"))
+        .and(warp::body::bytes())
+        .map(move |body: Bytes| reply(esplora.post_tx(&body)));
+
+    let (address, server) = warp::serve(get.or(post)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    format!("http://{address}")
+}
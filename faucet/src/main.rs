@@ -4,7 +4,6 @@ mod http;
 use clap::Parser;
 use error::Error;
 use git_version::git_version;
-use runtime::InterBtcSigner;
 use service::{on_shutdown, wait_or_shutdown};
 use std::net::SocketAddr;
 
@@ -16,6 +15,10 @@ const ABOUT: &str = env!("CARGO_PKG_DESCRIPTION");
 #[derive(Parser)]
 #[clap(name = NAME, version = VERSION, author = AUTHORS, about = ABOUT)]
 struct Opts {
+    /// Config file.
+    #[clap(flatten)]
+    config: runtime::cli::ConfigFileOpts,
+
     /// Keyring / keyfile options.
     #[clap(flatten)]
     account_info: runtime::cli::ProviderUserOpts,
@@ -53,10 +56,9 @@ async fn main() -> Result<(), Error> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, log::LevelFilter::Info.as_str()),
     );
-    let opts: Opts = Opts::parse();
+    let opts: Opts = runtime::cli::parse_with_config_file();
 
-    let (key_pair, _) = opts.account_info.get_key_pair()?;
-    let signer = InterBtcSigner::new(key_pair);
+    let (signer, _) = opts.account_info.get_signer()?;
 
     let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
 
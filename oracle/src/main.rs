@@ -11,8 +11,8 @@ use error::Error;
 use futures::future::join_all;
 use git_version::git_version;
 use runtime::{
-    cli::{parse_duration_ms, ProviderUserOpts},
-    CurrencyId, FixedU128, InterBtcParachain, InterBtcSigner, OracleKey, OraclePallet, TryFromSymbol,
+    cli::{parse_duration_ms, parse_with_config_file, ConfigFileOpts, ProviderUserOpts},
+    CurrencyId, FixedU128, InterBtcParachain, OracleKey, OraclePallet, TryFromSymbol,
 };
 use std::{path::PathBuf, time::Duration};
 use tokio::{join, time::sleep};
@@ -27,6 +27,10 @@ const CONFIRMATION_TARGET: u32 = 1;
 #[derive(Parser)]
 #[clap(name = NAME, version = VERSION, author = AUTHORS, about = ABOUT)]
 struct Opts {
+    /// Config file.
+    #[clap(flatten)]
+    config: ConfigFileOpts,
+
     /// Keyring / keyfile options
     #[clap(flatten)]
     account_info: ProviderUserOpts,
@@ -147,7 +151,7 @@ async fn _main() -> Result<(), Error> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, log::LevelFilter::Info.as_str()),
     );
-    let opts: Opts = Opts::parse();
+    let opts: Opts = parse_with_config_file();
 
     // read price configs from file
     let data = std::fs::read_to_string(opts.oracle_config)?;
@@ -168,8 +172,7 @@ async fn _main() -> Result<(), Error> {
     bitcoin_feeds.maybe_add_blockstream(opts.blockstream);
     bitcoin_feeds.maybe_add_blockcypher(opts.blockcypher);
 
-    let (key_pair, _) = opts.account_info.get_key_pair()?;
-    let signer = InterBtcSigner::new(key_pair);
+    let (signer, _) = opts.account_info.get_signer()?;
 
     loop {
         // TODO: retry these calls on failure
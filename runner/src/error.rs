@@ -7,6 +7,7 @@ use reqwest::Error as ReqwestError;
 use std::io::Error as IoError;
 use subxt::Error as SubxtError;
 use thiserror::Error;
+use toml::de::Error as TomlError;
 use url::ParseError as UrlParseError;
 
 #[derive(Error, Debug)]
@@ -23,6 +24,8 @@ pub enum Error {
     UrlParseError(#[from] UrlParseError),
     #[error("SubxtError: {0}")]
     SubxtError(#[from] SubxtError),
+    #[error("TomlError: {0}")]
+    TomlError(#[from] TomlError),
     #[error("Integer conversion error")]
     IntegerConversionError,
     #[error("A client release has not been downloaded")]
@@ -9,7 +9,7 @@ use futures::{FutureExt, TryFutureExt};
 use runner::ClientType;
 use signal_hook::consts::*;
 use signal_hook_tokio::Signals;
-use std::{fmt::Debug, path::PathBuf};
+use std::{ffi::OsString, fmt::Debug, path::PathBuf};
 
 use crate::runner::{retry_with_log_async, subxt_api, Runner};
 
@@ -28,16 +28,77 @@ pub struct Opts {
     #[clap(long, default_value = ".")]
     pub download_path: PathBuf,
 
+    /// Path to a TOML file with default values for the options above, e.g.
+    /// `parachain-ws = "ws://127.0.0.1:9944"`. Options set on the command line take
+    /// precedence. To configure the client, pass `--config` in the client arguments.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
     /// CLI arguments to pass to the client executable.
     pub client_args: Vec<String>,
 }
 
+/// Number of leading arguments that belong to the runner rather than to the client.
+fn runner_args_len(args: &[OsString]) -> usize {
+    let mut len = 1;
+    while let Some(arg) = args.get(len).map(|arg| arg.to_string_lossy()) {
+        if arg == "--" || !arg.starts_with('-') {
+            break;
+        }
+        // all options of the runner take a value
+        let is_flag = matches!(arg.as_ref(), "-h" | "--help" | "-V" | "--version");
+        len += if is_flag || arg.contains('=') { 1 } else { 2 };
+    }
+    len.min(args.len())
+}
+
+/// Inserts the options from the `--config` file that are not set on the command line.
+fn with_config_file(mut args: Vec<OsString>) -> Result<Vec<OsString>, Error> {
+    let runner_args: Vec<String> = args[1..runner_args_len(&args)]
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let path = runner_args.iter().enumerate().find_map(|(index, arg)| {
+        if arg == "--config" {
+            runner_args.get(index + 1).cloned()
+        } else {
+            arg.strip_prefix("--config=").map(ToString::to_string)
+        }
+    });
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(args),
+    };
+
+    let config: toml::value::Table = toml::from_str(&std::fs::read_to_string(path)?)?;
+    let file_args: Vec<OsString> = config
+        .into_iter()
+        .map(|(key, value)| (key.replace('_', "-"), value))
+        .filter(|(key, _)| {
+            let long = format!("--{}", key);
+            !runner_args
+                .iter()
+                .any(|arg| *arg == long || arg.starts_with(&format!("{}=", long)))
+        })
+        .map(|(key, value)| {
+            // strings are displayed in quotes
+            let value = match value {
+                toml::Value::String(value) => value,
+                value => value.to_string(),
+            };
+            format!("--{}={}", key, value).into()
+        })
+        .collect();
+    args.splice(1..1, file_args);
+    Ok(args)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, log::LevelFilter::Info.as_str()),
     );
-    let opts: Opts = Opts::parse();
+    let opts: Opts = Opts::parse_from(with_config_file(std::env::args_os().collect())?);
     let rpc_client = retry_with_log_async(
         || subxt_api(&opts.parachain_ws).into_future().boxed(),
         "Error fetching executable".to_string(),
@@ -1,12 +1,21 @@
 use crate::{
     error::{Error, KeyLoadingError},
     rpc::ShutdownSender,
-    InterBtcParachain, InterBtcSigner,
+    InterBtcParachain, InterBtcSigner, RemoteSigner, SS58_PREFIX,
 };
-use clap::Parser;
+use bitcoin::keystore::{self, Keystore};
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser};
+use schnorrkel::SecretKey;
 use sp_keyring::AccountKeyring;
-use std::{collections::HashMap, num::ParseIntError, str::FromStr, time::Duration};
-use subxt::sp_core::{sr25519::Pair, Pair as _};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use subxt::sp_core::{crypto::Ss58Codec, sr25519::Pair, Pair as _};
 
 #[derive(Parser, Debug, Clone)]
 pub struct ProviderUserOpts {
@@ -23,6 +32,33 @@ pub struct ProviderUserOpts {
     /// The name of the account from the keyfile to use.
     #[clap(long, conflicts_with = "keyring", requires = "keyfile")]
     pub keyname: Option<String>,
+
+    /// File containing the password of an encrypted keyfile. If unset, the password
+    /// is read from KEYFILE_PASSWORD or prompted for. The keyfile may be encrypted by
+    /// `vault generate-parachain-key --encrypt` or be a polkadot-js account export, in
+    /// which case the keyname is the account name or address.
+    #[clap(long, requires = "keyfile")]
+    pub keyfile_password_file: Option<PathBuf>,
+
+    /// Sign extrinsics with a separate signer process instead of a local key, e.g.
+    /// `unix:/run/signer.sock` or `http://127.0.0.1:3040`.
+    #[clap(long, conflicts_with_all = ["keyring", "keyfile"])]
+    pub remote_signer: Option<String>,
+
+    /// Bearer token for a remote signer that is reached over http.
+    #[clap(
+        long,
+        env = "REMOTE_SIGNER_TOKEN",
+        hide_env_values = true,
+        requires = "remote_signer"
+    )]
+    pub remote_signer_token: Option<String>,
+
+    /// Username used for wallet selection with a remote signer, which defaults to the
+    /// address of its account. Set this to the keyname the client ran with before switching
+    /// to the remote signer, to keep using the same bitcoin wallets.
+    #[clap(long, requires = "remote_signer")]
+    pub wallet_name: Option<String>,
 }
 
 impl ProviderUserOpts {
@@ -31,7 +67,11 @@ impl ProviderUserOpts {
         // load parachain credentials
         let (pair, user_name) = match (self.keyfile.as_ref(), self.keyname.as_ref(), &self.keyring) {
             (Some(file_path), Some(keyname), None) => {
-                (get_credentials_from_file(file_path, keyname)?, keyname.to_string())
+                let password_file = self.keyfile_password_file.as_deref();
+                (
+                    get_credentials_from_file(file_path, keyname, password_file)?,
+                    keyname.to_string(),
+                )
             }
             (None, None, Some(keyring)) => (keyring.pair(), format!("{}", keyring)),
             _ => {
@@ -41,6 +81,25 @@ impl ProviderUserOpts {
         };
         Ok((pair, user_name))
     }
+
+    /// Get the signer and the username, the latter of which is used for wallet selection.
+    /// The username of a remote signer is `--wallet-name` or the address of its account.
+    pub fn get_signer(&self) -> Result<(InterBtcSigner, String), Error> {
+        match &self.remote_signer {
+            Some(url) => {
+                let remote = RemoteSigner::connect(url, self.remote_signer_token.clone())?;
+                let user_name = match &self.wallet_name {
+                    Some(wallet_name) => wallet_name.clone(),
+                    None => remote.account_id().to_ss58check_with_version(SS58_PREFIX.into()),
+                };
+                Ok((InterBtcSigner::remote(remote), user_name))
+            }
+            None => {
+                let (pair, user_name) = self.get_key_pair()?;
+                Ok((InterBtcSigner::new(pair), user_name))
+            }
+        }
+    }
 }
 
 /// Loads the credentials for the given user from the keyfile
@@ -49,15 +108,71 @@ impl ProviderUserOpts {
 ///
 /// * `file_path` - path to the json file containing the credentials
 /// * `keyname` - name of the key to get
-fn get_credentials_from_file(file_path: &str, keyname: &str) -> Result<Pair, KeyLoadingError> {
-    let file = std::fs::File::open(file_path)?;
-    let reader = std::io::BufReader::new(file);
-    let map: HashMap<String, String> = serde_json::from_reader(reader)?;
+/// * `password_file` - file containing the password, if the keyfile is encrypted
+fn get_credentials_from_file(
+    file_path: &str,
+    keyname: &str,
+    password_file: Option<&Path>,
+) -> Result<Pair, KeyLoadingError> {
+    let mut data = std::fs::read(file_path)?;
+    if let Some(keystore) = Keystore::parse(&data) {
+        let password = keystore::read_password(password_file, KEYFILE_PASSWORD_ENV, "Keyfile password: ")?;
+        if keystore.expect_content(POLKADOT_JS_CONTENT).is_ok() {
+            return get_credentials_from_polkadot_js(&keystore, keyname, &password);
+        }
+        keystore.expect_content(KEYFILE_CONTENT)?;
+        data = keystore.decrypt(&password)?;
+    }
+    let map: HashMap<String, String> = serde_json::from_slice(&data)?;
     let pair_str = map.get(keyname).ok_or(KeyLoadingError::KeyNotFound)?;
     let pair = Pair::from_string(pair_str, None).map_err(KeyLoadingError::SecretStringError)?;
     Ok(pair)
 }
 
+/// Content type of a keyfile encrypted by `vault generate-parachain-key --encrypt`.
+pub const KEYFILE_CONTENT: &[&str] = &["keyfile"];
+/// Environment variable holding the password of an encrypted keyfile.
+pub const KEYFILE_PASSWORD_ENV: &str = "KEYFILE_PASSWORD";
+
+const POLKADOT_JS_CONTENT: &[&str] = &["pkcs8", "sr25519"];
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const PKCS8_DIVIDER: [u8; 5] = [161, 35, 3, 33, 0];
+
+/// Loads a polkadot-js account export, which holds a single account that is selected
+/// by either its name or its address.
+fn get_credentials_from_polkadot_js(
+    keystore: &Keystore,
+    keyname: &str,
+    password: &str,
+) -> Result<Pair, KeyLoadingError> {
+    let name = keystore
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.get("name"))
+        .and_then(|name| name.as_str());
+    if name != Some(keyname) && keystore.address.as_deref() != Some(keyname) {
+        return Err(KeyLoadingError::KeyNotFound);
+    }
+
+    let decrypted = keystore.decrypt(password)?;
+    // the pkcs8 encoding of the 64 byte secret key followed by the 32 byte public key
+    let secret_start = PKCS8_HEADER.len();
+    let public_start = secret_start + 64 + PKCS8_DIVIDER.len();
+    if decrypted.len() != public_start + 32
+        || decrypted[..secret_start] != PKCS8_HEADER
+        || decrypted[secret_start + 64..public_start] != PKCS8_DIVIDER
+    {
+        return Err(KeyLoadingError::InvalidPkcs8Key);
+    }
+    let secret = SecretKey::from_ed25519_bytes(&decrypted[secret_start..secret_start + 64])
+        .map_err(|_| KeyLoadingError::InvalidPkcs8Key)?;
+    let pair = Pair::from(secret);
+    if pair.public().0[..] != decrypted[public_start..] {
+        return Err(KeyLoadingError::InvalidPkcs8Key);
+    }
+    Ok(pair)
+}
+
 pub fn parse_account_keyring(src: &str) -> Result<AccountKeyring, Error> {
     AccountKeyring::from_str(src).map_err(|_| Error::KeyringAccountParsingError)
 }
@@ -121,3 +236,318 @@ impl ConnectionOpts {
         .await
     }
 }
+
+#[derive(Parser, Debug, Clone)]
+pub struct ConfigFileOpts {
+    /// Path to a TOML file with default values for the other options, e.g.
+    /// `keyfile = "keyfile.json"`. Options set on the command line take precedence,
+    /// followed by environment variables, the config file and the built-in defaults.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+}
+
+/// Parses the command line like [`Parser::parse`], but reads the options that are neither
+/// set on the command line nor in the environment from the file passed with `--config`.
+pub fn parse_with_config_file<P: Parser>() -> P {
+    try_parse_with_config_file(std::env::args_os()).unwrap_or_else(|err| err.exit())
+}
+
+/// Like [`parse_with_config_file`], but parses `args` and returns errors rather than exiting.
+pub fn try_parse_with_config_file<P, I, T>(args: I) -> Result<P, clap::Error>
+where
+    P: Parser,
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let mut command = P::command();
+    let path = match config_file_path(&args) {
+        Some(path) => path,
+        None => return P::try_parse_from(args),
+    };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| command.error(ErrorKind::Io, format!("Failed to read {}: {}", path.display(), err)))?;
+    let config = contents.parse::<toml::Value>().map_err(|err| {
+        command.error(
+            ErrorKind::InvalidValue,
+            format!("Failed to parse {}: {}", path.display(), err),
+        )
+    })?;
+    let subcommand = subcommand_position(&command, &args);
+    let file_args = config_file_args(&mut command, &args, subcommand, config)?;
+
+    // the options of a subcommand follow its name, e.g. `vault drain --keyname ...`
+    let position = subcommand.map_or(1, |index| index + 1).min(args.len());
+    args.splice(position..position, file_args);
+    P::try_parse_from(args)
+}
+
+fn config_file_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// The index of the subcommand in `args`, after the options of the command, e.g. in
+/// `vault --config config.toml drain`.
+fn subcommand_position(command: &clap::Command, args: &[OsString]) -> Option<usize> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.to_string_lossy();
+        let option = if arg == "--" {
+            return None;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            command
+                .get_arguments()
+                .find(|option| !long.contains('=') && option.get_long() == Some(long))
+        } else if let Some(short) = arg.strip_prefix('-') {
+            // a value may follow the flag directly, e.g. `-kvalue`
+            let mut chars = short.chars();
+            match (chars.next(), chars.next()) {
+                (Some(short), None) => command.get_arguments().find(|option| option.get_short() == Some(short)),
+                _ => None,
+            }
+        } else {
+            return command.find_subcommand(&*arg).map(|_| index);
+        };
+        // skip the value of the option, unless it was passed as `--name=value`
+        let takes_value = option.map_or(false, |option| option.get_action().takes_values());
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+/// Converts the entries of the config file into command line arguments, skipping
+/// the options that are already set on the command line or in the environment.
+fn config_file_args(
+    command: &mut clap::Command,
+    args: &[OsString],
+    subcommand: Option<usize>,
+    config: toml::Value,
+) -> Result<Vec<OsString>, clap::Error> {
+    let table = match config {
+        toml::Value::Table(table) => table,
+        _ => return Err(command.error(ErrorKind::InvalidValue, "The config file must contain a table")),
+    };
+    let target = subcommand
+        .and_then(|index| command.find_subcommand(&args[index]))
+        .unwrap_or(command)
+        .clone();
+    let is_set = |long: &str, short: Option<char>| {
+        args.iter().skip(1).take_while(|arg| *arg != "--").any(|arg| {
+            let arg = arg.to_string_lossy();
+            match arg.strip_prefix("--") {
+                Some(name) => name.split('=').next() == Some(long),
+                None => short.map_or(false, |short| arg.starts_with('-') && arg[1..].starts_with(short)),
+            }
+        })
+    };
+
+    let mut file_args = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let arg = match target.get_arguments().find(|arg| arg.get_long() == Some(long.as_str())) {
+            Some(arg) if long != "config" => arg,
+            _ => {
+                return Err(command.error(
+                    ErrorKind::UnknownArgument,
+                    format!("Unknown option `{}` in the config file", key),
+                ))
+            }
+        };
+        let in_env = arg
+            .get_env()
+            .map_or(false, |name: &OsStr| std::env::var_os(name).is_some());
+        if in_env || is_set(&long, arg.get_short()) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::Boolean(enabled) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                    if enabled {
+                        file_args.push(format!("--{}", long).into());
+                    }
+                    continue;
+                }
+                toml::Value::String(value) => value,
+                toml::Value::Table(_) | toml::Value::Array(_) => {
+                    return Err(command.error(
+                        ErrorKind::InvalidValue,
+                        format!("Invalid value for `{}` in the config file", key),
+                    ))
+                }
+                value => value.to_string(),
+            };
+            file_args.push(format!("--{}={}", long, value).into());
+        }
+    }
+    Ok(file_args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Subcommand;
+    use std::io::Write;
+
+    #[derive(Parser, Debug)]
+    struct Opts {
+        #[clap(flatten)]
+        config: ConfigFileOpts,
+
+        #[clap(long, default_value = "ws://127.0.0.1:9944")]
+        url: String,
+
+        #[clap(long, value_parser = parse_duration_ms, default_value = "1000")]
+        timeout_ms: Duration,
+
+        #[clap(long)]
+        flag: bool,
+
+        #[clap(long)]
+        name: Vec<String>,
+
+        #[clap(long, env = "CLI_TEST_PASSWORD")]
+        password: Option<String>,
+    }
+
+    #[derive(Parser, Debug)]
+    struct CommandOpts {
+        #[clap(flatten)]
+        config: ConfigFileOpts,
+
+        #[clap(long, default_value = "ws://127.0.0.1:9944")]
+        url: String,
+
+        #[clap(subcommand)]
+        command: Option<Command>,
+    }
+
+    #[derive(Subcommand, Debug, PartialEq, Eq)]
+    enum Command {
+        Drain {
+            #[clap(long)]
+            keyname: Option<String>,
+        },
+    }
+
+    fn config_file(contents: &str) -> tempdir::TempDir {
+        let dir = tempdir::TempDir::new("config").unwrap();
+        let mut file = std::fs::File::create(dir.path().join("config.toml")).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        dir
+    }
+
+    fn parse(dir: &tempdir::TempDir, args: &[&str]) -> Result<Opts, clap::Error> {
+        let config = format!("--config={}", dir.path().join("config.toml").display());
+        try_parse_with_config_file(["test", config.as_str()].iter().chain(args).copied())
+    }
+
+    #[test]
+    fn test_config_file_sets_defaults() {
+        let dir = config_file(
+            r#"
+            url = "ws://example.com"
+            timeout_ms = 5000
+            flag = true
+            name = ["alice", "bob"]
+            "#,
+        );
+        let opts = parse(&dir, &[]).unwrap();
+        assert_eq!(opts.url, "ws://example.com");
+        assert_eq!(opts.timeout_ms, Duration::from_millis(5000));
+        assert!(opts.flag);
+        assert_eq!(opts.name, vec!["alice", "bob"]);
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_file() {
+        let dir = config_file(
+            r#"
+            url = "ws://example.com"
+            name = ["alice", "bob"]
+            password = "secret"
+            "#,
+        );
+        std::env::set_var("CLI_TEST_PASSWORD", "from-env");
+        let opts = parse(&dir, &["--url", "ws://localhost", "--name=carol"]).unwrap();
+        std::env::remove_var("CLI_TEST_PASSWORD");
+        assert_eq!(opts.url, "ws://localhost");
+        assert_eq!(opts.name, vec!["carol"]);
+        assert_eq!(opts.password.as_deref(), Some("from-env"));
+    }
+
+    #[test]
+    fn test_config_file_applies_to_subcommand_after_options() {
+        let dir = config_file(r#"keyname = "alice""#);
+        let config = dir.path().join("config.toml").display().to_string();
+        let opts: CommandOpts =
+            try_parse_with_config_file(["test", "--url", "ws://localhost", "--config", config.as_str(), "drain"])
+                .unwrap();
+        assert_eq!(opts.url, "ws://localhost");
+        assert_eq!(
+            opts.command,
+            Some(Command::Drain {
+                keyname: Some("alice".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_file_rejects_unknown_options() {
+        let dir = config_file("unknown = 1");
+        assert_eq!(parse(&dir, &[]).unwrap_err().kind(), ErrorKind::UnknownArgument);
+    }
+
+    fn write_keystore(dir: &tempdir::TempDir, keystore: &Keystore) -> (String, PathBuf) {
+        let keyfile = dir.path().join("keyfile.json");
+        std::fs::write(&keyfile, keystore.to_json().unwrap()).unwrap();
+        let password_file = dir.path().join("password");
+        std::fs::write(&password_file, "password\n").unwrap();
+        (keyfile.display().to_string(), password_file)
+    }
+
+    #[test]
+    fn test_encrypted_keyfile() {
+        let dir = tempdir::TempDir::new("keyfile").unwrap();
+        let keystore = Keystore::encrypt(KEYFILE_CONTENT, br#"{ "MyUser1": "//Alice" }"#, "password").unwrap();
+        let (keyfile, password_file) = write_keystore(&dir, &keystore);
+
+        let pair = get_credentials_from_file(&keyfile, "MyUser1", Some(&password_file)).unwrap();
+        assert_eq!(pair.public(), AccountKeyring::Alice.public());
+        assert!(matches!(
+            get_credentials_from_file(&keyfile, "MyUser2", Some(&password_file)),
+            Err(KeyLoadingError::KeyNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_polkadot_js_keyfile() {
+        let pair = AccountKeyring::Bob.pair();
+        let keypair: &schnorrkel::Keypair = pair.as_ref();
+        let mut pkcs8 = PKCS8_HEADER.to_vec();
+        pkcs8.extend_from_slice(&keypair.secret.to_ed25519_bytes());
+        pkcs8.extend_from_slice(&PKCS8_DIVIDER);
+        pkcs8.extend_from_slice(&pair.public().0);
+
+        let dir = tempdir::TempDir::new("keyfile").unwrap();
+        let mut keystore = Keystore::encrypt(POLKADOT_JS_CONTENT, &pkcs8, "password").unwrap();
+        keystore.meta = Some(serde_json::json!({ "name": "bob" }));
+        let (keyfile, password_file) = write_keystore(&dir, &keystore);
+
+        let loaded = get_credentials_from_file(&keyfile, "bob", Some(&password_file)).unwrap();
+        assert_eq!(loaded.public(), pair.public());
+    }
+}
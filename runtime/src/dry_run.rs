@@ -0,0 +1,81 @@
+use crate::{BtcPublicKey, RawBlockHeader, H256};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// An extrinsic that is passed to the [`DryRunHook`] rather than submitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DryRunAction {
+    ExecuteIssue {
+        issue_id: H256,
+    },
+    CancelIssue {
+        issue_id: H256,
+    },
+    ExecuteRedeem {
+        redeem_id: H256,
+    },
+    CancelRedeem {
+        redeem_id: H256,
+        reimburse: bool,
+    },
+    RequestReplace {
+        vault_id: String,
+        amount: u128,
+    },
+    WithdrawReplace {
+        vault_id: String,
+        amount: u128,
+    },
+    AcceptReplace {
+        new_vault_id: String,
+        old_vault_id: String,
+        amount_btc: u128,
+        collateral: u128,
+    },
+    ExecuteReplace {
+        replace_id: H256,
+    },
+    CancelReplace {
+        replace_id: H256,
+    },
+    RegisterVault {
+        vault_id: String,
+        collateral: u128,
+    },
+    RegisterPublicKey {
+        public_key: String,
+    },
+    DepositCollateral {
+        vault_id: String,
+        amount: u128,
+    },
+    WithdrawCollateral {
+        vault_id: String,
+        amount: u128,
+    },
+    SetAcceptNewIssues {
+        vault_id: String,
+        accept_new_issues: bool,
+    },
+    InitializeBtcRelay {
+        block_hash: String,
+        height: u32,
+    },
+    StoreBlockHeaders {
+        block_hashes: Vec<String>,
+    },
+}
+
+impl DryRunAction {
+    pub(crate) fn public_key(public_key: &BtcPublicKey) -> String {
+        public_key.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub(crate) fn block_hash(header: &RawBlockHeader) -> String {
+        header.hash().to_hex_le()
+    }
+}
+
+/// Receives the extrinsics that are intercepted in dry-run mode.
+pub type DryRunHook = Arc<dyn Fn(DryRunAction) + Send + Sync>;
@@ -1,6 +1,7 @@
 pub use jsonrpsee::core::Error as JsonRpseeError;
 
 use crate::{metadata::DispatchError, types::*, BTC_RELAY_MODULE, ISSUE_MODULE, SYSTEM_MODULE};
+use bitcoin::KeystoreError;
 use codec::Error as CodecError;
 use jsonrpsee::{client_transport::ws::WsHandshakeError, core::error::Error as RequestError, types::error::CallError};
 use prometheus::Error as PrometheusError;
@@ -64,6 +65,8 @@ pub enum Error {
     BitcoinNetworkMismatch(String, String),
     #[error("Failed to load credentials from file: {0}")]
     KeyLoadingFailure(#[from] KeyLoadingError),
+    #[error("Signing failed: {0}")]
+    SignerError(#[from] SignerError),
     #[error("Error serializing: {0}")]
     Serialize(#[from] TryFromSliceError),
     #[error("Error converting: {0}")]
@@ -191,6 +194,30 @@ pub enum KeyLoadingError {
     IoError(#[from] IoError),
     #[error("Invalid secret string: {0:?}")]
     SecretStringError(SecretStringError),
+    #[error("Keystore error: {0}")]
+    KeystoreError(#[from] KeystoreError),
+    #[error("Invalid pkcs8 encoded key")]
+    InvalidPkcs8Key,
+}
+
+#[derive(Error, Debug)]
+pub enum SignerError {
+    #[error("Io error: {0}")]
+    IoError(#[from] IoError),
+    #[error("Json parsing error: {0}")]
+    JsonError(#[from] SerdeJsonError),
+    #[error("Error decoding: {0}")]
+    CodecError(#[from] CodecError),
+    #[error("Unsupported signer url: {0}")]
+    UnsupportedUrl(String),
+    #[error("Unsupported metadata version")]
+    UnsupportedMetadata,
+    #[error("Invalid response from signer: {0}")]
+    InvalidResponse(String),
+    #[error("Signer rejected the payload: {0}")]
+    Rejected(String),
+    #[error("Signer returned an invalid signature")]
+    InvalidSignature,
 }
 
 // https://github.com/paritytech/substrate/blob/e60597dff0aa7ffad623be2cc6edd94c7dc51edd/client/rpc-api/src/author/error.rs#L80
@@ -13,10 +13,15 @@ use bitcoin::{
     secp256k1::{self, constants::SECRET_KEY_SIZE, Secp256k1, SecretKey},
     serialize, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, GetBlockResult,
     Hash, Network, OutPoint, PartialMerkleTree, PrivateKey, PublicKey, SatPerVbyte, Script, Transaction,
-    TransactionExt, TransactionMetadata, TxIn, TxMerkleNode, TxOut, Txid, Uint256, PUBLIC_KEY_SIZE,
+    TransactionExt, TransactionMetadata, TxIn, TxMerkleNode, TxOut, Txid, Uint256, WalletTransaction, PUBLIC_KEY_SIZE,
 };
 use rand::{thread_rng, Rng};
-use std::{convert::TryInto, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     sync::{Mutex, OwnedMutexGuard, RwLock},
     time::sleep,
@@ -31,10 +36,34 @@ use tokio::{
 pub struct MockBitcoinCore {
     parachain_rpc: Arc<InterBtcParachain>,
     blocks: Arc<RwLock<Vec<Block>>>,
+    /// blocks that are known but not part of the best chain
+    stale_blocks: Arc<RwLock<Vec<Block>>>,
     mempool: Arc<RwLock<Vec<Transaction>>>,
+    /// fee rates the transactions were created with, since they are not actually funded
+    fee_rates: Arc<RwLock<HashMap<Txid, SatPerVbyte>>>,
     transaction_creation_lock: Arc<Mutex<()>>,
 }
 
+/// A branch competing with the best chain of a [`MockBitcoinCore`]. It starts after
+/// the block at `parent_height`, and becomes the best chain through
+/// [`MockBitcoinCore::reorg_to`].
+#[derive(Clone, Debug)]
+pub struct Branch {
+    parent_height: u32,
+    blocks: Vec<Block>,
+}
+
+impl Branch {
+    /// height of the last block of the branch
+    pub fn tip_height(&self) -> u32 {
+        self.parent_height + self.blocks.len() as u32
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+}
+
 pub fn to_block_header(value: Vec<u8>) -> RawBlockHeader {
     crate::RawBlockHeader(value.try_into().unwrap())
 }
@@ -45,7 +74,9 @@ impl MockBitcoinCore {
         let ret = Self {
             parachain_rpc: Arc::new(parachain_rpc),
             blocks: Arc::new(RwLock::new(vec![])),
+            stale_blocks: Arc::new(RwLock::new(vec![])),
             mempool: Arc::new(RwLock::new(vec![])),
+            fee_rates: Arc::new(RwLock::new(HashMap::new())),
             transaction_creation_lock: Arc::new(Mutex::new(())),
         };
 
@@ -86,13 +117,88 @@ impl MockBitcoinCore {
         Self {
             parachain_rpc: Arc::new(parachain_rpc),
             blocks: Arc::new(RwLock::new(vec![])),
+            stale_blocks: Arc::new(RwLock::new(vec![])),
             mempool: Arc::new(RwLock::new(vec![])),
+            fee_rates: Arc::new(RwLock::new(HashMap::new())),
             transaction_creation_lock: Arc::new(Mutex::new(())),
         }
     }
 
-    /// creates a fork
-    pub fn enable_forking_test() {}
+    /// Start a branch that competes with the best chain after the block at `parent_height`.
+    pub async fn create_branch(&self, parent_height: u32) -> Branch {
+        assert!(
+            (parent_height as usize) < self.blocks.read().await.len(),
+            "parent of the branch does not exist"
+        );
+        Branch {
+            parent_height,
+            blocks: vec![],
+        }
+    }
+
+    /// Add a block with the given transactions on top of the branch, and relay it to the parachain.
+    /// The block does not become part of the best chain until the chain is reorganized to the branch.
+    pub async fn extend_branch(&self, branch: &mut Branch, transactions: Vec<Transaction>) -> Block {
+        let block = self.extend_branch_unrelayed(branch, transactions).await;
+        self.send_block(block.clone()).await;
+        block
+    }
+
+    /// Like [`MockBitcoinCore::extend_branch`], but without relaying the block, such that the
+    /// relayer has to submit it.
+    pub async fn extend_branch_unrelayed(&self, branch: &mut Branch, transactions: Vec<Transaction>) -> Block {
+        let prev_blockhash = match branch.blocks.last() {
+            Some(block) => block.block_hash(),
+            None => self.blocks.read().await[branch.parent_height as usize].block_hash(),
+        };
+        // use a random coinbase address, such that the block differs from any other at this height
+        let coinbase_address = BtcAddress::P2PKH(H160::from(thread_rng().gen::<[u8; 20]>()));
+        let block = Self::build_block(prev_blockhash, branch.tip_height() + 1, &coinbase_address, transactions);
+
+        branch.blocks.push(block.clone());
+        self.stale_blocks.write().await.push(block.clone());
+        block
+    }
+
+    /// Make the given branch the best chain. It must be longer than the current best chain.
+    /// Transactions that are only in the replaced blocks are moved to the mempool, and
+    /// transactions in the branch are removed from the mempool.
+    pub async fn reorg_to(&self, branch: &Branch) {
+        // lock the mempool first, in the same order as `flush_mempool`
+        let mut mempool = self.mempool.write().await;
+        let mut blocks = self.blocks.write().await;
+        let mut stale_blocks = self.stale_blocks.write().await;
+
+        let parent = &blocks[branch.parent_height as usize];
+        assert_eq!(
+            branch.blocks.first().map(|x| x.header.prev_blockhash),
+            Some(parent.block_hash()),
+            "branch does not build on the best chain"
+        );
+        assert!(
+            branch.tip_height() as usize >= blocks.len(),
+            "branch is not longer than the best chain"
+        );
+
+        let replaced = blocks.split_off(branch.parent_height as usize + 1);
+        blocks.extend(branch.blocks.iter().cloned());
+        stale_blocks.retain(|x| !branch.blocks.contains(x));
+        stale_blocks.extend(replaced.iter().cloned());
+
+        let included = branch
+            .blocks
+            .iter()
+            .flat_map(|x| x.txdata.iter().map(|y| y.txid()))
+            .collect::<HashSet<_>>();
+        mempool.retain(|x| !included.contains(&x.txid()));
+        for transaction in replaced.iter().flat_map(|x| x.txdata.iter().skip(1)) {
+            // skip the coinbase transactions, which are invalid outside of their block
+            let txid = transaction.txid();
+            if !included.contains(&txid) && !mempool.iter().any(|x| x.txid() == txid) {
+                mempool.push(transaction.clone());
+            }
+        }
+    }
 
     /// relay a given block to the parachain
     async fn send_block(&self, block: Block) {
@@ -104,10 +210,6 @@ impl MockBitcoinCore {
     }
 
     async fn generate_block_with_transaction(&self, transaction: &Transaction) -> Block {
-        let target = U256::from(2).pow(254.into());
-        let mut bytes = [0u8; 32];
-        target.to_big_endian(&mut bytes);
-        let target = Uint256::from_be_bytes(bytes);
         let mut blocks = self.blocks.write().await;
 
         let prev_blockhash = if blocks.is_empty() {
@@ -116,15 +218,34 @@ impl MockBitcoinCore {
             blocks[blocks.len() - 1].header.block_hash()
         };
 
+        let block = Self::build_block(
+            prev_blockhash,
+            blocks.len() as u32,
+            &BtcAddress::P2PKH(H160::from([1; 20])),
+            vec![transaction.clone()],
+        );
+
+        blocks.push(block.clone());
+
+        block
+    }
+
+    /// build a block at the given height, with a coinbase transaction followed by `transactions`
+    fn build_block(
+        prev_blockhash: BlockHash,
+        height: u32,
+        coinbase_address: &BtcAddress,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let target = U256::from(2).pow(254.into());
+        let mut bytes = [0u8; 32];
+        target.to_big_endian(&mut bytes);
+        let target = Uint256::from_be_bytes(bytes);
+
         let mut block = Block {
-            txdata: vec![
-                Self::generate_coinbase_transaction(
-                    &BtcAddress::P2PKH(H160::from([1; 20])),
-                    10000,
-                    blocks.len() as u32,
-                ),
-                transaction.clone(),
-            ],
+            txdata: std::iter::once(Self::generate_coinbase_transaction(coinbase_address, 10000, height))
+                .chain(transactions)
+                .collect(),
             header: BlockHeader {
                 version: 4,
                 merkle_root: TxMerkleNode::all_zeros(),
@@ -143,8 +264,6 @@ impl MockBitcoinCore {
             block.header.nonce += 1;
         }
 
-        blocks.push(block.clone());
-
         block
     }
 
@@ -214,6 +333,19 @@ impl MockBitcoinCore {
         }
     }
 
+    /// produce `count` blocks that only pay to a dummy address, and relay them to the parachain
+    pub async fn mine_blocks(&self, count: u32) {
+        let address = BtcAddress::P2PKH(H160::from([0; 20]))
+            .to_address(Network::Regtest)
+            .unwrap();
+        for _ in 0..count {
+            let block = self
+                .generate_block_with_transaction(&Self::generate_normal_transaction(&address, 10000))
+                .await;
+            self.send_block(block).await;
+        }
+    }
+
     /// send a transaction to the mempool
     pub async fn send_to_mempool(&self, transaction: Transaction) {
         self.mempool.write().await.push(transaction);
@@ -289,7 +421,7 @@ impl MockBitcoinCore {
         &self,
         address: Address,
         sat: u64,
-        _fee_rate: SatPerVbyte, // ignored in this impl
+        fee_rate: SatPerVbyte,
         request_id: Option<H256>,
     ) -> Result<Transaction, BitcoinError> {
         let mut transaction = MockBitcoinCore::generate_normal_transaction(&address, sat);
@@ -305,6 +437,7 @@ impl MockBitcoinCore {
             transaction.output.push(op_return);
         }
 
+        self.fee_rates.write().await.insert(transaction.txid(), fee_rate);
         Ok(transaction)
     }
 }
@@ -314,11 +447,12 @@ impl BitcoinCoreApi for MockBitcoinCore {
     fn network(&self) -> Network {
         Network::Regtest
     }
-    async fn wait_for_block(&self, height: u32, _num_confirmations: u32) -> Result<Block, BitcoinError> {
+    async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError> {
         loop {
             let blocks = self.blocks.read().await;
-            if let Some(block) = blocks.get(height as usize + 1) {
-                return Ok(block.clone());
+            // same as bitcoin core, the block itself counts as a confirmation
+            if blocks.len() >= height as usize + num_confirmations.max(1) as usize {
+                return Ok(blocks[height as usize].clone());
             }
             drop(blocks); // release the lock
             sleep(Duration::from_secs(1)).await;
@@ -330,6 +464,12 @@ impl BitcoinCoreApi for MockBitcoinCore {
     fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError> {
         Ok(vec![])
     }
+    async fn list_wallet_transactions(
+        &self,
+        _since_height: Option<u32>,
+    ) -> Result<Vec<WalletTransaction>, BitcoinError> {
+        Ok(vec![])
+    }
     async fn get_block_count(&self) -> Result<u64, BitcoinError> {
         Ok((self.blocks.read().await.len() - 1).try_into().unwrap())
     }
@@ -406,20 +546,18 @@ impl BitcoinCoreApi for MockBitcoinCore {
         Ok(0)
     }
     async fn get_block(&self, hash: &BlockHash) -> Result<Block, BitcoinError> {
+        // like bitcoin core, also return blocks that are no longer in the best chain
         let blocks = self.blocks.read().await;
+        let stale_blocks = self.stale_blocks.read().await;
         let block = blocks
             .iter()
+            .chain(stale_blocks.iter())
             .find(|x| &x.block_hash() == hash)
             .ok_or(BitcoinError::InvalidBitcoinHeight)?;
         Ok(block.clone())
     }
     async fn get_block_header(&self, hash: &BlockHash) -> Result<BlockHeader, BitcoinError> {
-        let blocks = self.blocks.read().await;
-        let block = blocks
-            .iter()
-            .find(|x| &x.block_hash() == hash)
-            .ok_or(BitcoinError::InvalidBitcoinHeight)?;
-        Ok(block.header)
+        Ok(self.get_block(hash).await?.header)
     }
     async fn get_mempool_transactions<'a>(
         &'a self,
@@ -465,6 +603,19 @@ impl BitcoinCoreApi for MockBitcoinCore {
         let txid = self.send_transaction(&tx).await?;
         Ok(txid)
     }
+    async fn create_and_send_transaction_with_max_fee(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        max_fee: u64,
+        request_id: Option<H256>,
+    ) -> Result<Txid, BitcoinError> {
+        let tx = self.create_transaction(address, sat, fee_rate, request_id).await?;
+        bitcoin::check_max_fee(&tx, fee_rate, max_fee)?;
+        let txid = self.send_transaction(&tx).await?;
+        Ok(txid)
+    }
     async fn send_to_address(
         &self,
         address: Address,
@@ -498,14 +649,42 @@ impl BitcoinCoreApi for MockBitcoinCore {
     }
 
     async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError> {
-        unimplemented!()
+        let mut mempool = self.mempool.write().await;
+        let transaction = mempool
+            .iter_mut()
+            .find(|x| x.txid() == *txid)
+            // confirmed transactions can't be bumped
+            .ok_or(BitcoinError::ConfirmationError)?;
+
+        // the transaction is not funded, so the replacement only differs in the sequence
+        // numbers of its inputs
+        for input in transaction.input.iter_mut() {
+            input.sequence = Sequence(input.sequence.0.wrapping_add(1));
+        }
+        let bumped_txid = transaction.txid();
+        self.fee_rates.write().await.insert(bumped_txid, fee_rate);
+        Ok(bumped_txid)
     }
 
     async fn is_in_mempool(&self, txid: Txid) -> Result<bool, BitcoinError> {
-        unimplemented!()
+        Ok(self.mempool.read().await.iter().any(|x| x.txid() == txid))
     }
 
     async fn fee_rate(&self, txid: Txid) -> Result<SatPerVbyte, BitcoinError> {
-        unimplemented!()
+        self.fee_rates
+            .read()
+            .await
+            .get(&txid)
+            .copied()
+            .ok_or(BitcoinError::MissingBitcoinFeeInfo)
+    }
+}
+
+#[async_trait]
+impl bitcoin::conformance::Harness for MockBitcoinCore {
+    async fn mine_blocks(&self, count: u32) -> Result<(), BitcoinError> {
+        self.flush_mempool().await;
+        MockBitcoinCore::mine_blocks(self, count).await;
+        Ok(())
     }
 }
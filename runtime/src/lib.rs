@@ -5,9 +5,11 @@ pub mod cli;
 mod addr;
 mod assets;
 mod conn;
+mod dry_run;
 mod error;
 mod retry;
 mod rpc;
+mod signer;
 
 pub mod types;
 
@@ -26,7 +28,8 @@ use subxt::{
 
 pub use addr::PartialAddress;
 pub use assets::{AssetRegistry, RuntimeCurrencyInfo, TryFromSymbol};
-pub use error::{Error, SubxtError};
+pub use dry_run::{DryRunAction, DryRunHook};
+pub use error::{Error, SignerError, SubxtError};
 pub use primitives::CurrencyInfo;
 pub use prometheus;
 pub use retry::{notify_retry, RetryPolicy};
@@ -37,6 +40,7 @@ pub use rpc::{
     OraclePallet, RedeemPallet, ReplacePallet, SecurityPallet, TimestampPallet, UtilFuncs, VaultRegistryPallet,
     DEFAULT_SPEC_NAME, SS58_PREFIX,
 };
+pub use signer::{CallNames, InterBtcSigner, RemoteSigner, SignerRequest, SignerResponse, UnhashedPayload};
 pub use sp_arithmetic::{traits as FixedPointTraits, FixedI128, FixedPointNumber, FixedU128};
 use std::time::Duration;
 pub use subxt::{
@@ -1,5 +1,6 @@
 use crate::{
     conn::{new_websocket_client, new_websocket_client_with_retry},
+    dry_run::{DryRunAction, DryRunHook},
     metadata,
     metadata::{DispatchError, Event as InterBtcEvent},
     notify_retry,
@@ -14,7 +15,7 @@ use primitives::UnsignedFixedPoint;
 use serde_json::Value;
 use std::{collections::BTreeSet, future::Future, ops::Range, sync::Arc, time::Duration};
 use subxt::{
-    rpc::{rpc_params, ClientT},
+    rpc::{rpc_params, ClientT, RuntimeVersion},
     BasicError, Client as SubxtClient, ClientBuilder as SubxtClientBuilder, Event, PolkadotExtrinsicParams, RpcClient,
     TransactionEvents, TransactionProgress,
 };
@@ -69,9 +70,11 @@ pub struct InterBtcParachain {
     api: Arc<RuntimeApi>,
     shutdown_tx: ShutdownSender,
     fee_rate_update_tx: FeeRateUpdateSender,
+    runtime_version: RuntimeVersion,
     pub native_currency_id: CurrencyId,
     pub relay_chain_currency_id: CurrencyId,
     pub wrapped_currency_id: CurrencyId,
+    dry_run: Option<DryRunHook>,
 }
 
 impl InterBtcParachain {
@@ -124,9 +127,11 @@ impl InterBtcParachain {
             account_id,
             shutdown_tx,
             fee_rate_update_tx,
+            runtime_version,
             native_currency_id,
             relay_chain_currency_id,
             wrapped_currency_id,
+            dry_run: None,
         };
         parachain_rpc.refresh_nonce().await;
         // TODO: refresh on registration
@@ -222,6 +227,34 @@ impl InterBtcParachain {
         signer.set_nonce(account_info);
     }
 
+    /// Passes the extrinsics that have side effects to `hook` instead of submitting them.
+    pub fn with_dry_run(mut self, hook: DryRunHook) -> Self {
+        self.dry_run = Some(hook);
+        self
+    }
+
+    /// Returns true if the extrinsic must not be submitted, after passing it to the dry-run hook.
+    fn intercept(&self, action: impl FnOnce() -> DryRunAction) -> bool {
+        match &self.dry_run {
+            Some(hook) => {
+                hook(action());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lets a remote signer check `call` even if its payload is too long to be signed directly,
+    /// which is the case for the execution of requests with large bitcoin transactions.
+    fn with_call(&self, signer: &InterBtcSigner, call: &EncodedCall) -> InterBtcSigner {
+        signer.with_call(
+            call,
+            self.runtime_version.spec_version,
+            self.runtime_version.transaction_version,
+            *self.ext_client.genesis(),
+        )
+    }
+
     /// Gets a copy of the signer with a unique nonce
     async fn with_unique_signer<'client, F, R>(
         &self,
@@ -235,18 +268,23 @@ impl InterBtcParachain {
     {
         notify_retry::<Error, _, _, _, _, _>(
             || async {
-                let signer = {
+                let (signer, failure) = {
                     let mut signer = self.signer.write().await;
                     // return the current value, increment afterwards
-                    let cloned_signer = signer.clone();
+                    let cloned_signer = signer.with_failure_slot();
                     signer.increment_nonce();
                     cloned_signer
                 };
                 match timeout(TRANSACTION_TIMEOUT, async {
+                    let progress = call(signer).await;
+                    // a failed remote signer lets the submission fail, report the cause instead
+                    if let Some(err) = failure.take() {
+                        return Err(Error::SignerError(err));
+                    }
                     if cfg!(feature = "testing-utils") {
-                        call(signer).await?.wait_for_in_block().await?.wait_for_success().await
+                        Ok(progress?.wait_for_in_block().await?.wait_for_success().await?)
                     } else {
-                        call(signer).await?.wait_for_finalized_success().await
+                        Ok(progress?.wait_for_finalized_success().await?)
                     }
                 })
                 .await
@@ -256,7 +294,7 @@ impl InterBtcParachain {
                         let _ = self.shutdown_tx.send(());
                         Err(Error::Timeout)
                     }
-                    Ok(x) => Ok(x?),
+                    Ok(x) => x,
                 }
             },
             |result| async {
@@ -273,6 +311,10 @@ impl InterBtcParachain {
                             self.refresh_nonce().await;
                             log::info!("Re-sending transaction after apparent fork");
                             Err(RetryPolicy::Skip(Error::BlockHashNotFound))
+                        } else if let Error::SignerError(_) = err {
+                            // the nonce was not used by the rejected extrinsic
+                            self.refresh_nonce().await;
+                            Err(RetryPolicy::Throw(err))
                         } else {
                             Err(RetryPolicy::Throw(err))
                         }
@@ -799,6 +841,12 @@ pub trait ReplacePallet {
 #[async_trait]
 impl ReplacePallet for InterBtcParachain {
     async fn request_replace(&self, vault_id: &VaultId, amount: u128) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::RequestReplace {
+            vault_id: vault_id.pretty_print(),
+            amount,
+        }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -812,6 +860,12 @@ impl ReplacePallet for InterBtcParachain {
     }
 
     async fn withdraw_replace(&self, vault_id: &VaultId, amount: u128) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::WithdrawReplace {
+            vault_id: vault_id.pretty_print(),
+            amount,
+        }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -832,6 +886,14 @@ impl ReplacePallet for InterBtcParachain {
         collateral: u128,
         btc_address: BtcAddress,
     ) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::AcceptReplace {
+            new_vault_id: new_vault.pretty_print(),
+            old_vault_id: old_vault.pretty_print(),
+            amount_btc,
+            collateral,
+        }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -851,12 +913,20 @@ impl ReplacePallet for InterBtcParachain {
     }
 
     async fn execute_replace(&self, replace_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::ExecuteReplace { replace_id }) {
+            return Ok(());
+        }
+        let call = &EncodedCall::Replace(metadata::runtime_types::replace::pallet::Call::execute_replace {
+            replace_id,
+            merkle_proof: merkle_proof.into(),
+            raw_tx: raw_tx.into(),
+        });
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
                 .replace()
                 .execute_replace(replace_id, merkle_proof.into(), raw_tx.into())
-                .sign_and_submit_then_watch_default(&signer)
+                .sign_and_submit_then_watch_default(&self.with_call(&signer, call))
                 .await
         })
         .await?;
@@ -864,6 +934,9 @@ impl ReplacePallet for InterBtcParachain {
     }
 
     async fn cancel_replace(&self, replace_id: H256) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::CancelReplace { replace_id }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -1148,12 +1221,20 @@ impl IssuePallet for InterBtcParachain {
     }
 
     async fn execute_issue(&self, issue_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::ExecuteIssue { issue_id }) {
+            return Ok(());
+        }
+        let call = &EncodedCall::Issue(metadata::runtime_types::issue::pallet::Call::execute_issue {
+            issue_id,
+            merkle_proof: merkle_proof.into(),
+            raw_tx: raw_tx.into(),
+        });
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
                 .issue()
                 .execute_issue(issue_id, merkle_proof.into(), raw_tx.into())
-                .sign_and_submit_then_watch_default(&signer)
+                .sign_and_submit_then_watch_default(&self.with_call(&signer, call))
                 .await
         })
         .await?;
@@ -1161,6 +1242,9 @@ impl IssuePallet for InterBtcParachain {
     }
 
     async fn cancel_issue(&self, issue_id: H256) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::CancelIssue { issue_id }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -1270,12 +1354,20 @@ impl RedeemPallet for InterBtcParachain {
     }
 
     async fn execute_redeem(&self, redeem_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::ExecuteRedeem { redeem_id }) {
+            return Ok(());
+        }
+        let call = &EncodedCall::Redeem(metadata::runtime_types::redeem::pallet::Call::execute_redeem {
+            redeem_id,
+            merkle_proof: merkle_proof.into(),
+            raw_tx: raw_tx.into(),
+        });
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
                 .redeem()
                 .execute_redeem(redeem_id, merkle_proof.into(), raw_tx.into())
-                .sign_and_submit_then_watch_default(&signer)
+                .sign_and_submit_then_watch_default(&self.with_call(&signer, call))
                 .await
         })
         .await?;
@@ -1283,6 +1375,9 @@ impl RedeemPallet for InterBtcParachain {
     }
 
     async fn cancel_redeem(&self, redeem_id: H256, reimburse: bool) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::CancelRedeem { redeem_id, reimburse }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -1459,6 +1554,12 @@ impl BtcRelayPallet for InterBtcParachain {
     /// * `header` - raw block header
     /// * `height` - starting height
     async fn initialize_btc_relay(&self, header: RawBlockHeader, height: BitcoinBlockHeight) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::InitializeBtcRelay {
+            block_hash: DryRunAction::block_hash(&header),
+            height,
+        }) {
+            return Ok(());
+        }
         let header = &header;
         // TODO: can we initialize the relay through the chain-spec?
         // we would also need to consider re-initialization per governance
@@ -1479,6 +1580,11 @@ impl BtcRelayPallet for InterBtcParachain {
     /// # Arguments
     /// * `header` - raw block header
     async fn store_block_header(&self, header: RawBlockHeader) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::StoreBlockHeaders {
+            block_hashes: vec![DryRunAction::block_hash(&header)],
+        }) {
+            return Ok(());
+        }
         let header = &header;
         self.with_unique_signer(|signer| async move {
             self.api
@@ -1497,6 +1603,11 @@ impl BtcRelayPallet for InterBtcParachain {
     /// # Arguments
     /// * `headers` - raw block headers
     async fn store_block_headers(&self, headers: Vec<RawBlockHeader>) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::StoreBlockHeaders {
+            block_hashes: headers.iter().map(DryRunAction::block_hash).collect(),
+        }) {
+            return Ok(());
+        }
         self.batch(
             headers
                 .into_iter()
@@ -1525,6 +1636,8 @@ pub trait VaultRegistryPallet {
 
     async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), Error>;
 
+    async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), Error>;
+
     async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, Error>;
 
     async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), Error>;
@@ -1597,6 +1710,12 @@ impl VaultRegistryPallet for InterBtcParachain {
     /// * `collateral` - deposit
     /// * `public_key` - Bitcoin public key
     async fn register_vault(&self, vault_id: &VaultId, collateral: u128) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::RegisterVault {
+            vault_id: vault_id.pretty_print(),
+            collateral,
+        }) {
+            return Ok(());
+        }
         // TODO: check MinimumDeposit
         if collateral == 0 {
             return Err(Error::InsufficientFunds);
@@ -1620,6 +1739,12 @@ impl VaultRegistryPallet for InterBtcParachain {
     /// # Arguments
     /// * `amount` - the amount of extra collateral to lock
     async fn deposit_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::DepositCollateral {
+            vault_id: vault_id.pretty_print(),
+            amount,
+        }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -1642,6 +1767,12 @@ impl VaultRegistryPallet for InterBtcParachain {
     /// # Arguments
     /// * `amount` - the amount of collateral to withdraw
     async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::WithdrawCollateral {
+            vault_id: vault_id.pretty_print(),
+            amount,
+        }) {
+            return Ok(());
+        }
         self.with_unique_signer(|signer| async move {
             self.api
                 .tx()
@@ -1654,6 +1785,29 @@ impl VaultRegistryPallet for InterBtcParachain {
         Ok(())
     }
 
+    /// Sets whether the vault accepts new issue requests.
+    ///
+    /// # Arguments
+    /// * `accept_new_issues` - false to stop the vault from being selected for issues
+    async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::SetAcceptNewIssues {
+            vault_id: vault_id.pretty_print(),
+            accept_new_issues,
+        }) {
+            return Ok(());
+        }
+        self.with_unique_signer(|signer| async move {
+            self.api
+                .tx()
+                .vault_registry()
+                .accept_new_issues(vault_id.currencies.clone(), accept_new_issues)
+                .sign_and_submit_then_watch_default(&signer)
+                .await
+        })
+        .await?;
+        Ok(())
+    }
+
     async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, Error> {
         let head = self.get_latest_block_hash().await?;
         Ok(self
@@ -1669,6 +1823,11 @@ impl VaultRegistryPallet for InterBtcParachain {
     /// # Arguments
     /// * `public_key` - the new public key of the vault
     async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), Error> {
+        if self.intercept(|| DryRunAction::RegisterPublicKey {
+            public_key: DryRunAction::public_key(&public_key),
+        }) {
+            return Ok(());
+        }
         let public_key = &public_key.clone();
         self.with_unique_signer(|signer| async move {
             self.api
@@ -0,0 +1,474 @@
+//! Signing of extrinsics, either with an in-process key pair or by a separate signer process
+//! so that the key does not have to live in the memory of the client.
+
+use crate::{error::SignerError, AccountId, EncodedCall, Index, InterBtcRuntime, KeyPair, H256};
+use codec::{Decode, Encode};
+use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed};
+use scale_info::TypeDef;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use subxt::{
+    extrinsic::{ExtrinsicParams, Signer},
+    sp_core::{
+        crypto::Ss58Codec,
+        sr25519::{Public, Signature},
+        Pair,
+    },
+    sp_runtime::MultiSignature,
+    PolkadotExtrinsicParams,
+};
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task::block_in_place,
+};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "parachain-metadata-interlay")] {
+        const METADATA: &[u8] = include_bytes!("../metadata-parachain-interlay.scale");
+    } else if #[cfg(feature = "parachain-metadata-kintsugi")] {
+        const METADATA: &[u8] = include_bytes!("../metadata-parachain-kintsugi.scale");
+    } else if #[cfg(feature = "parachain-metadata-interlay-testnet")] {
+        const METADATA: &[u8] = include_bytes!("../metadata-parachain-interlay-testnet.scale");
+    } else if #[cfg(feature = "parachain-metadata-kintsugi-testnet")] {
+        const METADATA: &[u8] = include_bytes!("../metadata-parachain-kintsugi-testnet.scale");
+    }
+}
+
+// subxt signs the hash of payloads longer than 256 bytes
+const HASHED_PAYLOAD_LENGTH: usize = 32;
+const REMOTE_SIGNER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signs extrinsics for [`crate::InterBtcParachain`].
+#[derive(Clone)]
+pub struct InterBtcSigner {
+    account_id: AccountId,
+    nonce: Option<Index>,
+    backend: SignerBackend,
+    failure: FailureSlot,
+    unhashed: Option<UnhashedPayload>,
+}
+
+#[derive(Clone)]
+enum SignerBackend {
+    Local(KeyPair),
+    Remote(Arc<RemoteSigner>),
+}
+
+/// Holds the error of a failed signing attempt, since [`Signer::sign`] can not return one.
+#[derive(Clone, Default)]
+pub(crate) struct FailureSlot(Arc<Mutex<Option<SignerError>>>);
+
+impl FailureSlot {
+    pub(crate) fn take(&self) -> Option<SignerError> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    fn set(&self, err: SignerError) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+    }
+}
+
+impl InterBtcSigner {
+    /// Signs with the given key pair.
+    pub fn new(pair: KeyPair) -> Self {
+        Self {
+            account_id: pair.public().into(),
+            nonce: None,
+            backend: SignerBackend::Local(pair),
+            failure: Default::default(),
+            unhashed: None,
+        }
+    }
+
+    /// Sends the payloads to the signer process behind `remote`.
+    pub fn remote(remote: RemoteSigner) -> Self {
+        Self {
+            account_id: remote.account_id.clone(),
+            nonce: None,
+            backend: SignerBackend::Remote(Arc::new(remote)),
+            failure: Default::default(),
+            unhashed: None,
+        }
+    }
+
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    pub fn set_nonce(&mut self, nonce: Index) {
+        self.nonce = Some(nonce);
+    }
+
+    pub fn increment_nonce(&mut self) {
+        self.nonce = self.nonce.map(|nonce| nonce + 1);
+    }
+
+    /// Returns a copy of the signer that reports signing failures to the returned slot.
+    pub(crate) fn with_failure_slot(&self) -> (Self, FailureSlot) {
+        let failure = FailureSlot::default();
+        let signer = Self {
+            failure: failure.clone(),
+            ..self.clone()
+        };
+        (signer, failure)
+    }
+
+    /// Returns a copy of the signer that passes the unhashed payload of `call` to a remote
+    /// signer, so that it can check the call even if the payload is too long to be signed
+    /// directly. The extrinsic has to be submitted with the default parameters.
+    pub(crate) fn with_call(
+        &self,
+        call: &EncodedCall,
+        spec_version: u32,
+        transaction_version: u32,
+        genesis_hash: H256,
+    ) -> Self {
+        let unhashed = self.nonce.map(|nonce| {
+            let params = PolkadotExtrinsicParams::<InterBtcRuntime>::new(
+                spec_version,
+                transaction_version,
+                nonce,
+                genesis_hash,
+                Default::default(),
+            );
+            let (mut extra, mut additional) = (Vec::new(), Vec::new());
+            params.encode_extra_to(&mut extra);
+            params.encode_additional_to(&mut additional);
+            UnhashedPayload {
+                call: hex::encode(call.encode()),
+                extra: hex::encode(extra),
+                additional: hex::encode(additional),
+            }
+        });
+        Self {
+            unhashed,
+            ..self.clone()
+        }
+    }
+}
+
+impl Signer<InterBtcRuntime> for InterBtcSigner {
+    fn nonce(&self) -> Option<Index> {
+        self.nonce
+    }
+
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    fn address(&self) -> AccountId {
+        self.account_id.clone()
+    }
+
+    fn sign(&self, payload: &[u8]) -> MultiSignature {
+        match &self.backend {
+            SignerBackend::Local(pair) => pair.sign(payload).into(),
+            SignerBackend::Remote(remote) => match remote.sign(payload, self.unhashed.as_ref()) {
+                Ok(signature) => signature.into(),
+                Err(err) => {
+                    log::error!("Failed to sign with the remote signer: {}", err);
+                    self.failure.set(err);
+                    // the extrinsic is rejected by the node, after which the failure is reported
+                    Signature::from_raw([0; 64]).into()
+                }
+            },
+        }
+    }
+}
+
+/// A request to the signer process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum SignerRequest {
+    AccountId,
+    /// Sign the hex encoded `payload`. If the payload is hashed, its unhashed parts may be
+    /// passed along so that the call can be checked.
+    Sign {
+        payload: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unhashed: Option<UnhashedPayload>,
+    },
+}
+
+/// The hex encoded parts of a signing payload, which is their concatenation, or the blake2
+/// hash of it if it is longer than 256 bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnhashedPayload {
+    /// The call, starting with its pallet and call index.
+    pub call: String,
+    pub extra: String,
+    pub additional: String,
+}
+
+impl UnhashedPayload {
+    /// Decodes the call and the whole payload.
+    pub fn decode(&self) -> Result<(Vec<u8>, Vec<u8>), hex::FromHexError> {
+        let call = hex::decode(self.call.trim_start_matches("0x"))?;
+        let mut payload = call.clone();
+        payload.extend(hex::decode(self.extra.trim_start_matches("0x"))?);
+        payload.extend(hex::decode(self.additional.trim_start_matches("0x"))?);
+        Ok((call, payload))
+    }
+}
+
+/// The response of the signer process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerResponse {
+    /// The ss58 encoded account.
+    AccountId(String),
+    /// The hex encoded sr25519 signature.
+    Signature(String),
+    Rejected(String),
+}
+
+#[derive(Debug, Clone)]
+enum Endpoint {
+    Unix(String),
+    Http {
+        host: String,
+        path: String,
+        token: Option<String>,
+    },
+}
+
+/// Client of a signer process, which is reached over a unix socket (`unix:<path>`), with one
+/// json request and response per line, or over http (`http://<host>[/<path>]`), where the
+/// request is posted as json with an optional bearer token. Signing happens during the
+/// submission of an extrinsic, which can not be awaited, so the requests are blocking.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    endpoint: Endpoint,
+    account_id: AccountId,
+}
+
+impl RemoteSigner {
+    /// Connects to the signer at `url` and queries its account. The `token` is only sent
+    /// over http.
+    pub fn connect(url: &str, token: Option<String>) -> Result<Self, SignerError> {
+        let endpoint = if let Some(path) = url.strip_prefix("unix:") {
+            Endpoint::Unix(path.trim_start_matches("//").to_string())
+        } else if let Some(address) = url.strip_prefix("http://") {
+            let (host, path) = address.split_at(address.find('/').unwrap_or(address.len()));
+            Endpoint::Http {
+                host: host.to_string(),
+                path: if path.is_empty() {
+                    "/".to_string()
+                } else {
+                    path.to_string()
+                },
+                token,
+            }
+        } else {
+            return Err(SignerError::UnsupportedUrl(url.to_string()));
+        };
+
+        match request(&endpoint, &SignerRequest::AccountId)? {
+            SignerResponse::AccountId(account_id) => Ok(Self {
+                endpoint,
+                account_id: AccountId::from_ss58check_with_version(&account_id)
+                    .map_err(|err| SignerError::InvalidResponse(format!("{:?}", err)))?
+                    .0,
+            }),
+            SignerResponse::Rejected(reason) => Err(SignerError::Rejected(reason)),
+            response => Err(SignerError::InvalidResponse(format!("{:?}", response))),
+        }
+    }
+
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Requests the signature of `payload`, and verifies it against the account of the signer.
+    /// The `unhashed` payload is only passed along if `payload` is hashed.
+    pub fn sign(&self, payload: &[u8], unhashed: Option<&UnhashedPayload>) -> Result<Signature, SignerError> {
+        let request = SignerRequest::Sign {
+            payload: hex::encode(payload),
+            unhashed: unhashed.filter(|_| CallNames::is_hashed(payload)).cloned(),
+        };
+        let signature = match self::request(&self.endpoint, &request)? {
+            SignerResponse::Signature(signature) => signature,
+            SignerResponse::Rejected(reason) => return Err(SignerError::Rejected(reason)),
+            response => return Err(SignerError::InvalidResponse(format!("{:?}", response))),
+        };
+
+        let signature = hex::decode(signature.trim_start_matches("0x")).map_err(|_| SignerError::InvalidSignature)?;
+        let signature = Signature::from_slice(&signature).ok_or(SignerError::InvalidSignature)?;
+        let public = Public::from_raw(*self.account_id.as_ref());
+        if KeyPair::verify(&signature, payload, &public) {
+            Ok(signature)
+        } else {
+            Err(SignerError::InvalidSignature)
+        }
+    }
+}
+
+/// Runs the blocking `f` without stalling the other tasks on the worker thread, if called
+/// from a multi-threaded runtime.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(f),
+        _ => f(),
+    }
+}
+
+fn request(endpoint: &Endpoint, request: &SignerRequest) -> Result<SignerResponse, SignerError> {
+    let body = serde_json::to_string(request)?;
+    blocking(|| send(endpoint, &body))
+}
+
+fn send(endpoint: &Endpoint, body: &str) -> Result<SignerResponse, SignerError> {
+    let response = match endpoint {
+        Endpoint::Unix(path) => {
+            let mut stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(REMOTE_SIGNER_TIMEOUT))?;
+            stream.set_write_timeout(Some(REMOTE_SIGNER_TIMEOUT))?;
+            stream.write_all(format!("{}\n", body).as_bytes())?;
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line)?;
+            line
+        }
+        Endpoint::Http { host, path, token } => {
+            let mut stream = TcpStream::connect(host)?;
+            stream.set_read_timeout(Some(REMOTE_SIGNER_TIMEOUT))?;
+            stream.set_write_timeout(Some(REMOTE_SIGNER_TIMEOUT))?;
+            let authorization = token
+                .as_ref()
+                .map(|token| format!("Authorization: Bearer {}\r\n", token))
+                .unwrap_or_default();
+            write!(
+                stream,
+                "POST {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                path,
+                host,
+                authorization,
+                body.len(),
+                body
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            let (head, body) = response
+                .split_once("\r\n\r\n")
+                .ok_or_else(|| SignerError::InvalidResponse(response.clone()))?;
+            if !head.starts_with("HTTP/1.1 200") {
+                return Err(SignerError::InvalidResponse(
+                    head.lines().next().unwrap_or_default().to_string(),
+                ));
+            }
+            body.to_string()
+        }
+    };
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// Names of the calls of the runtime, e.g. `VaultRegistry.deposit_collateral`, by their
+/// pallet and call index, which are the first two bytes of a signing payload.
+#[derive(Debug, Clone)]
+pub struct CallNames(HashMap<[u8; 2], String>);
+
+impl CallNames {
+    /// Reads the call names from the metadata the client was compiled with.
+    pub fn from_metadata() -> Result<Self, SignerError> {
+        let metadata = match RuntimeMetadataPrefixed::decode(&mut &METADATA[..])?.1 {
+            RuntimeMetadata::V14(metadata) => metadata,
+            _ => return Err(SignerError::UnsupportedMetadata),
+        };
+
+        let mut names = HashMap::new();
+        for pallet in metadata.pallets.iter() {
+            let calls = match pallet
+                .calls
+                .as_ref()
+                .and_then(|calls| metadata.types.resolve(calls.ty.id()))
+            {
+                Some(calls) => calls,
+                None => continue,
+            };
+            if let TypeDef::Variant(calls) = calls.type_def() {
+                for call in calls.variants() {
+                    names.insert([pallet.index, call.index()], format!("{}.{}", pallet.name, call.name()));
+                }
+            }
+        }
+        Ok(Self(names))
+    }
+
+    /// Whether `payload` is the hash of a payload that is too long to be signed directly.
+    pub fn is_hashed(payload: &[u8]) -> bool {
+        payload.len() <= HASHED_PAYLOAD_LENGTH
+    }
+
+    /// Returns the name of the call in `payload`, or `None` if the call is unknown or the
+    /// payload is hashed, in which case the call can not be determined.
+    pub fn of_payload(&self, payload: &[u8]) -> Option<&str> {
+        if Self::is_hashed(payload) {
+            return None;
+        }
+        self.of_call(payload)
+    }
+
+    /// Returns the name of the encoded `call`, or `None` if it is unknown.
+    pub fn of_call(&self, call: &[u8]) -> Option<&str> {
+        match call {
+            [pallet, call, ..] => self.0.get(&[*pallet, *call]).map(String::as_str),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_names() {
+        let names = CallNames::from_metadata().unwrap();
+        let (&index, _) = names
+            .0
+            .iter()
+            .find(|(_, name)| *name == "VaultRegistry.deposit_collateral")
+            .unwrap();
+
+        let mut payload = index.to_vec();
+        payload.extend_from_slice(&[0; 64]);
+        assert_eq!(names.of_payload(&payload), Some("VaultRegistry.deposit_collateral"));
+        assert_eq!(names.of_payload(&[index[0], index[1], 0, 0]), None);
+    }
+
+    #[test]
+    fn test_signer_messages() {
+        assert_eq!(
+            serde_json::to_string(&SignerRequest::Sign {
+                payload: "0a0b".to_string(),
+                unhashed: None,
+            })
+            .unwrap(),
+            r#"{"method":"sign","payload":"0a0b"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<SignerRequest>(
+                r#"{"method":"sign","payload":"0a0b","unhashed":{"call":"0102","extra":"03","additional":"0x04"}}"#
+            )
+            .unwrap(),
+            SignerRequest::Sign {
+                payload: "0a0b".to_string(),
+                unhashed: Some(UnhashedPayload {
+                    call: "0102".to_string(),
+                    extra: "03".to_string(),
+                    additional: "0x04".to_string(),
+                }),
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<SignerResponse>(r#"{"rejected":"call not allowed"}"#).unwrap(),
+            SignerResponse::Rejected("call not allowed".to_string())
+        );
+    }
+}
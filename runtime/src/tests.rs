@@ -11,7 +11,7 @@ use crate::{integration::*, FeedValuesEvent, OracleKey, RuntimeCurrencyInfo, Vau
 use module_bitcoin::{formatter::TryFormattable, types::BlockBuilder};
 pub use primitives::CurrencyId::ForeignAsset;
 use sp_keyring::AccountKeyring;
-use std::{convert::TryInto, time::Duration};
+use std::{convert::TryInto, sync::Arc, time::Duration};
 
 fn dummy_public_key() -> BtcPublicKey {
     BtcPublicKey {
@@ -180,3 +180,45 @@ async fn test_currency_id_parsing() {
     );
     assert_eq!(ForeignAsset(2).decimals().unwrap(), 10);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mock_bitcoin_core_conformance() {
+    let (client, _tmp_dir) = default_provider_client(AccountKeyring::Alice).await;
+    let parachain_rpc = setup_provider(client.clone(), AccountKeyring::Alice).await;
+    let btc_rpc = MockBitcoinCore::new(parachain_rpc).await;
+    let backend: Arc<dyn bitcoin::BitcoinCoreApi + Send + Sync> = Arc::new(btc_rpc.clone());
+
+    bitcoin::conformance::check_all(&backend, &btc_rpc).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mock_bitcoin_core_bumps_fee_of_mempool_transactions() {
+    use bitcoin::{BitcoinCoreApi, SatPerVbyte, TransactionExt};
+
+    let (client, _tmp_dir) = default_provider_client(AccountKeyring::Alice).await;
+    let parachain_rpc = setup_provider(client.clone(), AccountKeyring::Alice).await;
+    let btc_rpc = MockBitcoinCore::new(parachain_rpc).await;
+
+    let address = BtcAddress::P2PKH(H160::from([1; 20]))
+        .to_address(bitcoin::Network::Regtest)
+        .unwrap();
+    let request_id = crate::H256::from_low_u64_be(1);
+    let transaction = btc_rpc
+        .create_transaction(address.clone(), 10_000, SatPerVbyte(2), Some(request_id))
+        .await
+        .unwrap();
+    let txid = transaction.txid();
+    btc_rpc.send_to_mempool(transaction).await;
+    assert_eq!(btc_rpc.fee_rate(txid).await.unwrap(), SatPerVbyte(2));
+
+    let bumped_txid = btc_rpc.bump_fee(&txid, address, SatPerVbyte(4)).await.unwrap();
+    assert_ne!(bumped_txid, txid);
+    assert!(btc_rpc.is_in_mempool(bumped_txid).await.unwrap());
+    assert!(!btc_rpc.is_in_mempool(txid).await.unwrap());
+    assert_eq!(btc_rpc.fee_rate(bumped_txid).await.unwrap(), SatPerVbyte(4));
+
+    btc_rpc.flush_mempool().await;
+    let metadata = btc_rpc.wait_for_transaction_metadata(bumped_txid, 1).await.unwrap();
+    let transaction: bitcoin::Transaction = bitcoin::deserialize(&metadata.raw_tx).unwrap();
+    assert_eq!(transaction.get_op_return(), Some(request_id));
+}
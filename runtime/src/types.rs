@@ -20,8 +20,6 @@ pub type H160 = subxt::sp_core::H160;
 pub type H256 = subxt::sp_core::H256;
 pub type U256 = subxt::sp_core::U256;
 
-pub type InterBtcSigner = subxt::PairSigner<InterBtcRuntime, KeyPair>;
-
 pub type BtcAddress = module_btc_relay::BtcAddress;
 
 pub type FixedU128 = sp_arithmetic::FixedU128;
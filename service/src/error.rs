@@ -1,4 +1,4 @@
-use bitcoin::Error as BitcoinError;
+use bitcoin::{Error as BitcoinError, KeystoreError};
 use hyper::{http::Error as HyperHttpError, Error as HyperError};
 use runtime::Error as RuntimeError;
 use serde_json::Error as SerdeJsonError;
@@ -34,6 +34,8 @@ pub enum Error {
     RuntimeError(#[from] RuntimeError),
     #[error("BitcoinError: {0}")]
     BitcoinError(#[from] BitcoinError),
+    #[error("KeystoreError: {0}")]
+    KeystoreError(#[from] KeystoreError),
     #[error("TokioError: {0}")]
     TokioError(#[from] TokioJoinError),
     #[error("System I/O error: {0}")]
@@ -43,3 +45,20 @@ pub enum Error {
     #[error("Other: {0}")]
     Other(String),
 }
+
+impl Error {
+    /// Whether the connection to the parachain or to bitcoin core is lost, which can only
+    /// be recovered from by restarting the whole service.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Error::ClientShutdown => true,
+            Error::RuntimeError(err) => {
+                err.is_rpc_disconnect_error()
+                    || err.is_parachain_shutdown_error()
+                    || matches!(err, RuntimeError::ChannelClosed)
+            }
+            Error::BitcoinError(err) => err.is_transport_error(),
+            _ => false,
+        }
+    }
+}
@@ -9,10 +9,12 @@ use std::{marker::PhantomData, sync::Arc};
 
 mod cli;
 mod error;
+mod supervisor;
 mod trace;
 
 pub use cli::{LoggingFormat, MonitoringConfig, RestartPolicy, ServiceConfig};
 pub use error::Error;
+pub use supervisor::{supervise, HealthRegistry, RestartBudget, TaskHealth, TaskStatus};
 pub use trace::init_subscriber;
 pub use warp;
 
@@ -102,18 +104,7 @@ impl<Config: Clone + Send + 'static, S: Service<Config>, F: Fn()> ConnectionMana
             let config_copy = self.bitcoin_config.clone();
             let network_copy = bitcoin_core.network();
             let constructor = move |vault_id: VaultId| {
-                let collateral_currency: CurrencyId = vault_id.collateral_currency();
-                let wrapped_currency: CurrencyId = vault_id.wrapped_currency();
-                let wallet_name = format!(
-                    "{}-{}-{}",
-                    prefix,
-                    collateral_currency
-                        .symbol()
-                        .map_err(|_| BitcoinError::FailedToConstructWalletName)?,
-                    wrapped_currency
-                        .symbol()
-                        .map_err(|_| BitcoinError::FailedToConstructWalletName)?,
-                );
+                let wallet_name = vault_wallet_name(&prefix, &vault_id)?;
                 config_copy.new_client_with_network(Some(wallet_name), network_copy)
             };
 
@@ -142,6 +133,22 @@ impl<Config: Clone + Send + 'static, S: Service<Config>, F: Fn()> ConnectionMana
     }
 }
 
+/// Name of the bitcoin wallet holding the funds of the given vault.
+pub fn vault_wallet_name(prefix: &str, vault_id: &VaultId) -> Result<String, BitcoinError> {
+    let collateral_currency: CurrencyId = vault_id.collateral_currency();
+    let wrapped_currency: CurrencyId = vault_id.wrapped_currency();
+    Ok(format!(
+        "{}-{}-{}",
+        prefix,
+        collateral_currency
+            .symbol()
+            .map_err(|_| BitcoinError::FailedToConstructWalletName)?,
+        wrapped_currency
+            .symbol()
+            .map_err(|_| BitcoinError::FailedToConstructWalletName)?,
+    ))
+}
+
 pub async fn wait_or_shutdown<F>(shutdown_tx: ShutdownSender, future2: F) -> Result<(), Error>
 where
     F: Future<Output = Result<(), Error>>,
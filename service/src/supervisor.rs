@@ -0,0 +1,227 @@
+//! Supervision of the long-running tasks of a service. A failed task is restarted on its
+//! own so that the other tasks, e.g. in-flight payments, are not interrupted.
+
+use crate::Error;
+use futures::Future;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+
+/// How often a task may be restarted before its failure is escalated to the service.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartBudget {
+    /// Maximum number of restarts within `period`.
+    pub max_restarts: usize,
+    pub period: Duration,
+    /// Delay before the first restart, doubled for every further restart within `period`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartBudget {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            period: Duration::from_secs(3600),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartBudget {
+    fn backoff(&self, recent_restarts: usize) -> Duration {
+        let exponent = recent_restarts.saturating_sub(1).min(31) as u32;
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    /// Failed and waiting for the backoff to restart.
+    Restarting,
+    /// Finished without error.
+    Completed,
+    /// Failed and not restarted, which shuts down the service.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    pub status: TaskStatus,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+}
+
+impl Default for TaskHealth {
+    fn default() -> Self {
+        Self {
+            status: TaskStatus::Running,
+            restarts: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Health of all supervised tasks, by name.
+#[derive(Debug, Clone, Default)]
+pub struct HealthRegistry(Arc<RwLock<HashMap<String, TaskHealth>>>);
+
+impl HealthRegistry {
+    pub fn snapshot(&self) -> HashMap<String, TaskHealth> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskHealth)) {
+        let mut tasks = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        f(tasks.entry(name.to_string()).or_default());
+    }
+}
+
+/// Runs the task created by `factory` until it completes, and recreates it whenever it fails.
+/// Connection errors can not be recovered from by restarting a single task, so they are
+/// returned right away, as is the error of a task that exhausted its restart budget.
+pub async fn supervise<F, Fut>(
+    name: String,
+    budget: RestartBudget,
+    health: HealthRegistry,
+    mut factory: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut restarts = VecDeque::new();
+    loop {
+        health.update(&name, |task| task.status = TaskStatus::Running);
+        let err = match factory().await {
+            Ok(()) => {
+                health.update(&name, |task| task.status = TaskStatus::Completed);
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+        health.update(&name, |task| task.last_error = Some(err.to_string()));
+
+        if err.is_connection_error() {
+            tracing::warn!("Task {} lost its connection: {}", name, err);
+            health.update(&name, |task| task.status = TaskStatus::Failed);
+            return Err(err);
+        }
+
+        let now = Instant::now();
+        while restarts
+            .front()
+            .map_or(false, |restart| now.duration_since(*restart) > budget.period)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= budget.max_restarts {
+            tracing::error!(
+                "Task {} exhausted its budget of {} restarts per {:?}: {}",
+                name,
+                budget.max_restarts,
+                budget.period,
+                err
+            );
+            health.update(&name, |task| task.status = TaskStatus::Failed);
+            return Err(err);
+        }
+        restarts.push_back(now);
+
+        let backoff = budget.backoff(restarts.len());
+        tracing::warn!("Task {} failed, restarting in {:?}: {}", name, backoff, err);
+        health.update(&name, |task| {
+            task.status = TaskStatus::Restarting;
+            task.restarts += 1;
+        });
+        sleep(backoff).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::Error as RuntimeError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn budget(max_restarts: usize) -> RestartBudget {
+        RestartBudget {
+            max_restarts,
+            period: Duration::from_secs(3600),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let budget = budget(5);
+        assert_eq!(budget.backoff(1), Duration::from_millis(1));
+        assert_eq!(budget.backoff(2), Duration::from_millis(2));
+        assert_eq!(budget.backoff(3), Duration::from_millis(4));
+        assert_eq!(budget.backoff(10), Duration::from_millis(4));
+        assert_eq!(budget.backoff(usize::MAX), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_failed_task() {
+        let health = HealthRegistry::default();
+        let attempts = AtomicUsize::new(0);
+        let result = supervise("task".to_string(), budget(5), health.clone(), || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(Error::Other("failure".to_string()))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let task = &health.snapshot()["task"];
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(task.restarts, 2);
+        assert_eq!(task.last_error.as_deref(), Some("Other: failure"));
+    }
+
+    #[tokio::test]
+    async fn test_supervise_escalates_when_budget_exhausted() {
+        let health = HealthRegistry::default();
+        let attempts = AtomicUsize::new(0);
+        let result = supervise("task".to_string(), budget(2), health.clone(), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::Other("failure".to_string()))
+        })
+        .await;
+
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(health.snapshot()["task"].status, TaskStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_supervise_escalates_connection_errors() {
+        let health = HealthRegistry::default();
+        let attempts = AtomicUsize::new(0);
+        let result = supervise("task".to_string(), budget(5), health.clone(), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::RuntimeError(RuntimeError::ChannelClosed))
+        })
+        .await;
+
+        assert!(matches!(result, Err(Error::RuntimeError(RuntimeError::ChannelClosed))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        let task = &health.snapshot()["task"];
+        assert_eq!(task.status, TaskStatus::Failed);
+        assert_eq!(task.restarts, 0);
+    }
+}
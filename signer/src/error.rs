@@ -0,0 +1,15 @@
+use runtime::{Error as RuntimeError, SignerError};
+use std::io::Error as IoError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("RuntimeError: {0}")]
+    RuntimeError(#[from] RuntimeError),
+    #[error("SignerError: {0}")]
+    SignerError(#[from] SignerError),
+    #[error("IoError: {0}")]
+    IoError(#[from] IoError),
+    #[error("Failed to bind the http server: {0}")]
+    BindError(String),
+}
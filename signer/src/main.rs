@@ -0,0 +1,339 @@
+mod error;
+
+use clap::Parser;
+use error::Error;
+use git_version::git_version;
+use runtime::{CallNames, KeyPair, SignerRequest, SignerResponse, Ss58Codec, UnhashedPayload, SS58_PREFIX};
+use service::warp::{self, http::StatusCode, Filter, Reply};
+use sp_core::{crypto::Pair, hashing::blake2_256};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::SocketAddr,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixListener,
+};
+
+const VERSION: &str = git_version!(args = ["--tags"]);
+const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const NAME: &str = env!("CARGO_PKG_NAME");
+const ABOUT: &str = env!("CARGO_PKG_DESCRIPTION");
+
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+#[derive(Parser)]
+#[clap(name = NAME, version = VERSION, author = AUTHORS, about = ABOUT)]
+struct Opts {
+    /// Config file.
+    #[clap(flatten)]
+    config: runtime::cli::ConfigFileOpts,
+
+    /// Keyring / keyfile options.
+    #[clap(flatten)]
+    account_info: runtime::cli::ProviderUserOpts,
+
+    /// Settings specific to the signer.
+    #[clap(flatten)]
+    signer: SignerConfig,
+}
+
+#[derive(Parser, Clone)]
+struct SignerConfig {
+    /// Path of the unix socket to listen on, which is only accessible to the current user.
+    #[clap(long, required_unless_present = "http_addr")]
+    unix_socket: Option<PathBuf>,
+
+    /// Address to listen on for http requests, e.g. `127.0.0.1:3040`. Prefer a loopback
+    /// address, since anyone who knows the token can have the allowed calls signed.
+    #[clap(long, requires = "http_token")]
+    http_addr: Option<SocketAddr>,
+
+    /// Bearer token required by http requests.
+    #[clap(long, env = "SIGNER_HTTP_TOKEN", hide_env_values = true)]
+    http_token: Option<String>,
+
+    /// Call that may be signed, as `Pallet.call`, e.g. `VaultRegistry.deposit_collateral`.
+    /// Can be given multiple times.
+    #[clap(long)]
+    allow: Vec<String>,
+
+    /// Sign payloads longer than 256 bytes that are passed only as their hash, without their
+    /// unhashed parts, so that the call can not be checked against the allowed calls.
+    #[clap(long)]
+    allow_hashed_payloads: bool,
+}
+
+/// Signs the payloads of the allowed calls.
+struct Signer {
+    pair: KeyPair,
+    call_names: CallNames,
+    allowed: HashSet<String>,
+    allow_hashed_payloads: bool,
+}
+
+impl Signer {
+    fn handle(&self, request: SignerRequest) -> SignerResponse {
+        match request {
+            SignerRequest::AccountId => {
+                SignerResponse::AccountId(self.pair.public().to_ss58check_with_version(SS58_PREFIX.into()))
+            }
+            SignerRequest::Sign { payload, unhashed } => match self.check(&payload, unhashed.as_ref()) {
+                Ok(payload) => SignerResponse::Signature(hex::encode(self.pair.sign(&payload))),
+                Err(reason) => {
+                    log::warn!("Rejected payload: {}", reason);
+                    SignerResponse::Rejected(reason)
+                }
+            },
+        }
+    }
+
+    fn handle_json(&self, request: &str) -> String {
+        let response = match serde_json::from_str(request) {
+            Ok(request) => self.handle(request),
+            Err(err) => SignerResponse::Rejected(format!("Invalid request: {}", err)),
+        };
+        serde_json::to_string(&response).unwrap_or_default()
+    }
+
+    /// Decodes the payload and checks that its call is allowed. The call of a hashed payload
+    /// is taken from its unhashed parts, after checking that they hash to the payload.
+    fn check(&self, payload: &str, unhashed: Option<&UnhashedPayload>) -> Result<Vec<u8>, String> {
+        let payload =
+            hex::decode(payload.trim_start_matches("0x")).map_err(|err| format!("Invalid payload: {}", err))?;
+        if !CallNames::is_hashed(&payload) {
+            self.check_call(&payload)?;
+            return Ok(payload);
+        }
+        match unhashed {
+            Some(unhashed) => {
+                let (call, unhashed) = unhashed
+                    .decode()
+                    .map_err(|err| format!("Invalid unhashed payload: {}", err))?;
+                if blake2_256(&unhashed)[..] != payload[..] {
+                    return Err("Unhashed payload does not match the payload".to_string());
+                }
+                self.check_call(&call)?;
+                Ok(payload)
+            }
+            None if self.allow_hashed_payloads => {
+                log::info!("Signing hashed payload");
+                Ok(payload)
+            }
+            None => Err("Hashed payloads are not allowed".to_string()),
+        }
+    }
+
+    fn check_call(&self, call: &[u8]) -> Result<(), String> {
+        match self.call_names.of_call(call) {
+            Some(call) if self.allowed.contains(call) => {
+                log::info!("Signing {}", call);
+                Ok(())
+            }
+            Some(call) => Err(format!("{} is not allowed", call)),
+            None => Err("Unknown call".to_string()),
+        }
+    }
+}
+
+async fn serve_unix(path: &Path, signer: Arc<Signer>) -> Result<(), Error> {
+    // remove the socket of a previous run, which can not be bound again
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    log::info!("Listening on unix:{}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let signer = signer.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let response = signer.handle_json(&line);
+                if writer.write_all(format!("{}\n", response).as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+fn is_authorized(token: &str, authorization: Option<&str>) -> bool {
+    match authorization.and_then(|header| header.strip_prefix("Bearer ")) {
+        Some(bearer) => bool::from(token.as_bytes().ct_eq(bearer.as_bytes())),
+        None => false,
+    }
+}
+
+async fn serve_http(addr: SocketAddr, signer: Arc<Signer>, token: String) -> Result<(), Error> {
+    let route = warp::post()
+        .and(warp::body::content_length_limit(MAX_REQUEST_SIZE))
+        .and(warp::body::bytes())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |body: warp::hyper::body::Bytes, authorization: Option<String>| {
+            let response = if is_authorized(&token, authorization.as_deref()) {
+                let response = signer.handle_json(&String::from_utf8_lossy(&body));
+                warp::reply::with_header(response, "content-type", "application/json").into_response()
+            } else {
+                warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED).into_response()
+            };
+            async move { Ok::<_, Infallible>(response) }
+        });
+
+    let (addr, server) = warp::serve(route)
+        .try_bind_ephemeral(addr)
+        .map_err(|err| Error::BindError(err.to_string()))?;
+    log::info!("Listening on http://{}", addr);
+    server.await;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, log::LevelFilter::Info.as_str()),
+    );
+    let opts: Opts = runtime::cli::parse_with_config_file();
+
+    let (pair, _) = opts.account_info.get_key_pair()?;
+    let signer = Arc::new(Signer {
+        pair,
+        call_names: CallNames::from_metadata()?,
+        allowed: opts.signer.allow.into_iter().collect(),
+        allow_hashed_payloads: opts.signer.allow_hashed_payloads,
+    });
+    log::info!(
+        "Signing for {}",
+        signer.pair.public().to_ss58check_with_version(SS58_PREFIX.into())
+    );
+
+    // the token is required by clap if the address is set
+    let http = opts.signer.http_addr.zip(opts.signer.http_token);
+    match (opts.signer.unix_socket, http) {
+        (Some(path), Some((addr, token))) => {
+            let (unix, http) =
+                futures::future::join(serve_unix(&path, signer.clone()), serve_http(addr, signer, token)).await;
+            unix.and(http)
+        }
+        (Some(path), None) => serve_unix(&path, signer).await,
+        (None, Some((addr, token))) => serve_http(addr, signer, token).await,
+        // should never occur, due to clap constraints
+        (None, None) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::{InterBtcSigner, RemoteSigner, Signer as _};
+    use sp_keyring::AccountKeyring;
+
+    fn signer(allowed: &[&str]) -> Arc<Signer> {
+        Arc::new(Signer {
+            pair: AccountKeyring::Alice.pair(),
+            call_names: CallNames::from_metadata().unwrap(),
+            allowed: allowed.iter().map(ToString::to_string).collect(),
+            allow_hashed_payloads: false,
+        })
+    }
+
+    fn payload(signer: &Signer, call: &str) -> Vec<u8> {
+        // pallet and call index, followed by the rest of a payload
+        let index = (0..=u8::MAX)
+            .flat_map(|pallet| (0..=u8::MAX).map(move |call| [pallet, call]))
+            .find(|index| signer.call_names.of_payload(&[&index[..], &[0u8; 64][..]].concat()) == Some(call))
+            .unwrap();
+        [&index[..], &[0u8; 64][..]].concat()
+    }
+
+    #[test]
+    fn test_only_allowed_calls_are_signed() {
+        let signer = signer(&["VaultRegistry.deposit_collateral"]);
+
+        let allowed = payload(&signer, "VaultRegistry.deposit_collateral");
+        assert!(signer.check(&hex::encode(&allowed), None).is_ok());
+
+        let disallowed = payload(&signer, "VaultRegistry.withdraw_collateral");
+        assert_eq!(
+            signer.check(&hex::encode(&disallowed), None),
+            Err("VaultRegistry.withdraw_collateral is not allowed".to_string())
+        );
+        assert!(signer.check(&hex::encode([0u8; 32]), None).is_err());
+    }
+
+    #[test]
+    fn test_hashed_payloads_are_checked_against_their_call() {
+        let signer = signer(&["Redeem.execute_redeem"]);
+        let unhashed = |call: &[u8]| UnhashedPayload {
+            call: hex::encode(call),
+            extra: hex::encode([1u8; 8]),
+            additional: hex::encode([2u8; 300]),
+        };
+        let hash = |unhashed: &UnhashedPayload| hex::encode(blake2_256(&unhashed.decode().unwrap().1));
+
+        let allowed = unhashed(&payload(&signer, "Redeem.execute_redeem"));
+        assert!(signer.check(&hash(&allowed), Some(&allowed)).is_ok());
+        assert_eq!(
+            signer.check(&hash(&allowed), None),
+            Err("Hashed payloads are not allowed".to_string())
+        );
+
+        let disallowed = unhashed(&payload(&signer, "Redeem.cancel_redeem"));
+        assert_eq!(
+            signer.check(&hash(&disallowed), Some(&disallowed)),
+            Err("Redeem.cancel_redeem is not allowed".to_string())
+        );
+        // the unhashed parts of another payload can not be passed off as those of the payload
+        assert_eq!(
+            signer.check(&hash(&disallowed), Some(&allowed)),
+            Err("Unhashed payload does not match the payload".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized("secret", Some("Bearer secret")));
+        assert!(!is_authorized("secret", Some("Bearer wrong")));
+        assert!(!is_authorized("secret", Some("secret")));
+        assert!(!is_authorized("secret", None));
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_over_unix_socket() {
+        let dir = tempdir::TempDir::new("signer").unwrap();
+        let path = dir.path().join("signer.sock");
+        let signer = signer(&["VaultRegistry.deposit_collateral"]);
+        let allowed = payload(&signer, "VaultRegistry.deposit_collateral");
+        let disallowed = payload(&signer, "VaultRegistry.withdraw_collateral");
+
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move { serve_unix(&path, signer).await }
+        });
+        while !path.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let url = format!("unix:{}", path.display());
+        tokio::task::spawn_blocking(move || {
+            let remote = RemoteSigner::connect(&url, None).unwrap();
+            assert_eq!(remote.account_id(), &AccountKeyring::Alice.to_account_id());
+            assert!(remote.sign(&allowed, None).is_ok());
+            assert!(remote.sign(&disallowed, None).is_err());
+
+            let signer = InterBtcSigner::remote(remote);
+            assert_eq!(signer.address(), AccountKeyring::Alice.to_account_id());
+        })
+        .await
+        .unwrap();
+        server.abort();
+    }
+}
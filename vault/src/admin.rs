@@ -0,0 +1,327 @@
+//! Administration commands of the vault binary, e.g. `vault deposit-collateral`, which submit
+//! a single extrinsic or report the state of the vaults of the account. All results can be
+//! printed either human-readable or as JSON.
+
+use crate::{error::Error, system::is_vault_registered};
+use bitcoin::Error as BitcoinError;
+use runtime::{
+    CurrencyId, Error as RuntimeError, FixedU128, InterBtcParachain, PrettyPrint, ReplacePallet, RuntimeCurrencyInfo,
+    Ss58Codec, UtilFuncs, VaultId, VaultRegistryPallet, VaultStatus, SS58_PREFIX,
+};
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+#[derive(Serialize)]
+pub struct VaultInfo {
+    pub vault_id: String,
+    pub collateral_currency: String,
+    pub wrapped_currency: String,
+    pub status: &'static str,
+    /// Not available once the vault is liquidated.
+    pub balances: Option<VaultBalances>,
+}
+
+#[derive(Serialize)]
+pub struct VaultBalances {
+    pub issued_tokens: String,
+    pub to_be_issued_tokens: String,
+    pub to_be_redeemed_tokens: String,
+    pub to_be_replaced_tokens: String,
+    pub collateral: String,
+    pub collateralization: Option<f64>,
+}
+
+impl Display for VaultInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.vault_id, self.status)?;
+        if let Some(balances) = &self.balances {
+            writeln!(
+                f,
+                "  collateral:            {} {}",
+                balances.collateral, self.collateral_currency
+            )?;
+            for (name, amount) in [
+                ("issued:               ", &balances.issued_tokens),
+                ("to be issued:         ", &balances.to_be_issued_tokens),
+                ("to be redeemed:       ", &balances.to_be_redeemed_tokens),
+                ("to be replaced:       ", &balances.to_be_replaced_tokens),
+            ] {
+                writeln!(f, "  {} {} {}", name, amount, self.wrapped_currency)?;
+            }
+            match balances.collateralization {
+                Some(collateralization) => writeln!(f, "  collateralization:     {:.2}%", collateralization * 100.0)?,
+                None => writeln!(f, "  collateralization:     -")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The registration and vaults of the account.
+#[derive(Serialize)]
+pub struct Status {
+    pub account_id: String,
+    /// Hex encoded bitcoin public key, if registered.
+    pub public_key: Option<String>,
+    pub vaults: Vec<VaultInfo>,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Account:    {}", self.account_id)?;
+        writeln!(
+            f,
+            "Public key: {}",
+            self.public_key.as_deref().unwrap_or("not registered")
+        )?;
+        if self.vaults.is_empty() {
+            writeln!(f, "No registered vaults")?;
+        }
+        for vault in &self.vaults {
+            write!(f, "\n{}", vault)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of a submitted extrinsic, along with the state of the vault afterwards.
+#[derive(Serialize)]
+pub struct Submitted {
+    pub call: &'static str,
+    /// Amount in the smallest unit of the currency, followed by its symbol.
+    pub amount: String,
+    pub vault: VaultInfo,
+}
+
+impl Display for Submitted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Submitted {} of {}\n", self.call, self.amount)?;
+        write!(f, "{}", self.vault)
+    }
+}
+
+/// Formats `output` as pretty printed JSON or as human-readable text.
+pub fn format_output<T: Serialize + Display>(output: &T, json: bool) -> Result<String, Error> {
+    if json {
+        Ok(serde_json::to_string_pretty(output)?)
+    } else {
+        Ok(output.to_string().trim_end().to_string())
+    }
+}
+
+pub(crate) async fn get_vault_info<P: VaultRegistryPallet>(
+    parachain_rpc: &P,
+    vault_id: &VaultId,
+) -> Result<VaultInfo, Error> {
+    let (status, balances) = match parachain_rpc.get_vault(vault_id).await {
+        Ok(vault) => {
+            let collateral = parachain_rpc.get_vault_total_collateral(vault_id.clone()).await?;
+            // fails if the vault has not issued any tokens
+            let collateralization = parachain_rpc
+                .get_collateralization_from_vault(vault_id.clone(), false)
+                .await
+                .ok();
+            let status = match vault.status {
+                VaultStatus::Active(true) => "active",
+                VaultStatus::Active(false) => "not_accepting_issues",
+                VaultStatus::Liquidated => "liquidated",
+            };
+            let balances = VaultBalances {
+                issued_tokens: vault.issued_tokens.to_string(),
+                to_be_issued_tokens: vault.to_be_issued_tokens.to_string(),
+                to_be_redeemed_tokens: vault.to_be_redeemed_tokens.to_string(),
+                to_be_replaced_tokens: vault.to_be_replaced_tokens.to_string(),
+                collateral: collateral.to_string(),
+                collateralization: collateralization.map(|x| FixedU128::from_inner(x).to_float()),
+            };
+            (status, Some(balances))
+        }
+        Err(RuntimeError::VaultLiquidated) => ("liquidated", None),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(VaultInfo {
+        vault_id: vault_id.pretty_print(),
+        collateral_currency: vault_id.collateral_currency().symbol()?,
+        wrapped_currency: vault_id.wrapped_currency().symbol()?,
+        status,
+        balances,
+    })
+}
+
+/// The vault of the account with the given collateral currency, e.g. `KSM`.
+fn vault_id(parachain_rpc: &InterBtcParachain, collateral_currency: &str) -> Result<VaultId, Error> {
+    Ok(VaultId::new(
+        parachain_rpc.get_account_id().clone(),
+        CurrencyId::try_from_symbol(collateral_currency.to_string())?,
+        parachain_rpc.wrapped_currency_id,
+    ))
+}
+
+pub async fn get_status(parachain_rpc: &InterBtcParachain) -> Result<Status, Error> {
+    let account_id = parachain_rpc.get_account_id();
+    let mut vaults = Vec::new();
+    for vault_id in parachain_rpc.get_vaults_by_account_id(account_id).await? {
+        vaults.push(get_vault_info(parachain_rpc, &vault_id).await?);
+    }
+    Ok(Status {
+        account_id: account_id.to_ss58check_with_version(SS58_PREFIX.into()),
+        public_key: parachain_rpc.get_public_key().await?.map(|key| hex::encode(key.0)),
+        vaults,
+    })
+}
+
+async fn submitted(
+    parachain_rpc: &InterBtcParachain,
+    call: &'static str,
+    vault_id: &VaultId,
+    amount: u128,
+    currency: CurrencyId,
+) -> Result<Submitted, Error> {
+    Ok(Submitted {
+        call,
+        amount: format!("{} {}", amount, currency.symbol()?),
+        vault: get_vault_info(parachain_rpc, vault_id).await?,
+    })
+}
+
+pub async fn deposit_collateral(
+    parachain_rpc: &InterBtcParachain,
+    collateral_currency: &str,
+    amount: u128,
+) -> Result<Submitted, Error> {
+    let vault_id = vault_id(parachain_rpc, collateral_currency)?;
+    crate::deposit_collateral(parachain_rpc, &vault_id, amount).await?;
+    submitted(
+        parachain_rpc,
+        "deposit_collateral",
+        &vault_id,
+        amount,
+        vault_id.collateral_currency(),
+    )
+    .await
+}
+
+pub async fn withdraw_collateral(
+    parachain_rpc: &InterBtcParachain,
+    collateral_currency: &str,
+    amount: u128,
+) -> Result<Submitted, Error> {
+    let vault_id = vault_id(parachain_rpc, collateral_currency)?;
+    parachain_rpc.withdraw_collateral(&vault_id, amount).await?;
+    submitted(
+        parachain_rpc,
+        "withdraw_collateral",
+        &vault_id,
+        amount,
+        vault_id.collateral_currency(),
+    )
+    .await
+}
+
+/// Registers a vault with another collateral currency. The bitcoin public key has to be
+/// registered already, which the vault does the first time it runs.
+pub async fn register(
+    parachain_rpc: &InterBtcParachain,
+    collateral_currency: &str,
+    collateral: u128,
+) -> Result<Submitted, Error> {
+    let vault_id = vault_id(parachain_rpc, collateral_currency)?;
+    if parachain_rpc.get_public_key().await?.is_none() {
+        return Err(BitcoinError::MissingPublicKey.into());
+    }
+    match is_vault_registered(parachain_rpc, &vault_id).await {
+        Err(Error::RuntimeError(RuntimeError::VaultLiquidated)) | Ok(true) => {
+            return Err(Error::VaultAlreadyRegistered(vault_id.pretty_print()))
+        }
+        Ok(false) => {}
+        Err(err) => return Err(err),
+    }
+    parachain_rpc.register_vault(&vault_id, collateral).await?;
+    submitted(
+        parachain_rpc,
+        "register_vault",
+        &vault_id,
+        collateral,
+        vault_id.collateral_currency(),
+    )
+    .await
+}
+
+pub async fn request_replace(
+    parachain_rpc: &InterBtcParachain,
+    collateral_currency: &str,
+    amount: u128,
+) -> Result<Submitted, Error> {
+    let vault_id = vault_id(parachain_rpc, collateral_currency)?;
+    parachain_rpc.request_replace(&vault_id, amount).await?;
+    submitted(
+        parachain_rpc,
+        "request_replace",
+        &vault_id,
+        amount,
+        vault_id.wrapped_currency(),
+    )
+    .await
+}
+
+pub async fn withdraw_replace(
+    parachain_rpc: &InterBtcParachain,
+    collateral_currency: &str,
+    amount: u128,
+) -> Result<Submitted, Error> {
+    let vault_id = vault_id(parachain_rpc, collateral_currency)?;
+    parachain_rpc.withdraw_replace(&vault_id, amount).await?;
+    submitted(
+        parachain_rpc,
+        "withdraw_replace",
+        &vault_id,
+        amount,
+        vault_id.wrapped_currency(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_info(balances: Option<VaultBalances>) -> VaultInfo {
+        VaultInfo {
+            vault_id: "vault[KSM->KBTC]".to_string(),
+            collateral_currency: "KSM".to_string(),
+            wrapped_currency: "KBTC".to_string(),
+            status: if balances.is_some() { "active" } else { "liquidated" },
+            balances,
+        }
+    }
+
+    #[test]
+    fn test_format_status() {
+        let status = Status {
+            account_id: "account".to_string(),
+            public_key: None,
+            vaults: vec![
+                vault_info(Some(VaultBalances {
+                    issued_tokens: "100".to_string(),
+                    to_be_issued_tokens: "0".to_string(),
+                    to_be_redeemed_tokens: "0".to_string(),
+                    to_be_replaced_tokens: "0".to_string(),
+                    collateral: "5000".to_string(),
+                    collateralization: Some(2.5),
+                })),
+                vault_info(None),
+            ],
+        };
+
+        let text = format_output(&status, false).unwrap();
+        assert!(text.contains("Public key: not registered"));
+        assert!(text.contains("collateral:            5000 KSM"));
+        assert!(text.contains("issued:                100 KBTC"));
+        assert!(text.contains("collateralization:     250.00%"));
+        assert!(text.contains("vault[KSM->KBTC] (liquidated)"));
+
+        let json: serde_json::Value = serde_json::from_str(&format_output(&status, true).unwrap()).unwrap();
+        assert_eq!(json["vaults"][0]["balances"]["collateral"], "5000");
+        assert_eq!(json["vaults"][1]["balances"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,762 @@
+//! Local management API of the vault, served as JSON-RPC over HTTP.
+//!
+//! Methods which only read state can be called by anyone who can reach the API. Methods
+//! which submit extrinsics require the `Authorization: Bearer <token>` header, and are
+//! disabled if no token is configured.
+
+use crate::{
+    admin::{get_vault_info, VaultInfo},
+    error::Error,
+    metrics::VaultDataReader,
+    system::VaultIdManager,
+};
+use bitcoin::TransactionExt;
+use futures::{try_join, Future};
+use jsonrpc_core::{Error as JsonRpcError, ErrorCode as JsonRpcErrorCode, MetaIoHandler, Metadata, Params};
+use runtime::{
+    InterBtcParachain, IssuePallet, IssueRequestStatus, PrettyPrint, RedeemPallet, RedeemRequestStatus, ReplacePallet,
+    ReplaceRequestStatus, RuntimeCurrencyInfo, UtilFuncs, VaultId, VaultRegistryPallet, H256,
+};
+use serde::{Deserialize, Serialize};
+use service::{
+    warp::{self, http::StatusCode, reply::Response, Filter, Rejection, Reply},
+    Error as ServiceError, HealthRegistry,
+};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use subtle::ConstantTimeEq;
+
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+const UNAUTHORIZED_ERROR_CODE: i64 = 1;
+
+#[derive(Clone, Default)]
+struct Meta {
+    authenticated: bool,
+}
+
+impl Metadata for Meta {}
+
+struct ApiContext<P, V> {
+    parachain_rpc: P,
+    vault_id_manager: V,
+    health: HealthRegistry,
+}
+
+#[derive(Serialize)]
+struct WalletBalance {
+    vault_id: String,
+    balance_sat: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RequestType {
+    Issue,
+    Redeem,
+    Replace,
+}
+
+#[derive(Serialize)]
+struct OpenRequest {
+    request_type: RequestType,
+    request_id: H256,
+    vault_id: String,
+    amount: String,
+    opentime: u32,
+    period: u32,
+    /// Parachain block after which the request can be cancelled.
+    deadline: u32,
+    btc_height: u32,
+}
+
+#[derive(Serialize)]
+struct PendingPayment {
+    request_type: RequestType,
+    request_id: H256,
+    vault_id: String,
+    txid: String,
+    block_height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct AmountParams {
+    /// Symbol of the collateral currency of the vault, e.g. `KSM`.
+    collateral_currency: String,
+    /// Amount in the smallest unit of the currency, as a decimal string.
+    amount: String,
+}
+
+/// Serve the API at `addr` until the future is dropped.
+pub async fn serve_api(
+    parachain_rpc: InterBtcParachain,
+    vault_id_manager: VaultIdManager,
+    health: HealthRegistry,
+    addr: SocketAddr,
+    token: Option<String>,
+) -> Result<(), ServiceError> {
+    let io = Arc::new(build_handler(ApiContext {
+        parachain_rpc,
+        vault_id_manager,
+        health,
+    }));
+
+    let (addr, server) = warp::serve(api_route(io, token))
+        .try_bind_ephemeral(addr)
+        .map_err(|err| ServiceError::Other(err.to_string()))?;
+    tracing::info!("Starting management API at http://{}", addr);
+    server.await;
+    Ok(())
+}
+
+/// Handles JSON-RPC requests, which are authenticated if they carry the bearer `token`.
+fn api_route(
+    io: Arc<MetaIoHandler<Meta>>,
+    token: Option<String>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let token = Arc::new(token);
+    warp::post()
+        .and(warp::path::end())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_REQUEST_SIZE))
+        .and(warp::body::bytes())
+        .and_then(move |authorization: Option<String>, body: warp::hyper::body::Bytes| {
+            let io = io.clone();
+            let meta = Meta {
+                authenticated: is_authorized(&token, authorization.as_deref()),
+            };
+            async move {
+                let request = String::from_utf8_lossy(&body);
+                let reply = match io.handle_request(&request, meta).await {
+                    Some(response) => {
+                        warp::reply::with_header(response, "content-type", "application/json").into_response()
+                    }
+                    // notifications have no response
+                    None => StatusCode::NO_CONTENT.into_response(),
+                };
+                Ok::<_, Infallible>(reply)
+            }
+        })
+}
+
+fn is_authorized(token: &Option<String>, authorization: Option<&str>) -> bool {
+    match (token, authorization.and_then(|header| header.strip_prefix("Bearer "))) {
+        // compare in constant time so the token cannot be guessed from the response time
+        (Some(token), Some(bearer)) => bool::from(token.as_bytes().ct_eq(bearer.as_bytes())),
+        _ => false,
+    }
+}
+
+fn build_handler<P, V>(ctx: ApiContext<P, V>) -> MetaIoHandler<Meta>
+where
+    P: VaultRegistryPallet + IssuePallet + RedeemPallet + ReplacePallet + UtilFuncs + Send + Sync + 'static,
+    V: VaultDataReader + Send + Sync + 'static,
+{
+    let ctx = Arc::new(ctx);
+    let mut io = MetaIoHandler::default();
+
+    add_method(&mut io, &ctx, "vaults", false, |ctx, _| async move {
+        get_vaults(&ctx).await
+    });
+    add_method(&mut io, &ctx, "wallet_balances", false, |ctx, _| async move {
+        get_wallet_balances(&ctx).await
+    });
+    add_method(&mut io, &ctx, "open_requests", false, |ctx, _| async move {
+        get_open_requests(&ctx).await
+    });
+    add_method(&mut io, &ctx, "pending_payments", false, |ctx, _| async move {
+        get_pending_payments(&ctx).await
+    });
+    add_method(&mut io, &ctx, "task_health", false, |ctx, _| async move {
+        Ok(ctx.health.snapshot())
+    });
+
+    add_method(&mut io, &ctx, "deposit_collateral", true, |ctx, params| async move {
+        let (vault_id, amount) = parse_amount_params(&ctx, params).await?;
+        Ok(ctx.parachain_rpc.deposit_collateral(&vault_id, amount).await?)
+    });
+    add_method(&mut io, &ctx, "withdraw_collateral", true, |ctx, params| async move {
+        let (vault_id, amount) = parse_amount_params(&ctx, params).await?;
+        Ok(ctx.parachain_rpc.withdraw_collateral(&vault_id, amount).await?)
+    });
+    add_method(&mut io, &ctx, "request_replace", true, |ctx, params| async move {
+        let (vault_id, amount) = parse_amount_params(&ctx, params).await?;
+        Ok(ctx.parachain_rpc.request_replace(&vault_id, amount).await?)
+    });
+    add_method(&mut io, &ctx, "withdraw_replace", true, |ctx, params| async move {
+        let (vault_id, amount) = parse_amount_params(&ctx, params).await?;
+        Ok(ctx.parachain_rpc.withdraw_replace(&vault_id, amount).await?)
+    });
+
+    io
+}
+
+fn add_method<C, F, Fut, T>(io: &mut MetaIoHandler<Meta>, ctx: &Arc<C>, name: &str, requires_auth: bool, method: F)
+where
+    C: Send + Sync + 'static,
+    F: Fn(Arc<C>, Params) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Serialize,
+{
+    let ctx = ctx.clone();
+    io.add_method_with_meta(name, move |params, meta: Meta| {
+        let result = if requires_auth && !meta.authenticated {
+            Err(JsonRpcError {
+                code: JsonRpcErrorCode::ServerError(UNAUTHORIZED_ERROR_CODE),
+                message: "Unauthorized".to_string(),
+                data: None,
+            })
+        } else {
+            Ok(method(ctx.clone(), params))
+        };
+        async move { handle_resp(result?.await) }
+    });
+}
+
+fn handle_resp<T: Serialize>(resp: Result<T, Error>) -> Result<jsonrpc_core::Value, JsonRpcError> {
+    match resp.and_then(|data| Ok(serde_json::to_value(data)?)) {
+        Ok(value) => Ok(value),
+        Err(err) => Err(JsonRpcError {
+            code: match err {
+                Error::InvalidApiParams(_) => JsonRpcErrorCode::InvalidParams,
+                _ => JsonRpcErrorCode::InternalError,
+            },
+            message: err.to_string(),
+            data: None,
+        }),
+    }
+}
+
+async fn parse_amount_params<P, V: VaultDataReader>(
+    ctx: &ApiContext<P, V>,
+    params: Params,
+) -> Result<(VaultId, u128), Error> {
+    let params: AmountParams = params.parse().map_err(|err| Error::InvalidApiParams(err.message))?;
+    let amount = params
+        .amount
+        .parse()
+        .map_err(|_| Error::InvalidApiParams(format!("invalid amount `{}`", params.amount)))?;
+    let vault_id = get_vault_ids(&ctx.vault_id_manager)
+        .await
+        .into_iter()
+        .find(|vault_id| vault_id.collateral_currency().symbol().ok().as_ref() == Some(&params.collateral_currency))
+        .ok_or_else(|| Error::InvalidApiParams(format!("no vault with collateral {}", params.collateral_currency)))?;
+    Ok((vault_id, amount))
+}
+
+async fn get_vault_ids<V: VaultDataReader>(vault_id_manager: &V) -> Vec<VaultId> {
+    vault_id_manager
+        .get_entries()
+        .await
+        .into_iter()
+        .map(|vault| vault.vault_id)
+        .collect()
+}
+
+async fn get_vaults<P: VaultRegistryPallet, V: VaultDataReader>(
+    ctx: &ApiContext<P, V>,
+) -> Result<Vec<VaultInfo>, Error> {
+    let mut vaults = Vec::new();
+    for vault_id in get_vault_ids(&ctx.vault_id_manager).await {
+        vaults.push(get_vault_info(&ctx.parachain_rpc, &vault_id).await?);
+    }
+    Ok(vaults)
+}
+
+async fn get_wallet_balances<P, V: VaultDataReader>(ctx: &ApiContext<P, V>) -> Result<Vec<WalletBalance>, Error> {
+    ctx.vault_id_manager
+        .get_entries()
+        .await
+        .into_iter()
+        .map(|vault| {
+            Ok(WalletBalance {
+                vault_id: vault.vault_id.pretty_print(),
+                balance_sat: vault.btc_rpc.get_balance(None)?.to_sat(),
+            })
+        })
+        .collect()
+}
+
+async fn get_open_requests<P, V>(ctx: &ApiContext<P, V>) -> Result<Vec<OpenRequest>, Error>
+where
+    P: IssuePallet + RedeemPallet + ReplacePallet + UtilFuncs,
+{
+    let account_id = ctx.parachain_rpc.get_account_id().clone();
+    let (issue_requests, redeem_requests, replace_requests) = try_join!(
+        ctx.parachain_rpc.get_vault_issue_requests(account_id.clone()),
+        ctx.parachain_rpc.get_vault_redeem_requests(account_id.clone()),
+        ctx.parachain_rpc.get_old_vault_replace_requests(account_id),
+    )?;
+
+    let issues = issue_requests
+        .into_iter()
+        .filter(|(_, request)| request.status == IssueRequestStatus::Pending)
+        .map(|(request_id, request)| OpenRequest {
+            request_type: RequestType::Issue,
+            request_id,
+            vault_id: request.vault.pretty_print(),
+            amount: request.amount.to_string(),
+            opentime: request.opentime,
+            period: request.period,
+            deadline: request.opentime.saturating_add(request.period),
+            btc_height: request.btc_height,
+        });
+    let redeems = redeem_requests
+        .into_iter()
+        .filter(|(_, request)| request.status == RedeemRequestStatus::Pending)
+        .map(|(request_id, request)| OpenRequest {
+            request_type: RequestType::Redeem,
+            request_id,
+            vault_id: request.vault.pretty_print(),
+            amount: request.amount_btc.to_string(),
+            opentime: request.opentime,
+            period: request.period,
+            deadline: request.opentime.saturating_add(request.period),
+            btc_height: request.btc_height,
+        });
+    let replaces = replace_requests
+        .into_iter()
+        .filter(|(_, request)| request.status == ReplaceRequestStatus::Pending)
+        .map(|(request_id, request)| OpenRequest {
+            request_type: RequestType::Replace,
+            request_id,
+            vault_id: request.old_vault.pretty_print(),
+            amount: request.amount.to_string(),
+            opentime: request.accept_time,
+            period: request.period,
+            deadline: request.accept_time.saturating_add(request.period),
+            btc_height: request.btc_height,
+        });
+
+    Ok(issues.chain(redeems).chain(replaces).collect())
+}
+
+/// Bitcoin payments made for redeem and replace requests which have not been executed yet.
+/// Only the transactions since the oldest of these requests are searched.
+async fn get_pending_payments<P, V>(ctx: &ApiContext<P, V>) -> Result<Vec<PendingPayment>, Error>
+where
+    P: IssuePallet + RedeemPallet + ReplacePallet + UtilFuncs,
+    V: VaultDataReader,
+{
+    let open_requests: HashMap<H256, OpenRequest> = get_open_requests(ctx)
+        .await?
+        .into_iter()
+        .filter(|request| request.request_type != RequestType::Issue)
+        .map(|request| (request.request_id, request))
+        .collect();
+    let btc_start_height = match open_requests.values().map(|request| request.btc_height).min() {
+        Some(height) => height,
+        None => return Ok(vec![]),
+    };
+
+    let mut payments = Vec::new();
+    for vault in ctx.vault_id_manager.get_entries().await {
+        let (vault_id, btc_rpc) = (vault.vault_id, vault.btc_rpc);
+        for transaction in btc_rpc.list_wallet_transactions(Some(btc_start_height)).await? {
+            let block_height = transaction.block.map(|(_, height)| height);
+            if transaction.replaced || transaction.amount.is_positive() {
+                continue;
+            }
+            let tx = btc_rpc
+                .get_transaction(&transaction.txid, transaction.block.map(|(hash, _)| hash))
+                .await?;
+            if let Some(request) = tx.get_op_return().and_then(|request_id| open_requests.get(&request_id)) {
+                if request.vault_id == vault_id.pretty_print() {
+                    payments.push(PendingPayment {
+                        request_type: request.request_type,
+                        request_id: request.request_id,
+                        vault_id: request.vault_id.clone(),
+                        txid: transaction.txid.to_string(),
+                        block_height,
+                    });
+                }
+            }
+        }
+    }
+    Ok(payments)
+}
+
+#[cfg(all(test, feature = "parachain-metadata-kintsugi-testnet"))]
+mod tests {
+    use super::*;
+    use crate::{journal::PaymentJournal, metrics::PerCurrencyMetrics, system::VaultData};
+    use async_trait::async_trait;
+    use bitcoin::{
+        json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Network,
+        PackedLockTime, PrivateKey, PublicKey, SatPerVbyte, Script, SignedAmount, Transaction, TransactionMetadata,
+        TxOut, Txid, WalletTransaction,
+    };
+    use jsonrpc_core::serde_json::{json, Map, Value};
+    use runtime::{
+        AccountId, AssetMetadata, BlockNumber, BtcAddress, BtcPublicKey, CurrencyId, Error as RuntimeError,
+        InterBtcIssueRequest, InterBtcRedeemRequest, InterBtcReplaceRequest, InterBtcVault, RequestIssueEvent, Token,
+        DOT, IBTC,
+    };
+    use service::DynBitcoinCoreApi;
+
+    mockall::mock! {
+        Provider {}
+
+        #[async_trait]
+        pub trait UtilFuncs {
+            async fn get_current_chain_height(&self) -> Result<u32, RuntimeError>;
+            async fn get_rpc_properties(&self) -> Result<Map<String, Value>, RuntimeError>;
+            fn get_native_currency_id(&self) -> CurrencyId;
+            fn get_account_id(&self) -> &AccountId;
+            fn is_this_vault(&self, vault_id: &VaultId) -> bool;
+            async fn get_foreign_assets_metadata(&self) -> Result<Vec<(u32, AssetMetadata)>, RuntimeError>;
+            async fn get_foreign_asset_metadata(&self, id: u32) -> Result<AssetMetadata, RuntimeError>;
+        }
+
+        #[async_trait]
+        pub trait IssuePallet {
+            async fn request_issue(&self, amount: u128, vault_id: &VaultId) -> Result<RequestIssueEvent, RuntimeError>;
+            async fn execute_issue(&self, issue_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), RuntimeError>;
+            async fn cancel_issue(&self, issue_id: H256) -> Result<(), RuntimeError>;
+            async fn get_issue_request(&self, issue_id: H256) -> Result<InterBtcIssueRequest, RuntimeError>;
+            async fn get_vault_issue_requests(&self, account_id: AccountId) -> Result<Vec<(H256, InterBtcIssueRequest)>, RuntimeError>;
+            async fn get_issue_period(&self) -> Result<u32, RuntimeError>;
+            async fn get_all_active_issues(&self) -> Result<Vec<(H256, InterBtcIssueRequest)>, RuntimeError>;
+        }
+
+        #[async_trait]
+        pub trait RedeemPallet {
+            async fn request_redeem(&self, amount: u128, btc_address: BtcAddress, vault_id: &VaultId) -> Result<H256, RuntimeError>;
+            async fn execute_redeem(&self, redeem_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), RuntimeError>;
+            async fn cancel_redeem(&self, redeem_id: H256, reimburse: bool) -> Result<(), RuntimeError>;
+            async fn get_redeem_request(&self, redeem_id: H256) -> Result<InterBtcRedeemRequest, RuntimeError>;
+            async fn get_vault_redeem_requests(&self, account_id: AccountId) -> Result<Vec<(H256, InterBtcRedeemRequest)>, RuntimeError>;
+            async fn get_redeem_period(&self) -> Result<BlockNumber, RuntimeError>;
+        }
+
+        #[async_trait]
+        pub trait VaultRegistryPallet {
+            async fn get_vault(&self, vault_id: &VaultId) -> Result<InterBtcVault, RuntimeError>;
+            async fn get_vaults_by_account_id(&self, account_id: &AccountId) -> Result<Vec<VaultId>, RuntimeError>;
+            async fn get_all_vaults(&self) -> Result<Vec<InterBtcVault>, RuntimeError>;
+            async fn register_vault(&self, vault_id: &VaultId, collateral: u128) -> Result<(), RuntimeError>;
+            async fn deposit_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), RuntimeError>;
+            async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, RuntimeError>;
+            async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), RuntimeError>;
+            async fn get_required_collateral_for_wrapped(&self, amount_btc: u128, collateral_currency: CurrencyId) -> Result<u128, RuntimeError>;
+            async fn get_required_collateral_for_vault(&self, vault_id: VaultId) -> Result<u128, RuntimeError>;
+            async fn get_vault_total_collateral(&self, vault_id: VaultId) -> Result<u128, RuntimeError>;
+            async fn get_collateralization_from_vault(&self, vault_id: VaultId, only_issued: bool) -> Result<u128, RuntimeError>;
+            async fn set_current_client_release(&self, uri: &[u8], code_hash: &H256) -> Result<(), RuntimeError>;
+            async fn set_pending_client_release(&self, uri: &[u8], code_hash: &H256) -> Result<(), RuntimeError>;
+        }
+
+        #[async_trait]
+        pub trait ReplacePallet {
+            async fn request_replace(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn withdraw_replace(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn accept_replace(&self, new_vault: &VaultId, old_vault: &VaultId, amount_btc: u128, collateral: u128, btc_address: BtcAddress) -> Result<(), RuntimeError>;
+            async fn execute_replace(&self, replace_id: H256, merkle_proof: &[u8], raw_tx: &[u8]) -> Result<(), RuntimeError>;
+            async fn cancel_replace(&self, replace_id: H256) -> Result<(), RuntimeError>;
+            async fn get_new_vault_replace_requests(&self, account_id: AccountId) -> Result<Vec<(H256, InterBtcReplaceRequest)>, RuntimeError>;
+            async fn get_old_vault_replace_requests(&self, account_id: AccountId) -> Result<Vec<(H256, InterBtcReplaceRequest)>, RuntimeError>;
+            async fn get_replace_period(&self) -> Result<u32, RuntimeError>;
+            async fn get_replace_request(&self, replace_id: H256) -> Result<InterBtcReplaceRequest, RuntimeError>;
+            async fn get_replace_dust_amount(&self) -> Result<u128, RuntimeError>;
+        }
+    }
+
+    mockall::mock! {
+        Bitcoin {}
+
+        #[async_trait]
+        trait BitcoinCoreApi {
+            fn network(&self) -> Network;
+            async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
+            fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
+            fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
+            async fn get_block_count(&self) -> Result<u64, BitcoinError>;
+            async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
+            async fn get_proof(&self, txid: Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_block_hash(&self, height: u32) -> Result<BlockHash, BitcoinError>;
+            async fn get_pruned_height(&self) -> Result<u64, BitcoinError>;
+            async fn get_new_address(&self) -> Result<Address, BitcoinError>;
+            async fn get_new_public_key(&self) -> Result<PublicKey, BitcoinError>;
+            fn dump_derivation_key(&self, public_key: &PublicKey) -> Result<PrivateKey, BitcoinError>;
+            fn import_derivation_key(&self, private_key: &PrivateKey) -> Result<(), BitcoinError>;
+            async fn add_new_deposit_key(&self, public_key: PublicKey, secret_key: Vec<u8>) -> Result<(), BitcoinError>;
+            async fn get_best_block_hash(&self) -> Result<BlockHash, BitcoinError>;
+            async fn get_block(&self, hash: &BlockHash) -> Result<Block, BitcoinError>;
+            async fn get_block_header(&self, hash: &BlockHash) -> Result<BlockHeader, BitcoinError>;
+            async fn get_mempool_transactions<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError>;
+            async fn wait_for_transaction_metadata(&self, txid: Txid, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn create_and_send_transaction(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, max_fee: u64, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn send_to_address(&self, address: Address, sat: u64, request_id: Option<H256>, fee_rate: SatPerVbyte, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn create_or_load_wallet(&self) -> Result<(), BitcoinError>;
+            async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError>;
+            async fn rescan_electrs_for_addresses(&self, addresses: Vec<Address>) -> Result<(), BitcoinError>;
+            fn get_utxo_count(&self) -> Result<usize, BitcoinError>;
+            async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError>;
+            async fn is_in_mempool(&self, txid: Txid) -> Result<bool, BitcoinError>;
+            async fn fee_rate(&self, txid: Txid) -> Result<SatPerVbyte, BitcoinError>;
+        }
+    }
+
+    mockall::mock! {
+        VaultIdManager {}
+
+        #[async_trait]
+        trait VaultDataReader {
+            async fn get_entries(&self) -> Vec<VaultData>;
+        }
+    }
+
+    const TOKEN: &str = "secret";
+
+    fn dummy_vault_id() -> VaultId {
+        VaultId::new(AccountId::new([1u8; 32]), Token(DOT), Token(IBTC))
+    }
+
+    fn dummy_vault_id_manager(btc_rpc: DynBitcoinCoreApi) -> MockVaultIdManager {
+        let mut vault_id_manager = MockVaultIdManager::default();
+        vault_id_manager.expect_get_entries().returning(move || {
+            vec![VaultData {
+                vault_id: dummy_vault_id(),
+                btc_rpc: btc_rpc.clone(),
+                metrics: PerCurrencyMetrics::dummy(),
+                journal: PaymentJournal::temporary(),
+            }]
+        });
+        vault_id_manager
+    }
+
+    fn dummy_issue_request(status: IssueRequestStatus) -> InterBtcIssueRequest {
+        InterBtcIssueRequest {
+            amount: 100,
+            btc_address: Default::default(),
+            btc_height: 90,
+            fee: Default::default(),
+            griefing_collateral: Default::default(),
+            opentime: 1_000,
+            period: 500,
+            requester: AccountId::new([2u8; 32]),
+            btc_public_key: BtcPublicKey { 0: [0; 33] },
+            status,
+            vault: dummy_vault_id(),
+        }
+    }
+
+    fn dummy_redeem_request(status: RedeemRequestStatus) -> InterBtcRedeemRequest {
+        InterBtcRedeemRequest {
+            amount_btc: 200,
+            btc_address: Default::default(),
+            btc_height: 100,
+            fee: Default::default(),
+            transfer_fee_btc: Default::default(),
+            premium: Default::default(),
+            opentime: 2_000,
+            period: 300,
+            redeemer: AccountId::new([2u8; 32]),
+            status,
+            vault: dummy_vault_id(),
+        }
+    }
+
+    /// A provider with a pending and a completed request of each type, except replace.
+    fn provider_with_requests() -> MockProvider {
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_get_account_id()
+            .return_const(AccountId::new([1u8; 32]));
+        parachain_rpc.expect_get_vault_issue_requests().returning(|_| {
+            Ok(vec![
+                (
+                    H256::from_low_u64_be(1),
+                    dummy_issue_request(IssueRequestStatus::Pending),
+                ),
+                (
+                    H256::from_low_u64_be(2),
+                    dummy_issue_request(IssueRequestStatus::Completed),
+                ),
+            ])
+        });
+        parachain_rpc.expect_get_vault_redeem_requests().returning(|_| {
+            Ok(vec![
+                (
+                    H256::from_low_u64_be(3),
+                    dummy_redeem_request(RedeemRequestStatus::Pending),
+                ),
+                (
+                    H256::from_low_u64_be(4),
+                    dummy_redeem_request(RedeemRequestStatus::Completed),
+                ),
+            ])
+        });
+        parachain_rpc
+            .expect_get_old_vault_replace_requests()
+            .returning(|_| Ok(vec![]));
+        parachain_rpc
+    }
+
+    fn payment(request_id: Option<H256>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: request_id
+                .map(|request_id| TxOut {
+                    value: 0,
+                    script_pubkey: Script::new_op_return(request_id.as_bytes()),
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn wallet_transaction(transaction: &Transaction, amount: i64) -> WalletTransaction {
+        WalletTransaction {
+            txid: transaction.txid(),
+            block: None,
+            amount: SignedAmount::from_sat(amount),
+            fee: None,
+            replaced: false,
+        }
+    }
+
+    /// Calls the method through the http route, and returns the JSON-RPC response.
+    async fn call<P, V>(ctx: ApiContext<P, V>, authorization: Option<&str>, method: &str, params: Value) -> Value
+    where
+        P: VaultRegistryPallet + IssuePallet + RedeemPallet + ReplacePallet + UtilFuncs + Send + Sync + 'static,
+        V: VaultDataReader + Send + Sync + 'static,
+    {
+        let route = api_route(Arc::new(build_handler(ctx)), Some(TOKEN.to_string()));
+        let mut request = warp::test::request().method("POST").path("/").json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }));
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let response = request.reply(&route).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let token = Some("secret".to_string());
+        assert!(is_authorized(&token, Some("Bearer secret")));
+        assert!(!is_authorized(&token, Some("Bearer wrong")));
+        assert!(!is_authorized(&token, Some("secret")));
+        assert!(!is_authorized(&token, None));
+        // actions are disabled without a token
+        assert!(!is_authorized(&None, Some("Bearer ")));
+    }
+
+    #[tokio::test]
+    async fn test_actions_require_token() {
+        let params = json!({ "collateral_currency": "DOT", "amount": "1000" });
+        for authorization in [None, Some("Bearer wrong"), Some(TOKEN)] {
+            let mut parachain_rpc = MockProvider::default();
+            parachain_rpc.expect_deposit_collateral().times(0);
+            let ctx = ApiContext {
+                parachain_rpc,
+                vault_id_manager: dummy_vault_id_manager(Arc::new(MockBitcoin::default())),
+                health: HealthRegistry::default(),
+            };
+            let response = call(ctx, authorization, "deposit_collateral", params.clone()).await;
+            assert_eq!(response["error"]["code"], UNAUTHORIZED_ERROR_CODE);
+        }
+
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_deposit_collateral()
+            .withf(|vault_id, amount| vault_id == &dummy_vault_id() && *amount == 1000)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let ctx = ApiContext {
+            parachain_rpc,
+            vault_id_manager: dummy_vault_id_manager(Arc::new(MockBitcoin::default())),
+            health: HealthRegistry::default(),
+        };
+        let response = call(ctx, Some(&format!("Bearer {TOKEN}")), "deposit_collateral", params).await;
+        assert_eq!(response["error"], Value::Null);
+        assert_eq!(response["result"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_read_methods_do_not_require_token() {
+        let ctx = ApiContext {
+            parachain_rpc: provider_with_requests(),
+            vault_id_manager: dummy_vault_id_manager(Arc::new(MockBitcoin::default())),
+            health: HealthRegistry::default(),
+        };
+        let response = call(ctx, None, "open_requests", json!([])).await;
+        assert!(response["result"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_open_requests() {
+        let ctx = ApiContext {
+            parachain_rpc: provider_with_requests(),
+            vault_id_manager: dummy_vault_id_manager(Arc::new(MockBitcoin::default())),
+            health: HealthRegistry::default(),
+        };
+        let requests = get_open_requests(&ctx).await.unwrap();
+        let summary: Vec<_> = requests
+            .iter()
+            .map(|request| (request.request_type, request.request_id, request.deadline))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (RequestType::Issue, H256::from_low_u64_be(1), 1_500),
+                (RequestType::Redeem, H256::from_low_u64_be(3), 2_300),
+            ]
+        );
+        assert_eq!(requests[1].amount, "200");
+        assert_eq!(requests[1].btc_height, 100);
+    }
+
+    #[tokio::test]
+    async fn test_pending_payments() {
+        let redeem_payment = payment(Some(H256::from_low_u64_be(3)));
+        let completed_payment = payment(Some(H256::from_low_u64_be(4)));
+        let unrelated_payment = payment(None);
+        let deposit = payment(Some(H256::from_low_u64_be(1)));
+
+        let mut btc_rpc = MockBitcoin::default();
+        let transactions = vec![
+            wallet_transaction(&redeem_payment, -200),
+            wallet_transaction(&completed_payment, -200),
+            wallet_transaction(&unrelated_payment, -300),
+            wallet_transaction(&deposit, 100),
+        ];
+        // only transactions since the oldest pending redeem or replace request are searched
+        btc_rpc
+            .expect_list_wallet_transactions()
+            .withf(|since_height| *since_height == Some(100))
+            .times(1)
+            .returning(move |_| Ok(transactions.clone()));
+        btc_rpc.expect_get_transaction().returning(move |txid, _| {
+            Ok([&redeem_payment, &completed_payment, &unrelated_payment]
+                .into_iter()
+                .find(|tx| &tx.txid() == txid)
+                .cloned()
+                .cloned()
+                .expect("incoming transactions are skipped"))
+        });
+
+        let ctx = ApiContext {
+            parachain_rpc: provider_with_requests(),
+            vault_id_manager: dummy_vault_id_manager(Arc::new(btc_rpc)),
+            health: HealthRegistry::default(),
+        };
+        let payments = get_pending_payments(&ctx).await.unwrap();
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].request_type, RequestType::Redeem);
+        assert_eq!(payments[0].request_id, H256::from_low_u64_be(3));
+        assert_eq!(payments[0].vault_id, dummy_vault_id().pretty_print());
+        assert_eq!(payments[0].block_height, None);
+    }
+}
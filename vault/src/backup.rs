@@ -0,0 +1,138 @@
+//! Backup and restore of the bitcoin derivation key, from which all deposit keys of the
+//! vault are derived. Losing it means losing access to the bitcoin the vault holds.
+
+use crate::Error;
+use bitcoin::{secp256k1::Secp256k1, Error as BitcoinError, PrivateKey, PublicKey};
+use runtime::{BtcPublicKey, InterBtcParachain, Ss58Codec, UtilFuncs, VaultRegistryPallet, SS58_PREFIX};
+use serde::{Deserialize, Serialize};
+use service::DynBitcoinCoreApi;
+
+/// Content type of an encrypted [`KeyBackup`].
+pub const BACKUP_CONTENT: &[&str] = &["vault-key-backup"];
+/// Environment variable holding the password of the backup file.
+pub const BACKUP_PASSWORD_ENV: &str = "KEY_BACKUP_PASSWORD";
+
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyBackup {
+    pub version: u32,
+    /// The ss58 encoded account of the vault.
+    pub account_id: String,
+    /// The hex encoded public key registered on the parachain.
+    pub public_key: String,
+    /// The WIF encoded derivation key.
+    pub derivation_key: String,
+}
+
+impl KeyBackup {
+    fn new(account_id: String, public_key: &PublicKey, private_key: &PrivateKey) -> Self {
+        Self {
+            version: BACKUP_VERSION,
+            account_id,
+            public_key: public_key.to_string(),
+            derivation_key: private_key.to_wif(),
+        }
+    }
+
+    /// Returns the derivation key if it belongs to the `registered` public key.
+    pub fn verify(&self, registered: &BtcPublicKey) -> Result<PrivateKey, Error> {
+        if self.version != BACKUP_VERSION {
+            return Err(Error::InvalidKeyBackup(format!("unsupported version {}", self.version)));
+        }
+        let private_key =
+            PrivateKey::from_wif(&self.derivation_key).map_err(|err| Error::InvalidKeyBackup(err.to_string()))?;
+        let public_key = PublicKey::from_private_key(&Secp256k1::new(), &private_key);
+        if public_key.to_string() != self.public_key {
+            return Err(Error::InvalidKeyBackup(
+                "the derivation key does not match the public key".to_string(),
+            ));
+        }
+        if public_key.to_bytes() != registered.0 {
+            return Err(Error::KeyBackupMismatch);
+        }
+        Ok(private_key)
+    }
+}
+
+async fn registered_public_key(parachain_rpc: &InterBtcParachain) -> Result<BtcPublicKey, Error> {
+    Ok(parachain_rpc
+        .get_public_key()
+        .await?
+        .ok_or(BitcoinError::MissingPublicKey)?)
+}
+
+/// Exports the derivation key of the public key that is registered on the parachain
+/// from the master wallet.
+pub async fn backup_keys(
+    parachain_rpc: &InterBtcParachain,
+    master_wallet: &DynBitcoinCoreApi,
+) -> Result<KeyBackup, Error> {
+    let registered = registered_public_key(parachain_rpc).await?;
+    let public_key = PublicKey::from_slice(&registered.0).map_err(BitcoinError::KeyError)?;
+    let private_key = master_wallet.dump_derivation_key(&public_key)?;
+    let account_id = parachain_rpc
+        .get_account_id()
+        .to_ss58check_with_version(SS58_PREFIX.into());
+    Ok(KeyBackup::new(account_id, &public_key, &private_key))
+}
+
+/// Imports the derivation key into the master wallet, from which it is copied to the
+/// per-currency wallets when the vault starts. Refuses keys that do not belong to the
+/// public key registered on the parachain.
+pub async fn restore_keys(
+    parachain_rpc: &InterBtcParachain,
+    master_wallet: &DynBitcoinCoreApi,
+    backup: &KeyBackup,
+) -> Result<(), Error> {
+    let private_key = backup.verify(&registered_public_key(parachain_rpc).await?)?;
+    if private_key.network != master_wallet.network() {
+        return Err(Error::InvalidKeyBackup(format!(
+            "the derivation key is for {}, but bitcoin core runs on {}",
+            private_key.network,
+            master_wallet.network()
+        )));
+    }
+    master_wallet.import_derivation_key(&private_key)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Network, SecretKey};
+    use std::convert::TryInto;
+
+    fn backup() -> (KeyBackup, BtcPublicKey) {
+        let private_key = PrivateKey::new(SecretKey::from_slice(&[1; 32]).unwrap(), Network::Regtest);
+        let public_key = PublicKey::from_private_key(&Secp256k1::new(), &private_key);
+        let registered = BtcPublicKey {
+            0: public_key.to_bytes().try_into().unwrap(),
+        };
+        (
+            KeyBackup::new("vault".to_string(), &public_key, &private_key),
+            registered,
+        )
+    }
+
+    #[test]
+    fn test_restore_registered_key() {
+        let (backup, registered) = backup();
+        let private_key = backup.verify(&registered).unwrap();
+        assert_eq!(private_key.to_wif(), backup.derivation_key);
+    }
+
+    #[test]
+    fn test_refuse_key_of_other_public_key() {
+        let (backup, _) = backup();
+        let other = BtcPublicKey { 0: [2; 33] };
+        assert!(matches!(backup.verify(&other), Err(Error::KeyBackupMismatch)));
+    }
+
+    #[test]
+    fn test_refuse_inconsistent_backup() {
+        let (mut backup, registered) = backup();
+        backup.public_key = hex::encode([3u8; 33]);
+        assert!(matches!(backup.verify(&registered), Err(Error::InvalidKeyBackup(_))));
+    }
+}
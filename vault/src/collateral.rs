@@ -0,0 +1,559 @@
+//! Automatic collateral top-ups and replace requests, to keep vaults away from the
+//! liquidation threshold, and rebalancing of excess collateral.
+
+use crate::{
+    error::Error,
+    notify::{Notification, Notifier},
+    system::AbortOnDrop,
+    VaultIdManager,
+};
+use runtime::{
+    CollateralBalancesPallet, CurrencyId, FeedValuesEvent, FixedPointNumber, FixedU128, InterBtcParachain, OracleKey,
+    PrettyPrint, ReplacePallet, RuntimeCurrencyInfo, VaultId, VaultRegistryPallet,
+};
+use service::Error as ServiceError;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time::{interval, sleep},
+};
+
+/// Number of exchange rate updates buffered for each vault monitor.
+const PRICE_UPDATE_CAPACITY: usize = 16;
+
+/// The amount of collateral to add or remove for the vault to reach the target
+/// collateralization. The collateralization scales linearly with the collateral.
+fn collateral_to_target(total_collateral: u128, collateralization: FixedU128, target: FixedU128) -> Option<u128> {
+    let (current, target) = (collateralization.into_inner(), target.into_inner());
+    let difference = current.max(target) - current.min(target);
+    FixedU128::checked_from_rational(difference, current)?.checked_mul_int(total_collateral)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollateralTopUp {
+    /// Collateralization below which collateral is deposited.
+    pub threshold: FixedU128,
+    /// Collateralization to restore when depositing.
+    pub target: FixedU128,
+}
+
+impl CollateralTopUp {
+    pub fn new(threshold: f64, target: Option<f64>) -> Self {
+        Self {
+            threshold: FixedU128::from_float(threshold),
+            target: FixedU128::from_float(target.unwrap_or(threshold).max(threshold)),
+        }
+    }
+
+    /// The amount of collateral to deposit to bring the vault back to the target
+    /// collateralization, or `None` if it is above the threshold.
+    fn amount(&self, total_collateral: u128, collateralization: FixedU128) -> Option<u128> {
+        if collateralization >= self.threshold {
+            return None;
+        }
+        collateral_to_target(total_collateral, collateralization, self.target)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProactiveReplace {
+    /// Collateralization below which part of the issued tokens is put up for replacement.
+    pub threshold: FixedU128,
+    /// Collateralization the replacement should restore. Open requests are withdrawn
+    /// once the vault recovers to this collateralization.
+    pub target: FixedU128,
+}
+
+impl ProactiveReplace {
+    pub fn new(threshold: f64, target: f64) -> Self {
+        Self {
+            threshold: FixedU128::from_float(threshold),
+            target: FixedU128::from_float(target.max(threshold)),
+        }
+    }
+
+    /// The amount of issued tokens to replace to bring the vault back to the target
+    /// collateralization, or `None` if it is above the threshold.
+    fn amount(&self, issued_tokens: u128, collateralization: FixedU128) -> Option<u128> {
+        if collateralization >= self.threshold {
+            return None;
+        }
+        // the collateralization is inversely proportional to the issued tokens
+        let excess = self.target.into_inner().checked_sub(collateralization.into_inner())?;
+        FixedU128::checked_from_rational(excess, self.target.into_inner())?.checked_mul_int(issued_tokens)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollateralRebalance {
+    /// Collateralization above which excess collateral is withdrawn.
+    pub upper_threshold: FixedU128,
+    /// Collateralization to withdraw down to, and to top up other vaults to.
+    pub target: FixedU128,
+    /// Maximum number of deposits and withdrawals per period.
+    pub max_actions: usize,
+    pub period: Duration,
+    /// Only log the actions that would be taken.
+    pub dry_run: bool,
+}
+
+impl CollateralRebalance {
+    pub fn new(upper_threshold: f64, target: f64, max_actions: usize, period: Duration, dry_run: bool) -> Self {
+        Self {
+            upper_threshold: FixedU128::from_float(upper_threshold),
+            target: FixedU128::from_float(target.min(upper_threshold)),
+            max_actions,
+            period,
+            dry_run,
+        }
+    }
+
+    /// Fails if the target is not above the top-up target, in which case collateral that is
+    /// withdrawn down to the target would be deposited again by the top-up.
+    pub fn validate(&self, top_up: Option<&CollateralTopUp>) -> Result<(), Error> {
+        match top_up {
+            Some(top_up) if self.target <= top_up.target => Err(Error::InvalidConfig(format!(
+                "the rebalance target of {} must be above the collateral top-up target of {}",
+                self.target.to_float(),
+                top_up.target.to_float()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Sliding window limit on the number of actions, e.g. rebalancing deposits and withdrawals.
+pub(crate) struct ActionLimit {
+    max_actions: usize,
+    period: Duration,
+    history: VecDeque<Instant>,
+}
+
+impl ActionLimit {
+    pub(crate) fn new(max_actions: usize, period: Duration) -> Self {
+        Self {
+            max_actions,
+            period,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Records an action at `now` if the limit allows it.
+    pub(crate) fn try_acquire(&mut self, now: Instant) -> bool {
+        while matches!(self.history.front(), Some(time) if now.duration_since(*time) >= self.period) {
+            self.history.pop_front();
+        }
+        if self.history.len() < self.max_actions {
+            self.history.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+async fn get_collateralization<P: VaultRegistryPallet>(parachain_rpc: &P, vault_id: &VaultId) -> Option<FixedU128> {
+    match parachain_rpc
+        .get_collateralization_from_vault(vault_id.clone(), false)
+        .await
+    {
+        Ok(x) => Some(FixedU128::from_inner(x)),
+        Err(err) => {
+            // this also fails if nothing has been issued, i.e. the collateralization is infinite
+            tracing::debug!("Failed to get collateralization: {}", err);
+            None
+        }
+    }
+}
+
+async fn top_up_if_needed<P: VaultRegistryPallet + CollateralBalancesPallet>(
+    parachain_rpc: &P,
+    vault_id: &VaultId,
+    top_up: CollateralTopUp,
+    notifier: &Notifier,
+) -> Result<(), Error> {
+    let collateralization = match get_collateralization(parachain_rpc, vault_id).await {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let total_collateral = parachain_rpc.get_vault_total_collateral(vault_id.clone()).await?;
+    let amount = match top_up.amount(total_collateral, collateralization) {
+        Some(x) if x > 0 => x,
+        _ => return Ok(()),
+    };
+
+    let currency_id = vault_id.collateral_currency();
+    let free_balance = parachain_rpc.get_free_balance(currency_id).await?;
+    if free_balance < amount {
+        tracing::error!(
+            "CRITICAL: [{}] collateralization is {:.2}%, but the free balance of {} {} is insufficient to deposit {} - the vault is at risk of liquidation",
+            vault_id.pretty_print(),
+            collateralization.to_float() * 100.0,
+            free_balance,
+            currency_id.symbol()?,
+            amount
+        );
+        notifier.notify(Notification::LiquidationRisk {
+            vault_id: vault_id.pretty_print(),
+            collateralization: format!("{:.2}%", collateralization.to_float() * 100.0),
+            free_balance,
+            deposit: amount,
+        });
+        return Err(Error::InsufficientFunds);
+    }
+
+    tracing::info!(
+        "[{}] Collateralization is {:.2}% - depositing {} {}",
+        vault_id.pretty_print(),
+        collateralization.to_float() * 100.0,
+        amount,
+        currency_id.symbol()?,
+    );
+    parachain_rpc.deposit_collateral(vault_id, amount).await?;
+    Ok(())
+}
+
+/// Requests replacement of part of the issued tokens of a vault that is below the
+/// threshold, so that other vaults take over the risk before it is liquidated. Withdraws
+/// the outstanding part of what it `requested` once the vault has recovered, leaving other
+/// replace requests, e.g. those made manually or by the drain, in place.
+async fn replace_if_needed<P: VaultRegistryPallet + ReplacePallet>(
+    parachain_rpc: &P,
+    vault_id: &VaultId,
+    policy: ProactiveReplace,
+    requested: &mut u128,
+) -> Result<(), Error> {
+    let collateralization = match get_collateralization(parachain_rpc, vault_id).await {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let vault = parachain_rpc.get_vault(vault_id).await?;
+
+    if let Some(amount) = policy.amount(vault.issued_tokens, collateralization) {
+        // don't request the part that is already up for replacement again
+        let amount = amount.saturating_sub(vault.to_be_replaced_tokens);
+        if amount > 0 {
+            tracing::warn!(
+                "[{}] Collateralization is {:.2}% - requesting replacement of {} tokens",
+                vault_id.pretty_print(),
+                collateralization.to_float() * 100.0,
+                amount
+            );
+            parachain_rpc.request_replace(vault_id, amount).await?;
+            *requested += amount;
+        }
+    } else if collateralization >= policy.target {
+        // part of the request may have been accepted by other vaults in the meantime
+        let amount = std::mem::take(requested).min(vault.to_be_replaced_tokens);
+        if amount > 0 {
+            tracing::info!(
+                "[{}] Collateralization recovered to {:.2}% - withdrawing replace request of {} tokens",
+                vault_id.pretty_print(),
+                collateralization.to_float() * 100.0,
+                amount
+            );
+            parachain_rpc.withdraw_replace(vault_id, amount).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Tops up the vault if needed, and requests replacement if that was not sufficient.
+async fn defend_vault<P: VaultRegistryPallet + CollateralBalancesPallet + ReplacePallet>(
+    parachain_rpc: &P,
+    vault_id: &VaultId,
+    top_up: Option<CollateralTopUp>,
+    replace: Option<ProactiveReplace>,
+    notifier: &Notifier,
+    requested_replacement: &mut u128,
+) {
+    if let Some(top_up) = top_up {
+        if let Err(err) = top_up_if_needed(parachain_rpc, vault_id, top_up, notifier).await {
+            tracing::error!("[{}] Failed to top up collateral: {}", vault_id.pretty_print(), err);
+        }
+    }
+    if let Some(replace) = replace {
+        if let Err(err) = replace_if_needed(parachain_rpc, vault_id, replace, requested_replacement).await {
+            tracing::error!(
+                "[{}] Failed to update replace request: {}",
+                vault_id.pretty_print(),
+                err
+            );
+        }
+    }
+}
+
+/// Withdraws the excess collateral of vaults above the upper threshold, and deposits it
+/// into vaults below the target. Collateral can only move between vaults with the same
+/// collateral currency, so anything that can not be deposited remains free balance.
+async fn rebalance_collateral<P: VaultRegistryPallet>(
+    parachain_rpc: &P,
+    vault_ids: &[VaultId],
+    policy: &CollateralRebalance,
+    limit: &mut ActionLimit,
+) -> Result<(), Error> {
+    let mut vaults = Vec::new();
+    for vault_id in vault_ids {
+        if let Some(collateralization) = get_collateralization(parachain_rpc, vault_id).await {
+            let total_collateral = parachain_rpc.get_vault_total_collateral(vault_id.clone()).await?;
+            vaults.push((vault_id, collateralization, total_collateral));
+        }
+    }
+
+    let dry_run = if policy.dry_run { "[dry-run] " } else { "" };
+    let mut freed = HashMap::new();
+    for (vault_id, collateralization, total_collateral) in vaults.iter() {
+        if *collateralization <= policy.upper_threshold {
+            continue;
+        }
+        let amount = match collateral_to_target(*total_collateral, *collateralization, policy.target) {
+            Some(x) if x > 0 => x,
+            _ => continue,
+        };
+        if !limit.try_acquire(Instant::now()) {
+            tracing::warn!("Rebalancing limit of {} actions reached", policy.max_actions);
+            return Ok(());
+        }
+        tracing::info!(
+            "{}[{}] Collateralization is {:.2}% - withdrawing {}",
+            dry_run,
+            vault_id.pretty_print(),
+            collateralization.to_float() * 100.0,
+            amount
+        );
+        if !policy.dry_run {
+            parachain_rpc.withdraw_collateral(vault_id, amount).await?;
+        }
+        *freed.entry(vault_id.collateral_currency()).or_insert(0u128) += amount;
+    }
+
+    for (vault_id, collateralization, total_collateral) in vaults.iter() {
+        if *collateralization >= policy.target {
+            continue;
+        }
+        let available = match freed.get_mut(&vault_id.collateral_currency()) {
+            Some(x) if *x > 0 => x,
+            _ => continue,
+        };
+        let amount = match collateral_to_target(*total_collateral, *collateralization, policy.target) {
+            Some(x) if x > 0 => x.min(*available),
+            _ => continue,
+        };
+        if !limit.try_acquire(Instant::now()) {
+            tracing::warn!("Rebalancing limit of {} actions reached", policy.max_actions);
+            return Ok(());
+        }
+        tracing::info!(
+            "{}[{}] Collateralization is {:.2}% - depositing {} of withdrawn collateral",
+            dry_run,
+            vault_id.pretty_print(),
+            collateralization.to_float() * 100.0,
+            amount
+        );
+        if !policy.dry_run {
+            parachain_rpc.deposit_collateral(vault_id, amount).await?;
+        }
+        *available -= amount;
+    }
+    Ok(())
+}
+
+/// Defends a single vault: checks it on every exchange rate update of its collateral
+/// currency, and periodically in case a deposit failed. The `lock` is shared with the
+/// other monitors and the rebalancing, so that they don't move collateral concurrently.
+#[allow(clippy::too_many_arguments)]
+async fn monitor_vault(
+    parachain_rpc: InterBtcParachain,
+    vault_id: VaultId,
+    top_up: Option<CollateralTopUp>,
+    replace: Option<ProactiveReplace>,
+    notifier: Notifier,
+    mut price_updates: broadcast::Receiver<CurrencyId>,
+    check_interval: Duration,
+    lock: Arc<Mutex<()>>,
+) {
+    let collateral_currency = vault_id.collateral_currency();
+    // replacements requested by the policy, which are withdrawn once the vault recovers
+    let mut requested_replacement = 0;
+    let mut timer = interval(check_interval);
+    // the first tick completes immediately
+    timer.tick().await;
+
+    loop {
+        tokio::select! {
+            update = price_updates.recv() => match update {
+                Ok(currency_id) if currency_id != collateral_currency => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+                // our exchange rate was updated, or we missed updates
+                _ => {}
+            },
+            _ = timer.tick() => {}
+        }
+        let _guard = lock.lock().await;
+        defend_vault(
+            &parachain_rpc,
+            &vault_id,
+            top_up,
+            replace,
+            &notifier,
+            &mut requested_replacement,
+        )
+        .await;
+    }
+}
+
+/// Deposits collateral whenever the collateralization of one of our vaults drops below
+/// the top-up threshold, and requests replacement if that is not possible, in which case
+/// the operator is notified. Every vault is defended by its own monitor, which is started
+/// once the vault is registered. The periodic check also rebalances excess collateral, if
+/// enabled. The replacements requested by this policy are only tracked in memory, so those
+/// outstanding when the vault restarts are not withdrawn.
+pub async fn maintain_collateralization(
+    parachain_rpc: InterBtcParachain,
+    vault_id_manager: VaultIdManager,
+    top_up: Option<CollateralTopUp>,
+    replace: Option<ProactiveReplace>,
+    rebalance: Option<CollateralRebalance>,
+    notifier: Notifier,
+    check_interval: Duration,
+) -> Result<(), ServiceError> {
+    let (price_tx, _) = broadcast::channel(PRICE_UPDATE_CAPACITY);
+    let lock = Arc::new(Mutex::new(()));
+    let price_tx = &price_tx;
+
+    let price_listener = parachain_rpc.on_event::<FeedValuesEvent, _, _, _>(
+        |event| async move {
+            for (key, _value) in event.values.iter() {
+                if let OracleKey::ExchangeRate(currency_id) = key {
+                    // fails if no monitor is running yet
+                    let _ = price_tx.send(currency_id.clone());
+                }
+            }
+        },
+        |error| tracing::error!("Error reading FeedValues event: {}", error.to_string()),
+    );
+
+    let poller = async {
+        // the monitors are stopped when this service is
+        let mut monitors = HashMap::new();
+        let mut limit = rebalance.map(|policy| ActionLimit::new(policy.max_actions, policy.period));
+        loop {
+            let vault_ids = vault_id_manager.get_vault_ids().await;
+            for vault_id in vault_ids.iter() {
+                monitors.entry(vault_id.clone()).or_insert_with(|| {
+                    AbortOnDrop(tokio::spawn(monitor_vault(
+                        parachain_rpc.clone(),
+                        vault_id.clone(),
+                        top_up,
+                        replace,
+                        notifier.clone(),
+                        price_tx.subscribe(),
+                        check_interval,
+                        lock.clone(),
+                    )))
+                });
+            }
+
+            sleep(check_interval).await;
+            if let (Some(policy), Some(limit)) = (rebalance.as_ref(), limit.as_mut()) {
+                let _guard = lock.lock().await;
+                if let Err(err) = rebalance_collateral(&parachain_rpc, &vault_ids, policy, limit).await {
+                    tracing::error!("Failed to rebalance collateral: {}", err);
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        result = price_listener => result?,
+        _ = poller => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_up_amount() {
+        let top_up = CollateralTopUp::new(1.5, Some(2.0));
+
+        // above the threshold
+        assert_eq!(top_up.amount(1_000, FixedU128::from_float(1.6)), None);
+        assert_eq!(top_up.amount(1_000, FixedU128::from_float(1.5)), None);
+        // 1000 collateral at 125% requires another 600 to reach 200%
+        assert_eq!(top_up.amount(1_000, FixedU128::from_float(1.25)), Some(600));
+        assert_eq!(top_up.amount(1_000, FixedU128::from_float(1.0)), Some(1_000));
+    }
+
+    #[test]
+    fn test_replace_amount() {
+        let replace = ProactiveReplace::new(1.6, 2.0);
+
+        assert_eq!(replace.amount(1_000, FixedU128::from_float(1.6)), None);
+        // replacing a quarter of the 1000 issued tokens brings 150% back to 200%
+        assert_eq!(replace.amount(1_000, FixedU128::from_float(1.5)), Some(250));
+        assert_eq!(replace.amount(1_000, FixedU128::from_float(1.0)), Some(500));
+    }
+
+    #[test]
+    fn test_collateral_to_target() {
+        let target = FixedU128::from_float(2.0);
+        // 1000 collateral at 400% has 500 in excess of 200%
+        assert_eq!(
+            collateral_to_target(1_000, FixedU128::from_float(4.0), target),
+            Some(500)
+        );
+        assert_eq!(collateral_to_target(1_000, target, target), Some(0));
+        assert_eq!(collateral_to_target(1_000, FixedU128::from_inner(0), target), None);
+    }
+
+    #[test]
+    fn test_action_limit() {
+        let mut limit = ActionLimit::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(limit.try_acquire(start));
+        assert!(limit.try_acquire(start + Duration::from_secs(10)));
+        assert!(!limit.try_acquire(start + Duration::from_secs(20)));
+        // the first action leaves the window
+        assert!(limit.try_acquire(start + Duration::from_secs(60)));
+        assert!(!limit.try_acquire(start + Duration::from_secs(65)));
+        assert!(limit.try_acquire(start + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_rebalance_target_is_capped() {
+        let policy = CollateralRebalance::new(3.0, 3.5, 1, Duration::from_secs(60), false);
+        assert_eq!(policy.target, policy.upper_threshold);
+    }
+
+    #[test]
+    fn test_rebalance_target_must_be_above_top_up_target() {
+        let policy = CollateralRebalance::new(3.0, 2.5, 1, Duration::from_secs(60), false);
+        assert!(policy.validate(None).is_ok());
+        assert!(policy.validate(Some(&CollateralTopUp::new(1.5, Some(2.0)))).is_ok());
+        assert!(matches!(
+            policy.validate(Some(&CollateralTopUp::new(1.5, Some(2.5)))),
+            Err(Error::InvalidConfig(_))
+        ));
+        // the top-up target defaults to its threshold
+        assert!(policy.validate(Some(&CollateralTopUp::new(2.6, None))).is_err());
+    }
+
+    #[test]
+    fn test_target_defaults_to_threshold() {
+        let top_up = CollateralTopUp::new(2.0, None);
+        assert_eq!(top_up.target, top_up.threshold);
+        assert_eq!(top_up.amount(1_000, FixedU128::from_float(1.0)), Some(1_000));
+
+        // the target can not be below the threshold
+        assert_eq!(CollateralTopUp::new(2.0, Some(1.5)).target, FixedU128::from_float(2.0));
+    }
+}
@@ -0,0 +1,121 @@
+//! Escalation of bitcoin payments that are still unconfirmed as the deadline of their
+//! request approaches.
+
+use crate::notify::Notifier;
+use bitcoin::SatPerVbyte;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EscalationStage {
+    /// Log a warning.
+    Warn,
+    /// Bump the fee beyond the oracle estimate.
+    FeeBump,
+    /// Keep bumping, and alert the operator since manual intervention (e.g. CPFP) may be needed.
+    Alert,
+}
+
+/// When to escalate an unconfirmed payment. Each stage starts once the slack, i.e. the
+/// time to the deadline minus the time the payment still needs to confirm, drops below
+/// its threshold.
+#[derive(Clone)]
+pub struct DeadlineEscalation {
+    pub warn_slack: Duration,
+    pub fee_bump_slack: Duration,
+    pub alert_slack: Duration,
+    /// Multiple of the oracle fee estimate to bump to.
+    pub fee_bump_multiplier: f64,
+    /// Interval in which payments are checked.
+    pub check_interval: Duration,
+    pub notifier: Notifier,
+}
+
+impl Default for DeadlineEscalation {
+    /// Never escalates.
+    fn default() -> Self {
+        Self {
+            warn_slack: Duration::ZERO,
+            fee_bump_slack: Duration::ZERO,
+            alert_slack: Duration::ZERO,
+            fee_bump_multiplier: 1.0,
+            check_interval: Duration::from_secs(60),
+            notifier: Notifier::default(),
+        }
+    }
+}
+
+impl DeadlineEscalation {
+    /// Sets the thresholds of the stages as multiples of the payment margin.
+    pub fn new(
+        payment_margin: Duration,
+        warn_factor: f64,
+        fee_bump_factor: f64,
+        alert_factor: f64,
+        fee_bump_multiplier: f64,
+        notifier: Notifier,
+    ) -> Self {
+        Self {
+            warn_slack: payment_margin.mul_f64(warn_factor),
+            fee_bump_slack: payment_margin.mul_f64(fee_bump_factor),
+            alert_slack: payment_margin.mul_f64(alert_factor),
+            fee_bump_multiplier,
+            notifier,
+            ..Default::default()
+        }
+    }
+
+    pub fn stage(&self, time_to_deadline: Duration, time_to_confirm: Duration) -> Option<EscalationStage> {
+        let slack = time_to_deadline.saturating_sub(time_to_confirm);
+        if slack < self.alert_slack {
+            Some(EscalationStage::Alert)
+        } else if slack < self.fee_bump_slack {
+            Some(EscalationStage::FeeBump)
+        } else if slack < self.warn_slack {
+            Some(EscalationStage::Warn)
+        } else {
+            None
+        }
+    }
+
+    /// The fee rate to bump payments at risk to. Payments are only bumped if their fee rate
+    /// is lower, so that repeated checks don't keep raising it.
+    pub fn forced_fee_rate(&self, estimate: SatPerVbyte) -> SatPerVbyte {
+        SatPerVbyte((estimate.0 as f64 * self.fee_bump_multiplier).ceil() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_stage() {
+        let escalation = DeadlineEscalation::new(MINUTE * 120, 1.0, 0.5, 0.25, 1.5, Notifier::default());
+        assert_eq!(escalation.stage(MINUTE * 200, MINUTE * 60), None);
+        assert_eq!(escalation.stage(MINUTE * 170, MINUTE * 60), Some(EscalationStage::Warn));
+        assert_eq!(
+            escalation.stage(MINUTE * 110, MINUTE * 60),
+            Some(EscalationStage::FeeBump)
+        );
+        assert_eq!(escalation.stage(MINUTE * 80, MINUTE * 60), Some(EscalationStage::Alert));
+        // past the deadline
+        assert_eq!(escalation.stage(MINUTE, MINUTE * 60), Some(EscalationStage::Alert));
+    }
+
+    #[test]
+    fn test_default_never_escalates() {
+        assert_eq!(DeadlineEscalation::default().stage(Duration::ZERO, MINUTE), None);
+    }
+
+    #[test]
+    fn test_forced_fee_rate() {
+        let escalation = DeadlineEscalation {
+            fee_bump_multiplier: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(escalation.forced_fee_rate(SatPerVbyte(10)), SatPerVbyte(15));
+        assert_eq!(escalation.forced_fee_rate(SatPerVbyte(3)), SatPerVbyte(5));
+    }
+}
@@ -0,0 +1,171 @@
+//! Drain mode, which winds down all vaults of the account so that the client can be
+//! stopped without leaving any requests or collateral behind.
+
+use crate::{error::Error, VaultIdManager};
+use futures::future;
+use runtime::{
+    Error as RuntimeError, InterBtcParachain, InterBtcVault, PrettyPrint, ReplacePallet, VaultId, VaultRegistryPallet,
+    VaultStatus,
+};
+use service::Error as ServiceError;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The next action needed to drain a single vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DrainStep {
+    /// Request replacement of the given amount of issued tokens.
+    RequestReplace(u128),
+    /// Wait for open issue, redeem and replace requests to settle.
+    WaitForSettlement,
+    /// No tokens remain - the collateral can be withdrawn.
+    WithdrawCollateral,
+}
+
+impl DrainStep {
+    fn from_vault(vault: &InterBtcVault) -> Self {
+        // tokens that are being redeemed or are already up for replacement can not be requested
+        let replaceable = vault
+            .issued_tokens
+            .saturating_sub(vault.to_be_redeemed_tokens)
+            .saturating_sub(vault.to_be_replaced_tokens);
+        if replaceable > 0 {
+            DrainStep::RequestReplace(replaceable)
+        } else if vault.issued_tokens > 0 || vault.to_be_issued_tokens > 0 || vault.to_be_redeemed_tokens > 0 {
+            DrainStep::WaitForSettlement
+        } else {
+            DrainStep::WithdrawCollateral
+        }
+    }
+}
+
+/// Takes the next step to drain the vault. Returns true once it has neither tokens nor
+/// collateral left.
+async fn drain_vault<P: VaultRegistryPallet + ReplacePallet>(
+    parachain_rpc: &P,
+    vault_id: &VaultId,
+) -> Result<bool, Error> {
+    let vault = match parachain_rpc.get_vault(vault_id).await {
+        Ok(vault) => vault,
+        Err(RuntimeError::VaultLiquidated) => {
+            tracing::warn!("[{}] Vault is liquidated - nothing to drain", vault_id.pretty_print());
+            return Ok(true);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // new issues would keep raising the issued tokens, so that the vault never drains
+    if matches!(vault.status, VaultStatus::Active(true)) {
+        tracing::info!("[{}] No longer accepting new issues", vault_id.pretty_print());
+        parachain_rpc.set_accept_new_issues(vault_id, false).await?;
+    }
+
+    match DrainStep::from_vault(&vault) {
+        DrainStep::RequestReplace(amount) => {
+            tracing::info!(
+                "[{}] Requesting replacement of {} issued tokens",
+                vault_id.pretty_print(),
+                amount
+            );
+            parachain_rpc.request_replace(vault_id, amount).await?;
+            Ok(false)
+        }
+        DrainStep::WaitForSettlement => {
+            tracing::info!(
+                "[{}] Waiting for open requests to settle - issued: {}, to be issued: {}, to be redeemed: {}, to be replaced: {}",
+                vault_id.pretty_print(),
+                vault.issued_tokens,
+                vault.to_be_issued_tokens,
+                vault.to_be_redeemed_tokens,
+                vault.to_be_replaced_tokens
+            );
+            Ok(false)
+        }
+        DrainStep::WithdrawCollateral => {
+            let total_collateral = parachain_rpc.get_vault_total_collateral(vault_id.clone()).await?;
+            if total_collateral > 0 {
+                tracing::info!(
+                    "[{}] All requests settled - withdrawing {} collateral",
+                    vault_id.pretty_print(),
+                    total_collateral
+                );
+                parachain_rpc.withdraw_collateral(vault_id, total_collateral).await?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Drains all vaults of the account: requests replacement of all issued tokens, waits
+/// for every request to settle and withdraws the collateral. Redeems and replace payments
+/// are handled by the regular listeners in the meantime. Once done, the vaults stay idle
+/// until the client is stopped.
+pub async fn drain_vaults(
+    parachain_rpc: InterBtcParachain,
+    vault_id_manager: VaultIdManager,
+) -> Result<(), ServiceError> {
+    tracing::info!("Draining all vaults - no new issue or replace requests will be accepted");
+    loop {
+        let mut drained = true;
+        for vault_id in vault_id_manager.get_vault_ids().await {
+            match drain_vault(&parachain_rpc, &vault_id).await {
+                Ok(true) => {}
+                Ok(false) => drained = false,
+                Err(err) => {
+                    tracing::error!("[{}] Failed to drain vault: {}", vault_id.pretty_print(), err);
+                    drained = false;
+                }
+            }
+        }
+        if drained {
+            break;
+        }
+        sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    tracing::info!("All vaults are drained - it is now safe to stop the vault client");
+    // returning would shut down the other tasks, which then restart the service
+    future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::{AccountId, Token, VaultStatus, DOT, IBTC};
+
+    fn vault(issued: u128, to_be_issued: u128, to_be_redeemed: u128, to_be_replaced: u128) -> InterBtcVault {
+        InterBtcVault {
+            id: VaultId::new(AccountId::new([1u8; 32]), Token(DOT), Token(IBTC)),
+            status: VaultStatus::Active(true),
+            banned_until: None,
+            secure_collateral_threshold: None,
+            to_be_issued_tokens: to_be_issued,
+            issued_tokens: issued,
+            to_be_redeemed_tokens: to_be_redeemed,
+            to_be_replaced_tokens: to_be_replaced,
+            replace_collateral: 0,
+            liquidated_collateral: 0,
+            active_replace_collateral: 0,
+        }
+    }
+
+    #[test]
+    fn test_drain_step() {
+        assert_eq!(
+            DrainStep::from_vault(&vault(100, 0, 0, 0)),
+            DrainStep::RequestReplace(100)
+        );
+        assert_eq!(
+            DrainStep::from_vault(&vault(100, 0, 30, 20)),
+            DrainStep::RequestReplace(50)
+        );
+        assert_eq!(
+            DrainStep::from_vault(&vault(100, 0, 30, 70)),
+            DrainStep::WaitForSettlement
+        );
+        assert_eq!(DrainStep::from_vault(&vault(0, 10, 0, 0)), DrainStep::WaitForSettlement);
+        assert_eq!(DrainStep::from_vault(&vault(0, 0, 0, 0)), DrainStep::WithdrawCollateral);
+    }
+}
@@ -0,0 +1,478 @@
+//! Dry-run mode, in which all listeners run normally but every side effect - bitcoin
+//! payments and extrinsics - is logged as a "would have done" record instead. Running two
+//! versions against the same chain and diffing their records shows how they differ.
+
+use crate::system::VERSION;
+use async_trait::async_trait;
+use bitcoin::{
+    json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Network,
+    PackedLockTime, PrivateKey, PublicKey, SatPerVbyte, Script, Transaction, TransactionExt, TransactionMetadata,
+    TxOut, Txid, WalletTransaction,
+};
+use futures::future;
+use runtime::{DryRunAction, DryRunHook, H256};
+use serde::Serialize;
+use service::DynBitcoinCoreApi;
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BitcoinAction {
+    CreateAndSendTransaction {
+        address: String,
+        amount_sat: u64,
+        fee_rate: u64,
+        request_id: Option<H256>,
+        txid: Txid,
+    },
+    SendToAddress {
+        address: String,
+        amount_sat: u64,
+        fee_rate: u64,
+        request_id: Option<H256>,
+        txid: Txid,
+    },
+    BumpFee {
+        txid: Txid,
+        address: String,
+        fee_rate: u64,
+        new_txid: Txid,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum InterceptedAction {
+    Extrinsic(DryRunAction),
+    Bitcoin(BitcoinAction),
+}
+
+#[derive(Serialize)]
+struct DryRunRecord<'a> {
+    timestamp: u64,
+    version: &'a str,
+    #[serde(flatten)]
+    action: &'a InterceptedAction,
+}
+
+/// Writes a record for every intercepted action to the log, and optionally appends it as
+/// a JSON line to a file.
+#[derive(Debug, Clone, Default)]
+pub struct DryRunLog {
+    path: Option<PathBuf>,
+}
+
+impl DryRunLog {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, action: InterceptedAction) {
+        let record = DryRunRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+            version: VERSION,
+            action: &action,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("Failed to serialize dry-run record: {}", err);
+                return;
+            }
+        };
+        tracing::info!("Dry run - would have done: {}", line);
+        if let Some(path) = &self.path {
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()));
+            if let Err(err) = result {
+                tracing::error!("Failed to write dry-run record to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Hook for [`runtime::InterBtcParachain::with_dry_run`].
+    pub fn extrinsic_hook(&self) -> DryRunHook {
+        let log = self.clone();
+        Arc::new(move |action| log.record(InterceptedAction::Extrinsic(action)))
+    }
+}
+
+/// Bitcoin wallet that records payments and fee bumps instead of broadcasting them. The
+/// simulated transactions never confirm, so requests are not executed.
+pub struct DryRunBitcoinCore {
+    inner: DynBitcoinCoreApi,
+    log: DryRunLog,
+    /// Simulated transactions that are "in the mempool", with their fee rate.
+    simulated: Mutex<HashMap<Txid, (Transaction, SatPerVbyte)>>,
+}
+
+impl DryRunBitcoinCore {
+    pub fn new(inner: DynBitcoinCoreApi, log: DryRunLog) -> Self {
+        Self {
+            inner,
+            log,
+            simulated: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a transaction with the outputs of the payment. It has no inputs, but its txid
+    /// identifies the payment, and its size is close to that of a real payment.
+    fn simulate(&self, address: &Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Txid {
+        let mut output = vec![TxOut {
+            value: sat,
+            script_pubkey: address.script_pubkey(),
+        }];
+        if let Some(request_id) = request_id {
+            output.push(TxOut {
+                value: 0,
+                script_pubkey: Script::new_op_return(request_id.as_bytes()),
+            });
+        }
+        let transaction = Transaction {
+            version: 2,
+            // distinguishes fee bumps of the same payment
+            lock_time: PackedLockTime(fee_rate.0 as u32),
+            input: vec![],
+            output,
+        };
+        let txid = transaction.txid();
+        self.simulated().insert(txid, (transaction, fee_rate));
+        txid
+    }
+
+    fn simulated(&self) -> MutexGuard<'_, HashMap<Txid, (Transaction, SatPerVbyte)>> {
+        self.simulated.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn is_simulated(&self, txid: &Txid) -> bool {
+        self.simulated().contains_key(txid)
+    }
+}
+
+#[async_trait]
+impl BitcoinCoreApi for DryRunBitcoinCore {
+    fn network(&self) -> Network {
+        self.inner.network()
+    }
+
+    async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError> {
+        self.inner.wait_for_block(height, num_confirmations).await
+    }
+
+    async fn get_block_count(&self) -> Result<u64, BitcoinError> {
+        self.inner.get_block_count().await
+    }
+
+    fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError> {
+        self.inner.get_balance(min_confirmations)
+    }
+
+    fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError> {
+        self.inner.list_transactions(max_count)
+    }
+
+    async fn list_wallet_transactions(
+        &self,
+        since_height: Option<u32>,
+    ) -> Result<Vec<WalletTransaction>, BitcoinError> {
+        self.inner.list_wallet_transactions(since_height).await
+    }
+
+    async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError> {
+        self.inner.get_raw_tx(txid, block_hash).await
+    }
+
+    async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError> {
+        if let Some((transaction, _)) = self.simulated().get(txid) {
+            return Ok(transaction.clone());
+        }
+        self.inner.get_transaction(txid, block_hash).await
+    }
+
+    async fn get_proof(&self, txid: Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError> {
+        self.inner.get_proof(txid, block_hash).await
+    }
+
+    async fn get_block_hash(&self, height: u32) -> Result<BlockHash, BitcoinError> {
+        self.inner.get_block_hash(height).await
+    }
+
+    async fn get_new_address(&self) -> Result<Address, BitcoinError> {
+        self.inner.get_new_address().await
+    }
+
+    async fn get_new_public_key(&self) -> Result<PublicKey, BitcoinError> {
+        self.inner.get_new_public_key().await
+    }
+
+    fn dump_derivation_key(&self, public_key: &PublicKey) -> Result<PrivateKey, BitcoinError> {
+        self.inner.dump_derivation_key(public_key)
+    }
+
+    fn import_derivation_key(&self, private_key: &PrivateKey) -> Result<(), BitcoinError> {
+        self.inner.import_derivation_key(private_key)
+    }
+
+    async fn add_new_deposit_key(&self, public_key: PublicKey, secret_key: Vec<u8>) -> Result<(), BitcoinError> {
+        self.inner.add_new_deposit_key(public_key, secret_key).await
+    }
+
+    async fn get_best_block_hash(&self) -> Result<BlockHash, BitcoinError> {
+        self.inner.get_best_block_hash().await
+    }
+
+    async fn get_pruned_height(&self) -> Result<u64, BitcoinError> {
+        self.inner.get_pruned_height().await
+    }
+
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block, BitcoinError> {
+        self.inner.get_block(hash).await
+    }
+
+    async fn get_block_header(&self, hash: &BlockHash) -> Result<BlockHeader, BitcoinError> {
+        self.inner.get_block_header(hash).await
+    }
+
+    async fn get_mempool_transactions<'a>(
+        &'a self,
+    ) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError> {
+        self.inner.get_mempool_transactions().await
+    }
+
+    async fn wait_for_transaction_metadata(
+        &self,
+        txid: Txid,
+        num_confirmations: u32,
+    ) -> Result<TransactionMetadata, BitcoinError> {
+        if self.is_simulated(&txid) {
+            return future::pending().await;
+        }
+        self.inner.wait_for_transaction_metadata(txid, num_confirmations).await
+    }
+
+    async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError> {
+        let simulated = self.simulated().remove(txid);
+        let new_txid = match simulated {
+            Some((transaction, _)) => {
+                let request_id = transaction.get_op_return();
+                let amount = transaction
+                    .output
+                    .first()
+                    .map(|output| output.value)
+                    .unwrap_or_default();
+                self.simulate(&address, amount, fee_rate, request_id)
+            }
+            // a payment made before the dry run - keep waiting for it
+            None => *txid,
+        };
+        self.log.record(InterceptedAction::Bitcoin(BitcoinAction::BumpFee {
+            txid: *txid,
+            address: address.to_string(),
+            fee_rate: fee_rate.0,
+            new_txid,
+        }));
+        Ok(new_txid)
+    }
+
+    async fn create_and_send_transaction(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        request_id: Option<H256>,
+    ) -> Result<Txid, BitcoinError> {
+        let txid = self.simulate(&address, sat, fee_rate, request_id);
+        self.log
+            .record(InterceptedAction::Bitcoin(BitcoinAction::CreateAndSendTransaction {
+                address: address.to_string(),
+                amount_sat: sat,
+                fee_rate: fee_rate.0,
+                request_id,
+                txid,
+            }));
+        Ok(txid)
+    }
+
+    async fn create_and_send_transaction_with_max_fee(
+        &self,
+        address: Address,
+        sat: u64,
+        fee_rate: SatPerVbyte,
+        _max_fee: u64,
+        request_id: Option<H256>,
+    ) -> Result<Txid, BitcoinError> {
+        // the simulated transaction is not funded, so its fee is unknown
+        self.create_and_send_transaction(address, sat, fee_rate, request_id)
+            .await
+    }
+
+    async fn send_to_address(
+        &self,
+        address: Address,
+        sat: u64,
+        request_id: Option<H256>,
+        fee_rate: SatPerVbyte,
+        _num_confirmations: u32,
+    ) -> Result<TransactionMetadata, BitcoinError> {
+        let txid = self.simulate(&address, sat, fee_rate, request_id);
+        self.log
+            .record(InterceptedAction::Bitcoin(BitcoinAction::SendToAddress {
+                address: address.to_string(),
+                amount_sat: sat,
+                fee_rate: fee_rate.0,
+                request_id,
+                txid,
+            }));
+        // the simulated transaction never confirms
+        future::pending().await
+    }
+
+    async fn create_or_load_wallet(&self) -> Result<(), BitcoinError> {
+        self.inner.create_or_load_wallet().await
+    }
+
+    async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError> {
+        self.inner.rescan_blockchain(start_height, end_height).await
+    }
+
+    async fn rescan_electrs_for_addresses(&self, addresses: Vec<Address>) -> Result<(), BitcoinError> {
+        self.inner.rescan_electrs_for_addresses(addresses).await
+    }
+
+    fn get_utxo_count(&self) -> Result<usize, BitcoinError> {
+        self.inner.get_utxo_count()
+    }
+
+    async fn is_in_mempool(&self, txid: Txid) -> Result<bool, BitcoinError> {
+        if self.is_simulated(&txid) {
+            return Ok(true);
+        }
+        self.inner.is_in_mempool(txid).await
+    }
+
+    async fn fee_rate(&self, txid: Txid) -> Result<SatPerVbyte, BitcoinError> {
+        if let Some((_, fee_rate)) = self.simulated().get(&txid) {
+            return Ok(*fee_rate);
+        }
+        self.inner.fee_rate(txid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    mockall::mock! {
+        Bitcoin {}
+
+        #[async_trait]
+        trait BitcoinCoreApi {
+            fn network(&self) -> Network;
+            async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
+            async fn get_block_count(&self) -> Result<u64, BitcoinError>;
+            fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
+            fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
+            async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
+            async fn get_proof(&self, txid: Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_block_hash(&self, height: u32) -> Result<BlockHash, BitcoinError>;
+            async fn get_new_address(&self) -> Result<Address, BitcoinError>;
+            async fn get_new_public_key(&self) -> Result<PublicKey, BitcoinError>;
+            fn dump_derivation_key(&self, public_key: &PublicKey) -> Result<PrivateKey, BitcoinError>;
+            fn import_derivation_key(&self, private_key: &PrivateKey) -> Result<(), BitcoinError>;
+            async fn add_new_deposit_key(&self, public_key: PublicKey, secret_key: Vec<u8>) -> Result<(), BitcoinError>;
+            async fn get_best_block_hash(&self) -> Result<BlockHash, BitcoinError>;
+            async fn get_pruned_height(&self) -> Result<u64, BitcoinError>;
+            async fn get_block(&self, hash: &BlockHash) -> Result<Block, BitcoinError>;
+            async fn get_block_header(&self, hash: &BlockHash) -> Result<BlockHeader, BitcoinError>;
+            async fn get_mempool_transactions<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError>;
+            async fn wait_for_transaction_metadata(&self, txid: Txid, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, max_fee: u64, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn send_to_address(&self, address: Address, sat: u64, request_id: Option<H256>, fee_rate: SatPerVbyte, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn create_or_load_wallet(&self) -> Result<(), BitcoinError>;
+            async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError>;
+            async fn rescan_electrs_for_addresses(&self, addresses: Vec<Address>) -> Result<(), BitcoinError>;
+            fn get_utxo_count(&self) -> Result<usize, BitcoinError>;
+            async fn is_in_mempool(&self, txid: Txid) -> Result<bool, BitcoinError>;
+            async fn fee_rate(&self, txid: Txid) -> Result<SatPerVbyte, BitcoinError>;
+        }
+    }
+
+    fn address() -> Address {
+        Address::from_str("bcrt1q6v2c7q7uv8vu6xle2k9ryfj3y3fuuy4rqnl50f").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_payments_are_simulated() {
+        let path = std::env::temp_dir().join(format!("vault-dry-run-{}.jsonl", std::process::id()));
+        let log = DryRunLog::new(Some(path.clone()));
+        // any call to the wallet that has side effects fails the test
+        let btc_rpc = DryRunBitcoinCore::new(Arc::new(MockBitcoin::default()), log);
+        let request_id = H256::from_low_u64_be(1);
+
+        let txid = btc_rpc
+            .create_and_send_transaction(address(), 10000, SatPerVbyte(5), Some(request_id))
+            .await
+            .unwrap();
+        assert!(btc_rpc.is_in_mempool(txid).await.unwrap());
+        assert_eq!(btc_rpc.fee_rate(txid).await.unwrap(), SatPerVbyte(5));
+        let transaction = btc_rpc.get_transaction(&txid, None).await.unwrap();
+        assert_eq!(transaction.get_op_return(), Some(request_id));
+
+        let new_txid = btc_rpc.bump_fee(&txid, address(), SatPerVbyte(8)).await.unwrap();
+        assert_ne!(new_txid, txid);
+        assert_eq!(btc_rpc.fee_rate(new_txid).await.unwrap(), SatPerVbyte(8));
+        let transaction = btc_rpc.get_transaction(&new_txid, None).await.unwrap();
+        assert_eq!(transaction.get_op_return(), Some(request_id));
+        assert_eq!(transaction.output[0].value, 10000);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["action"], "create_and_send_transaction");
+        assert_eq!(records[0]["amount_sat"], 10000);
+        assert_eq!(records[0]["txid"], txid.to_string());
+        assert_eq!(records[1]["action"], "bump_fee");
+        assert_eq!(records[1]["new_txid"], new_txid.to_string());
+        assert_eq!(records[1]["version"], VERSION);
+    }
+
+    #[test]
+    fn test_extrinsic_record() {
+        let action = InterceptedAction::Extrinsic(DryRunAction::CancelIssue {
+            issue_id: H256::from_low_u64_be(1),
+        });
+        let record = serde_json::to_value(&DryRunRecord {
+            timestamp: 0,
+            version: VERSION,
+            action: &action,
+        })
+        .unwrap();
+        assert_eq!(record["action"], "cancel_issue");
+        assert_eq!(
+            record["issue_id"],
+            serde_json::to_value(H256::from_low_u64_be(1)).unwrap()
+        );
+    }
+}
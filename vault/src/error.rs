@@ -1,7 +1,10 @@
-use bitcoin::Error as BitcoinError;
+use bitcoin::{Error as BitcoinError, KeystoreError};
 use hex::FromHexError;
 use jsonrpc_core_client::RpcError;
+use kv::Error as KvError;
+use lettre::{address::AddressError, error::Error as EmailError, transport::smtp::Error as SmtpError};
 use parity_scale_codec::Error as CodecError;
+use reqwest::Error as ReqwestError;
 use runtime::{Error as RuntimeError, SubxtError};
 use service::Error as ServiceError;
 use thiserror::Error;
@@ -23,6 +26,24 @@ pub enum Error {
     DeadlineExpired,
     #[error("Faucet url not set")]
     FaucetUrlNotSet,
+    #[error("Bitcoin fee exceeds the {0} cap")]
+    FeeCapExceeded(&'static str),
+    #[error("Replace request rejected by policy: {0}")]
+    ReplaceRejected(String),
+    #[error("Invalid API parameters: {0}")]
+    InvalidApiParams(String),
+    #[error("Lost the leader lease")]
+    LeaseLost,
+    #[error("The payment journal is not open")]
+    JournalClosed,
+    #[error("Vault {0} is already registered")]
+    VaultAlreadyRegistered(String),
+    #[error("Invalid key backup: {0}")]
+    InvalidKeyBackup(String),
+    #[error("The key backup does not belong to the public key registered on the parachain")]
+    KeyBackupMismatch,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
 
     #[error("ServiceError: {0}")]
     ServiceError(#[from] ServiceError),
@@ -32,10 +53,26 @@ pub enum Error {
     FromHexError(#[from] FromHexError),
     #[error("BitcoinError: {0}")]
     BitcoinError(#[from] BitcoinError),
+    #[error("KeystoreError: {0}")]
+    KeystoreError(#[from] KeystoreError),
     #[error("RuntimeError: {0}")]
     RuntimeError(#[from] RuntimeError),
     #[error("SubxtError: {0}")]
     SubxtError(#[from] SubxtError),
+    #[error("SerdeJsonError: {0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+    #[error("KvError: {0}")]
+    KvError(#[from] KvError),
+    #[error("ReqwestError: {0}")]
+    ReqwestError(#[from] ReqwestError),
+    #[error("SmtpError: {0}")]
+    SmtpError(#[from] SmtpError),
+    #[error("EmailError: {0}")]
+    EmailError(#[from] EmailError),
+    #[error("AddressError: {0}")]
+    AddressError(#[from] AddressError),
+    #[error("IoError: {0}")]
+    IoError(#[from] std::io::Error),
     #[error("CodecError: {0}")]
     CodecError(#[from] CodecError),
     #[error("BroadcastStreamRecvError: {0}")]
@@ -1,9 +1,17 @@
-use crate::{error::Error, metrics::update_bitcoin_metrics, system::VaultData, VaultIdManager};
+use crate::{
+    deadline::{DeadlineEscalation, EscalationStage},
+    error::Error,
+    journal::{PaymentJournal, PaymentState},
+    metrics::{increment_fee_cap_counter, update_bitcoin_metrics},
+    notify::Notification,
+    system::VaultData,
+    VaultIdManager,
+};
 use bitcoin::{
     Error as BitcoinError, SatPerVbyte, Transaction, TransactionExt, TransactionMetadata, Txid,
     BLOCK_INTERVAL as BITCOIN_BLOCK_INTERVAL,
 };
-use futures::{future::Either, stream::StreamExt, try_join, TryStreamExt};
+use futures::{future::Either, stream::StreamExt, try_join, TryFutureExt, TryStreamExt};
 use runtime::{
     BtcAddress, BtcRelayPallet, Error as RuntimeError, FixedPointNumber, FixedU128, H256Le, InterBtcParachain,
     InterBtcRedeemRequest, InterBtcReplaceRequest, OraclePallet, PartialAddress, PrettyPrint, RedeemPallet,
@@ -11,12 +19,116 @@ use runtime::{
     H256,
 };
 use service::{spawn_cancelable, DynBitcoinCoreApi, Error as ServiceError, ShutdownSender};
-use std::{collections::HashMap, convert::TryInto, time::Duration};
-use tokio::time::sleep;
-use tokio_stream::wrappers::BroadcastStream;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    time::Duration,
+};
+use tokio::time::{interval, sleep};
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
 
 const ON_FORK_RETRY_DELAY: Duration = Duration::from_secs(10);
 
+/// Conservative estimate of the virtual size of a payment (two segwit inputs, payment,
+/// change and OP_RETURN outputs). Used to turn absolute fee caps into a fee rate before
+/// the transaction has been funded - payments that need more inputs are funded again at
+/// the fee rate derived from their real size.
+const ESTIMATED_PAYMENT_VSIZE: u64 = 250;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FeeCapReason {
+    FeeRate,
+    PaymentFraction,
+    FeeBudget,
+}
+
+impl FeeCapReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeeCapReason::FeeRate => "fee_rate",
+            FeeCapReason::PaymentFraction => "payment_fraction",
+            FeeCapReason::FeeBudget => "fee_budget",
+        }
+    }
+}
+
+/// Upper bounds on the bitcoin fees the vault is willing to pay, regardless of the
+/// estimate reported by the oracle.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FeeCaps {
+    /// Absolute fee rate ceiling.
+    pub max_fee_rate: Option<SatPerVbyte>,
+    /// Maximum fee as a fraction of the payment amount.
+    pub max_fee_fraction: Option<f64>,
+    /// Maximum fee as a multiple of the request's fee budget.
+    pub max_fee_budget_multiplier: Option<f64>,
+}
+
+impl FeeCaps {
+    /// Returns the lowest cap on the absolute fee of a payment of `amount`, in satoshis.
+    fn max_fee(&self, amount: u128, fee_budget: Option<u128>) -> Option<u64> {
+        let payment_fraction = self.max_fee_fraction.map(|fraction| amount as f64 * fraction);
+        let fee_budget = self
+            .max_fee_budget_multiplier
+            .zip(fee_budget)
+            .map(|(multiplier, budget)| budget as f64 * multiplier);
+        payment_fraction
+            .into_iter()
+            .chain(fee_budget)
+            .map(|fee| fee as u64)
+            .min()
+    }
+
+    /// Returns the lowest fee rate cap that applies to a transaction of `vsize` paying `amount`,
+    /// together with the cap that imposed it.
+    fn max_fee_rate(&self, amount: u128, fee_budget: Option<u128>, vsize: u64) -> Option<(SatPerVbyte, FeeCapReason)> {
+        let vsize = vsize.max(1) as f64;
+        let to_fee_rate = |fee: f64| SatPerVbyte((fee / vsize) as u64);
+
+        let payment_fraction = self
+            .max_fee_fraction
+            .map(|fraction| (to_fee_rate(amount as f64 * fraction), FeeCapReason::PaymentFraction));
+        let fee_budget = self
+            .max_fee_budget_multiplier
+            .zip(fee_budget)
+            .map(|(multiplier, budget)| (to_fee_rate(budget as f64 * multiplier), FeeCapReason::FeeBudget));
+
+        self.max_fee_rate
+            .map(|rate| (rate, FeeCapReason::FeeRate))
+            .into_iter()
+            .chain(payment_fraction)
+            .chain(fee_budget)
+            .min_by_key(|(rate, _)| rate.0)
+    }
+
+    /// Clamps `fee_rate` to the configured caps. Fails if the caps leave no room for a
+    /// non-zero fee rate.
+    fn apply(
+        &self,
+        fee_rate: SatPerVbyte,
+        amount: u128,
+        fee_budget: Option<u128>,
+        vsize: u64,
+    ) -> Result<SatPerVbyte, Error> {
+        match self.max_fee_rate(amount, fee_budget, vsize) {
+            Some((cap, reason)) if fee_rate > cap => {
+                increment_fee_cap_counter(reason.as_str());
+                if cap.0 == 0 {
+                    return Err(Error::FeeCapExceeded(reason.as_str()));
+                }
+                tracing::warn!(
+                    "Fee rate of {} sat/vByte exceeds the {} cap - using {} sat/vByte instead",
+                    fee_rate.0,
+                    reason.as_str(),
+                    cap.0
+                );
+                Ok(cap)
+            }
+            _ => Ok(fee_rate),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 struct Deadline {
     parachain: u32,
@@ -29,6 +141,8 @@ pub struct Request {
     btc_height: Option<u32>,
     /// Deadline (unit: active block number) after which payments will no longer be attempted.
     deadline: Option<Deadline>,
+    /// Deadline after which the request can be cancelled, i.e. without the payment margin.
+    expiry: Option<Deadline>,
     amount: u128,
     btc_address: BtcAddress,
     request_type: RequestType,
@@ -108,6 +222,12 @@ impl Request {
                 request.period,
                 payment_margin,
             )?),
+            expiry: Some(Self::calculate_deadline(
+                request.opentime,
+                request.btc_height,
+                request.period,
+                Duration::ZERO,
+            )?),
             btc_height: Some(request.btc_height),
             amount: request.amount_btc,
             btc_address: request.btc_address,
@@ -131,6 +251,12 @@ impl Request {
                 request.period,
                 payment_margin,
             )?),
+            expiry: Some(Self::calculate_deadline(
+                request.accept_time,
+                request.btc_height,
+                request.period,
+                Duration::ZERO,
+            )?),
             btc_height: Some(request.btc_height),
             amount: request.amount,
             btc_address: request.btc_address,
@@ -169,27 +295,83 @@ impl Request {
         vault: VaultData,
         num_confirmations: u32,
         auto_rbf: bool,
+        fee_caps: FeeCaps,
+        escalation: DeadlineEscalation,
     ) -> Result<(), Error> {
-        // ensure the deadline has not expired yet
-        if let Some(ref deadline) = self.deadline {
-            if parachain_rpc.get_current_active_block_number().await? >= deadline.parachain
-                && vault.btc_rpc.get_block_count().await? >= deadline.bitcoin as u64
-            {
-                return Err(Error::DeadlineExpired);
+        let txid = match self.find_journaled_payment(&vault.btc_rpc, &vault.journal).await? {
+            Some(txid) => {
+                tracing::info!("Resuming journaled payment {txid} for request #{:?}", self.hash);
+                txid
             }
-        }
+            None => {
+                // ensure the deadline has not expired yet
+                if let Some(ref deadline) = self.deadline {
+                    if parachain_rpc.get_current_active_block_number().await? >= deadline.parachain
+                        && vault.btc_rpc.get_block_count().await? >= deadline.bitcoin as u64
+                    {
+                        return Err(Error::DeadlineExpired);
+                    }
+                }
+
+                self.transfer_btc(&parachain_rpc, &vault.btc_rpc, &vault.journal, fee_caps)
+                    .await?
+            }
+        };
 
         let tx_metadata = self
-            .transfer_btc(
+            .wait_for_inclusion(
                 &parachain_rpc,
                 &vault.btc_rpc,
+                &vault.journal,
                 num_confirmations,
-                self.vault_id.clone(),
+                txid,
                 auto_rbf,
+                fee_caps,
+                &escalation,
             )
             .await?;
         let _ = update_bitcoin_metrics(&vault, tx_metadata.fee, self.fee_budget).await;
-        self.execute(parachain_rpc, tx_metadata).await
+        self.execute(parachain_rpc, tx_metadata).await?;
+        vault.journal.remove(self.hash)
+    }
+
+    /// Looks up a payment for this request that was made before a restart. The wallet
+    /// is only searched if the journaled transaction is not pending anymore, since it may
+    /// have been replaced by a fee bump that was not recorded yet.
+    async fn find_journaled_payment(
+        &self,
+        btc_rpc: &DynBitcoinCoreApi,
+        journal: &PaymentJournal,
+    ) -> Result<Option<Txid>, Error> {
+        let journaled_txid = match journal.get(self.hash)? {
+            None => return Ok(None),
+            Some(PaymentState::Intent) => None,
+            // a confirmed payment can no longer be replaced
+            Some(PaymentState::Confirmed { txid, .. }) => return Ok(Some(txid)),
+            Some(PaymentState::Broadcast { txid }) => Some(txid),
+        };
+
+        if let Some(txid) = journaled_txid {
+            // a replaced transaction is evicted from the mempool, so its fee rate can't be
+            // looked up anymore
+            if matches!(btc_rpc.is_in_mempool(txid).await, Ok(true)) && btc_rpc.fee_rate(txid).await.is_ok() {
+                return Ok(Some(txid));
+            }
+        }
+
+        for transaction in btc_rpc.list_wallet_transactions(None).await? {
+            if transaction.replaced || transaction.amount.to_sat() >= 0 {
+                continue;
+            }
+            let tx = btc_rpc
+                .get_transaction(&transaction.txid, transaction.block.map(|(hash, _)| hash))
+                .await?;
+            if tx.get_op_return() == Some(self.hash) {
+                journal.record_broadcast(self.hash, transaction.txid)?;
+                return Ok(Some(transaction.txid));
+            }
+        }
+        Ok(journaled_txid)
     }
 
     /// Make a bitcoin transfer to fulfil the request
@@ -201,50 +383,192 @@ impl Request {
             request_id = ?self.hash,
         )
     )]
-    async fn transfer_btc<P: OraclePallet + BtcRelayPallet + VaultRegistryPallet + UtilFuncs + Clone + Send + Sync>(
+    async fn transfer_btc<P: OraclePallet + Send + Sync>(
         &self,
         parachain_rpc: &P,
         btc_rpc: &DynBitcoinCoreApi,
-        num_confirmations: u32,
-        vault_id: VaultId,
-        auto_rbf: bool,
-    ) -> Result<TransactionMetadata, Error> {
-        let fee_rate = self.get_fee_rate(parachain_rpc).await?;
+        journal: &PaymentJournal,
+        fee_caps: FeeCaps,
+    ) -> Result<Txid, Error> {
+        let fee_rate = fee_caps.apply(
+            self.get_fee_rate(parachain_rpc).await?,
+            self.amount,
+            self.fee_budget,
+            ESTIMATED_PAYMENT_VSIZE,
+        )?;
 
         tracing::debug!("Using fee_rate = {} sat/vByte", fee_rate.0);
 
-        let txid = btc_rpc
-            .create_and_send_transaction(
-                self.btc_address
-                    .to_address(btc_rpc.network())
-                    .map_err(BitcoinError::ConversionError)?,
-                self.amount as u64,
-                fee_rate,
-                Some(self.hash),
-            )
-            .await?;
+        let address = self
+            .btc_address
+            .to_address(btc_rpc.network())
+            .map_err(BitcoinError::ConversionError)?;
+
+        // record the intent before broadcasting, so that after a crash we know to look
+        // for the payment in the wallet
+        journal.record_intent(self.hash)?;
+        let txid = match fee_caps.max_fee(self.amount, self.fee_budget) {
+            None => {
+                btc_rpc
+                    .create_and_send_transaction(address, self.amount as u64, fee_rate, Some(self.hash))
+                    .await?
+            }
+            Some(max_fee) => {
+                match btc_rpc
+                    .create_and_send_transaction_with_max_fee(
+                        address.clone(),
+                        self.amount as u64,
+                        fee_rate,
+                        max_fee,
+                        Some(self.hash),
+                    )
+                    .await
+                {
+                    Err(BitcoinError::FeeExceedsMax(vsize)) => {
+                        // the payment needs more inputs than estimated
+                        let fee_rate = fee_caps.apply(fee_rate, self.amount, self.fee_budget, vsize)?;
+                        tracing::debug!(
+                            "Funding the payment of {} vbytes again at {} sat/vByte",
+                            vsize,
+                            fee_rate.0
+                        );
+                        btc_rpc
+                            .create_and_send_transaction_with_max_fee(
+                                address,
+                                self.amount as u64,
+                                fee_rate,
+                                max_fee,
+                                Some(self.hash),
+                            )
+                            .await?
+                    }
+                    result => result?,
+                }
+            }
+        };
+        journal.record_broadcast(self.hash, txid)?;
 
-        self.wait_for_inclusion(parachain_rpc, btc_rpc, num_confirmations, txid, auto_rbf)
-            .await
+        Ok(txid)
     }
 
+    /// Clamps the fee rate of a fee bump to the configured caps, using the size of the
+    /// transaction that is being replaced.
+    async fn capped_bump_fee_rate(
+        &self,
+        btc_rpc: &DynBitcoinCoreApi,
+        txid: Txid,
+        fee_rate: SatPerVbyte,
+        fee_caps: FeeCaps,
+    ) -> Result<SatPerVbyte, Error> {
+        let tx = btc_rpc.get_transaction(&txid, None).await?;
+        // round up when converting from weight to vsize
+        let vsize = (tx.weight() as u64).saturating_add(3) / 4;
+        fee_caps.apply(fee_rate, self.amount, self.fee_budget, vsize)
+    }
+
+    /// Escalates if the payment might not confirm before the request expires. Returns the
+    /// current and the forced fee rate if the payment should be bumped.
+    async fn check_deadline<P: OraclePallet + SecurityPallet + Send + Sync>(
+        &self,
+        parachain_rpc: &P,
+        btc_rpc: &DynBitcoinCoreApi,
+        txid: Txid,
+        num_confirmations: u32,
+        escalation: &DeadlineEscalation,
+    ) -> Option<Result<(SatPerVbyte, SatPerVbyte), Error>> {
+        let expiry = self.expiry.as_ref()?;
+        // once included, the payment can no longer be bumped
+        match btc_rpc.is_in_mempool(txid).await {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => {
+                tracing::warn!("Failed to check the deadline of payment {txid}: {}", err);
+                return None;
+            }
+        }
+        let (parachain_height, bitcoin_height) = match try_join!(
+            parachain_rpc.get_current_active_block_number().map_err(Error::from),
+            btc_rpc.get_block_count().map_err(Error::from),
+        ) {
+            Ok(x) => x,
+            Err(err) => {
+                tracing::warn!("Failed to check the deadline of payment {txid}: {}", err);
+                return None;
+            }
+        };
+
+        let time_to_parachain_deadline = runtime::BLOCK_INTERVAL * expiry.parachain.saturating_sub(parachain_height);
+        let time_to_bitcoin_deadline = BITCOIN_BLOCK_INTERVAL
+            * (expiry.bitcoin as u64)
+                .saturating_sub(bitcoin_height)
+                .try_into()
+                .unwrap_or(u32::MAX);
+        // the request expires once both deadlines have passed
+        let time_to_deadline = time_to_parachain_deadline.max(time_to_bitcoin_deadline);
+        let time_to_confirm = BITCOIN_BLOCK_INTERVAL * num_confirmations.max(1);
+
+        match escalation.stage(time_to_deadline, time_to_confirm)? {
+            EscalationStage::Warn => {
+                tracing::warn!(
+                    "Payment {txid} is unconfirmed with {} minutes to the deadline",
+                    time_to_deadline.as_secs() / 60
+                );
+                return None;
+            }
+            EscalationStage::FeeBump => {
+                tracing::warn!(
+                    "Payment {txid} is unconfirmed with {} minutes to the deadline - forcing a fee bump",
+                    time_to_deadline.as_secs() / 60
+                );
+            }
+            EscalationStage::Alert => {
+                tracing::error!(
+                    "CRITICAL: payment {txid} is unconfirmed with {} minutes to the deadline - manual intervention (e.g. CPFP) may be needed",
+                    time_to_deadline.as_secs() / 60
+                );
+                escalation.notifier.notify(Notification::DeadlineAtRisk {
+                    vault_id: self.vault_id.pretty_print(),
+                    request_id: self.hash,
+                });
+            }
+        }
+
+        let current_fee_rate = match btc_rpc.fee_rate(txid).await {
+            Ok(x) => x,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let forced_fee_rate = match self.get_fee_rate(parachain_rpc).await {
+            Ok(estimate) => escalation.forced_fee_rate(estimate),
+            Err(err) => return Some(Err(err)),
+        };
+        if forced_fee_rate > current_fee_rate {
+            Some(Ok((current_fee_rate, forced_fee_rate)))
+        } else {
+            None
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     #[tracing::instrument(
         name = "wait_for_inclusion",
-        skip(self, parachain_rpc, btc_rpc),
+        skip(self, parachain_rpc, btc_rpc, journal, escalation),
         fields(
             request_type = ?self.request_type,
             request_id = ?self.hash,
         )
     )]
     async fn wait_for_inclusion<
-        P: OraclePallet + BtcRelayPallet + VaultRegistryPallet + UtilFuncs + Clone + Send + Sync,
+        P: OraclePallet + BtcRelayPallet + VaultRegistryPallet + SecurityPallet + UtilFuncs + Clone + Send + Sync,
     >(
         &self,
         parachain_rpc: &P,
         btc_rpc: &DynBitcoinCoreApi,
+        journal: &PaymentJournal,
         num_confirmations: u32,
         mut txid: Txid,
         auto_rbf: bool,
+        fee_caps: FeeCaps,
+        escalation: &DeadlineEscalation,
     ) -> Result<TransactionMetadata, Error> {
         'outer: loop {
             tracing::info!("Awaiting bitcoin confirmations for {txid}");
@@ -298,6 +622,10 @@ impl Request {
                         }
                     }
                 });
+            // payments close to the deadline are bumped regardless of auto-rbf
+            let forced_bumps = IntervalStream::new(interval(escalation.check_interval))
+                .filter_map(|_| self.check_deadline(parachain_rpc, btc_rpc, txid_copy, num_confirmations, escalation));
+            let subscription = futures::stream::select(subscription, forced_bumps);
 
             let wait_for_transaction_metadata = btc_rpc.wait_for_transaction_metadata(txid, num_confirmations);
             futures::pin_mut!(subscription);
@@ -317,6 +645,22 @@ impl Request {
                         metadata_fut = continuation;
                     }
                     Either::Right((Some(Ok((old_fee, new_fee))), continuation)) => {
+                        let new_fee = match self.capped_bump_fee_rate(btc_rpc, txid, new_fee, fee_caps).await {
+                            Ok(x) if x > old_fee => x,
+                            Ok(_) => {
+                                tracing::info!(
+                                    "Not bumping fee rate: the capped fee rate is not higher than {}",
+                                    old_fee.0
+                                );
+                                metadata_fut = continuation;
+                                continue;
+                            }
+                            Err(x) => {
+                                tracing::warn!("Not bumping fee rate: {}", x);
+                                metadata_fut = continuation;
+                                continue;
+                            }
+                        };
                         tracing::debug!("Attempting to bump fee rate from {} to {}...", old_fee.0, new_fee.0);
                         match btc_rpc
                             .bump_fee(
@@ -330,6 +674,7 @@ impl Request {
                         {
                             Ok(new_txid) => {
                                 tracing::info!("Bumped fee rate. Old txid = {txid}, new txid = {new_txid}");
+                                journal.record_broadcast(self.hash, new_txid)?;
                                 txid = new_txid;
                                 continue 'outer;
                             }
@@ -360,6 +705,7 @@ impl Request {
             {
                 Ok(_) => {
                     tracing::info!("Bitcoin successfully sent and relayed");
+                    journal.record_confirmed(self.hash, txid, tx_metadata.block_hash)?;
                     return Ok(tx_metadata);
                 }
                 Err(e) if e.is_invalid_chain_id() => {
@@ -428,6 +774,8 @@ pub async fn execute_open_requests(
     num_confirmations: u32,
     payment_margin: Duration,
     auto_rbf: bool,
+    fee_caps: FeeCaps,
+    escalation: DeadlineEscalation,
 ) -> Result<(), ServiceError> {
     let parachain_rpc = &parachain_rpc;
     let vault_id = parachain_rpc.get_account_id().clone();
@@ -438,6 +786,27 @@ pub async fn execute_open_requests(
         parachain_rpc.get_old_vault_replace_requests(vault_id.clone()),
     )?;
 
+    // the payments of requests that were cancelled or expired don't need to be resumed
+    let pending_ids: HashSet<_> = redeem_requests
+        .iter()
+        .filter(|(_, request)| request.status == RedeemRequestStatus::Pending)
+        .map(|(hash, _)| *hash)
+        .chain(
+            replace_requests
+                .iter()
+                .filter(|(_, request)| request.status == ReplaceRequestStatus::Pending)
+                .map(|(hash, _)| *hash),
+        )
+        .collect();
+    match vault_id_manager.journal().remove_unless_pending(&pending_ids) {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Removed {count} journal entries of requests that are no longer pending"),
+        Err(e) => tracing::warn!(
+            "Failed to remove journal entries of requests that are no longer pending: {}",
+            e
+        ),
+    }
+
     let open_redeems = redeem_requests
         .into_iter()
         .filter(|(_, request)| request.status == RedeemRequestStatus::Pending)
@@ -490,9 +859,10 @@ pub async fn execute_open_requests(
             // start a new task to (potentially) await confirmation and to execute on the parachain
             // make copies of the variables we move into the task
             let parachain_rpc = parachain_rpc.clone();
-            let btc_rpc = vault_id_manager.clone();
+            let vault_id_manager = vault_id_manager.clone();
+            let escalation = escalation.clone();
             spawn_cancelable(shutdown_tx.subscribe(), async move {
-                let btc_rpc = match btc_rpc.get_bitcoin_rpc(&request.vault_id).await {
+                let vault = match vault_id_manager.get_vault(&request.vault_id).await {
                     Some(x) => x,
                     None => {
                         tracing::error!(
@@ -503,15 +873,31 @@ pub async fn execute_open_requests(
                     }
                 };
 
+                if let Err(e) = vault.journal.record_broadcast(request.hash, tx.txid()) {
+                    tracing::warn!("Failed to journal payment for request #{}: {}", request.hash, e);
+                }
+
                 match request
-                    .wait_for_inclusion(&parachain_rpc, &btc_rpc, num_confirmations, tx.txid(), auto_rbf)
+                    .wait_for_inclusion(
+                        &parachain_rpc,
+                        &vault.btc_rpc,
+                        &vault.journal,
+                        num_confirmations,
+                        tx.txid(),
+                        auto_rbf,
+                        fee_caps,
+                        &escalation,
+                    )
                     .await
                 {
-                    Ok(tx_metadata) => {
-                        if let Err(e) = request.execute(parachain_rpc.clone(), tx_metadata).await {
-                            tracing::error!("Failed to execute request #{}: {}", request.hash, e);
+                    Ok(tx_metadata) => match request.execute(parachain_rpc.clone(), tx_metadata).await {
+                        Ok(_) => {
+                            if let Err(e) = vault.journal.remove(request.hash) {
+                                tracing::warn!("Failed to remove journal entry of request #{}: {}", request.hash, e);
+                            }
                         }
-                    }
+                        Err(e) => tracing::error!("Failed to execute request #{}: {}", request.hash, e),
+                    },
                     Err(e) => {
                         tracing::error!("Error while waiting for inclusion for request #{}: {}", request.hash, e);
                     }
@@ -529,6 +915,7 @@ pub async fn execute_open_requests(
         // make copies of the variables we move into the task
         let parachain_rpc = parachain_rpc.clone();
         let vault_id_manager = vault_id_manager.clone();
+        let escalation = escalation.clone();
         spawn_cancelable(shutdown_tx.subscribe(), async move {
             let vault = match vault_id_manager.get_vault(&request.vault_id).await {
                 Some(x) => x,
@@ -548,7 +935,7 @@ pub async fn execute_open_requests(
             );
 
             match request
-                .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf)
+                .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf, fee_caps, escalation)
                 .await
             {
                 Ok(_) => tracing::info!(
@@ -589,7 +976,7 @@ mod tests {
     use async_trait::async_trait;
     use bitcoin::{
         json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Hash, Network,
-        PrivateKey, PublicKey, Transaction, TransactionMetadata, Txid,
+        PrivateKey, PublicKey, Transaction, TransactionMetadata, Txid, WalletTransaction,
     };
     use jsonrpc_core::serde_json::{Map, Value};
     use runtime::{
@@ -634,6 +1021,7 @@ mod tests {
             async fn register_vault(&self, vault_id: &VaultId, collateral: u128) -> Result<(), RuntimeError>;
             async fn deposit_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
             async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), RuntimeError>;
             async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, RuntimeError>;
             async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), RuntimeError>;
             async fn get_required_collateral_for_wrapped(&self, amount_btc: u128, collateral_currency: CurrencyId) -> Result<u128, RuntimeError>;
@@ -719,6 +1107,7 @@ mod tests {
             async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
             fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
             fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
             async fn get_block_count(&self) -> Result<u64, BitcoinError>;
             async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
             async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
@@ -736,6 +1125,7 @@ mod tests {
             async fn get_mempool_transactions<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError>;
             async fn wait_for_transaction_metadata(&self, txid: Txid, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
             async fn create_and_send_transaction(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, max_fee: u64, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
             async fn send_to_address(&self, address: Address, sat: u64, request_id: Option<H256>, fee_rate: SatPerVbyte, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
             async fn create_or_load_wallet(&self) -> Result<(), BitcoinError>;
             async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError>;
@@ -809,6 +1199,169 @@ mod tests {
         assert_err!(Request::calculate_deadline(0, 0, 0, margin), Error::ArithmeticUnderflow);
     }
 
+    #[test]
+    fn fee_caps_use_the_lowest_applicable_cap() {
+        let fee_caps = FeeCaps {
+            max_fee_rate: Some(SatPerVbyte(50)),
+            max_fee_fraction: Some(0.01),
+            max_fee_budget_multiplier: Some(2.0),
+        };
+
+        // 1% of 1_000_000 sat over 250 vbytes = 40 sat/vbyte
+        assert_eq!(
+            fee_caps.max_fee_rate(1_000_000, None, 250),
+            Some((SatPerVbyte(40), FeeCapReason::PaymentFraction))
+        );
+        // 2 * 2_500 sat over 250 vbytes = 20 sat/vbyte
+        assert_eq!(
+            fee_caps.max_fee_rate(1_000_000, Some(2_500), 250),
+            Some((SatPerVbyte(20), FeeCapReason::FeeBudget))
+        );
+        assert_eq!(
+            fee_caps.max_fee_rate(100_000_000, None, 250),
+            Some((SatPerVbyte(50), FeeCapReason::FeeRate))
+        );
+        assert_eq!(FeeCaps::default().max_fee_rate(1_000_000, Some(2_500), 250), None);
+    }
+
+    #[test]
+    fn fee_caps_clamp_or_refuse() {
+        let fee_caps = FeeCaps {
+            max_fee_rate: Some(SatPerVbyte(50)),
+            ..Default::default()
+        };
+        assert_ok!(fee_caps.apply(SatPerVbyte(10), 1_000, None, 250), SatPerVbyte(10));
+        assert_ok!(fee_caps.apply(SatPerVbyte(1_000), 1_000, None, 250), SatPerVbyte(50));
+
+        let fee_caps = FeeCaps {
+            max_fee_fraction: Some(0.01),
+            ..Default::default()
+        };
+        // 1% of 1_000 sat is less than 1 sat/vbyte, so no valid fee rate remains
+        assert_err!(
+            fee_caps.apply(SatPerVbyte(10), 1_000, None, 250),
+            Error::FeeCapExceeded("payment_fraction")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pay_with_capped_fee_rate() {
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_get_bitcoin_fees()
+            .returning(move || Ok(FixedU128::from(1000)));
+        parachain_rpc
+            .expect_get_current_active_block_number()
+            .returning(|| Ok(50));
+        parachain_rpc.expect_execute_redeem().returning(|_, _, _| Ok(()));
+        parachain_rpc.expect_wait_for_block_in_relay().returning(|_, _| Ok(()));
+        parachain_rpc
+            .expect_on_fee_rate_change()
+            .returning(|| tokio::sync::broadcast::channel(2).1);
+
+        let mut mock_bitcoin = MockBitcoin::default();
+        mock_bitcoin.expect_network().returning(|| Network::Regtest);
+        mock_bitcoin
+            .expect_create_and_send_transaction()
+            .withf(|_, _, fee_rate, _| fee_rate == &SatPerVbyte(20))
+            .times(1)
+            .returning(|_, _, _, _| Ok(Txid::all_zeros()));
+        mock_bitcoin.expect_wait_for_transaction_metadata().returning(|_, _| {
+            Ok(TransactionMetadata {
+                txid: Txid::all_zeros(),
+                proof: vec![],
+                raw_tx: vec![],
+                block_height: 0,
+                block_hash: BlockHash::all_zeros(),
+                fee: None,
+            })
+        });
+        mock_bitcoin.expect_get_balance().returning(|_| Ok(Amount::ZERO));
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin);
+
+        let request = Request {
+            amount: 100,
+            deadline: None,
+            expiry: None,
+            btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
+            hash: H256::from_slice(&[1; 32]),
+            btc_height: None,
+            request_type: RequestType::Redeem,
+            vault_id: dummy_vault_id(),
+            fee_budget: None,
+        };
+
+        let vault_data = VaultData {
+            vault_id: dummy_vault_id(),
+            btc_rpc,
+            metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
+        };
+
+        let fee_caps = FeeCaps {
+            max_fee_rate: Some(SatPerVbyte(20)),
+            ..Default::default()
+        };
+        assert_ok!(
+            request
+                .pay_and_execute(
+                    parachain_rpc,
+                    vault_data,
+                    6,
+                    true,
+                    fee_caps,
+                    DeadlineEscalation::default()
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_derive_fee_rate_from_vsize_of_payment_with_many_inputs() {
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_get_bitcoin_fees()
+            .returning(move || Ok(FixedU128::from(1000)));
+
+        let mut mock_bitcoin = MockBitcoin::default();
+        mock_bitcoin.expect_network().returning(|| Network::Regtest);
+        // the payment needs 1000 vbytes, so the rate derived from the estimated size
+        // would pay 40_000 satoshis in fees
+        mock_bitcoin
+            .expect_create_and_send_transaction_with_max_fee()
+            .withf(|_, _, fee_rate, max_fee, _| fee_rate == &SatPerVbyte(40) && max_fee == &10_000)
+            .times(1)
+            .returning(|_, _, _, _, _| Err(BitcoinError::FeeExceedsMax(1000)));
+        mock_bitcoin
+            .expect_create_and_send_transaction_with_max_fee()
+            .withf(|_, _, fee_rate, max_fee, _| fee_rate == &SatPerVbyte(10) && max_fee == &10_000)
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(Txid::all_zeros()));
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin);
+
+        let request = Request {
+            amount: 1_000_000,
+            deadline: None,
+            expiry: None,
+            btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
+            hash: H256::from_slice(&[1; 32]),
+            btc_height: None,
+            request_type: RequestType::Redeem,
+            vault_id: dummy_vault_id(),
+            fee_budget: None,
+        };
+
+        let fee_caps = FeeCaps {
+            max_fee_fraction: Some(0.01),
+            ..Default::default()
+        };
+        assert_ok!(
+            request
+                .transfer_btc(&parachain_rpc, &btc_rpc, &PaymentJournal::temporary(), fee_caps)
+                .await
+        );
+    }
+
     mod pay_and_execute_redeem_tests {
         use bitcoin::Hash;
 
@@ -865,6 +1418,7 @@ mod tests {
                     parachain: parachain_deadline,
                     bitcoin: bitcoin_deadline,
                 }),
+                expiry: None,
                 btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
                 hash: H256::from_slice(&[1; 32]),
                 btc_height: None,
@@ -877,6 +1431,7 @@ mod tests {
                 vault_id: dummy_vault_id(),
                 btc_rpc,
                 metrics: PerCurrencyMetrics::dummy(),
+                journal: PaymentJournal::temporary(),
             };
 
             (request, parachain_rpc, vault_data)
@@ -886,21 +1441,54 @@ mod tests {
         async fn should_pay_and_execute_redeem_if_neither_parachain_nor_bitcoin_deadlines_expired() {
             let (request, parachain_rpc, btc_rpc) = should_pay_and_execute_with_deadlines(100, 50, 100, 50);
 
-            assert_ok!(request.pay_and_execute(parachain_rpc, btc_rpc, 6, true).await);
+            assert_ok!(
+                request
+                    .pay_and_execute(
+                        parachain_rpc,
+                        btc_rpc,
+                        6,
+                        true,
+                        FeeCaps::default(),
+                        DeadlineEscalation::default()
+                    )
+                    .await
+            );
         }
 
         #[tokio::test]
         async fn should_pay_and_execute_redeem_if_only_parachain_deadline_expired() {
             let (request, parachain_rpc, btc_rpc) = should_pay_and_execute_with_deadlines(100, 101, 100, 50);
 
-            assert_ok!(request.pay_and_execute(parachain_rpc, btc_rpc, 6, true).await);
+            assert_ok!(
+                request
+                    .pay_and_execute(
+                        parachain_rpc,
+                        btc_rpc,
+                        6,
+                        true,
+                        FeeCaps::default(),
+                        DeadlineEscalation::default()
+                    )
+                    .await
+            );
         }
 
         #[tokio::test]
         async fn should_pay_and_execute_redeem_if_only_bitcoin_deadline_expired() {
             let (request, parachain_rpc, btc_rpc) = should_pay_and_execute_with_deadlines(100, 50, 100, 101);
 
-            assert_ok!(request.pay_and_execute(parachain_rpc, btc_rpc, 6, true).await);
+            assert_ok!(
+                request
+                    .pay_and_execute(
+                        parachain_rpc,
+                        btc_rpc,
+                        6,
+                        true,
+                        FeeCaps::default(),
+                        DeadlineEscalation::default()
+                    )
+                    .await
+            );
         }
 
         #[tokio::test]
@@ -908,7 +1496,16 @@ mod tests {
             let (request, parachain_rpc, btc_rpc) = should_pay_and_execute_with_deadlines(100, 101, 100, 101);
 
             assert_err!(
-                request.pay_and_execute(parachain_rpc, btc_rpc, 6, true).await,
+                request
+                    .pay_and_execute(
+                        parachain_rpc,
+                        btc_rpc,
+                        6,
+                        true,
+                        FeeCaps::default(),
+                        DeadlineEscalation::default()
+                    )
+                    .await,
                 Error::DeadlineExpired
             );
         }
@@ -932,6 +1529,7 @@ mod tests {
                 parachain: 100,
                 bitcoin: 100,
             }),
+            expiry: None,
             btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
             hash: H256::from_slice(&[1; 32]),
             btc_height: None,
@@ -944,10 +1542,20 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         assert_err!(
-            request.pay_and_execute(parachain_rpc, vault_data, 6, true).await,
+            request
+                .pay_and_execute(
+                    parachain_rpc,
+                    vault_data,
+                    6,
+                    true,
+                    FeeCaps::default(),
+                    DeadlineEscalation::default()
+                )
+                .await,
             Error::DeadlineExpired
         );
     }
@@ -998,6 +1606,7 @@ mod tests {
                 parachain: 100,
                 bitcoin: 100,
             }),
+            expiry: None,
             btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
             hash: H256::from_slice(&[1; 32]),
             btc_height: None,
@@ -1010,8 +1619,50 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
+        };
+
+        assert_ok!(
+            request
+                .pay_and_execute(
+                    parachain_rpc,
+                    vault_data,
+                    6,
+                    true,
+                    FeeCaps::default(),
+                    DeadlineEscalation::default()
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_find_pending_journaled_payment_without_scanning_the_wallet() {
+        let txid = Txid::from_slice(&[2; 32]).unwrap();
+
+        let mut mock_bitcoin = MockBitcoin::default();
+        mock_bitcoin
+            .expect_is_in_mempool()
+            .withf(move |x| x == &txid)
+            .returning(|_| Ok(true));
+        mock_bitcoin.expect_fee_rate().returning(|_| Ok(SatPerVbyte(10)));
+        mock_bitcoin.expect_list_wallet_transactions().times(0);
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin);
+
+        let request = Request {
+            amount: 100,
+            deadline: None,
+            expiry: None,
+            btc_address: BtcAddress::P2SH(H160::from_slice(&[1; 20])),
+            hash: H256::from_slice(&[1; 32]),
+            btc_height: None,
+            request_type: RequestType::Redeem,
+            vault_id: dummy_vault_id(),
+            fee_budget: None,
         };
 
-        assert_ok!(request.pay_and_execute(parachain_rpc, vault_data, 6, true).await);
+        let journal = PaymentJournal::temporary();
+        journal.record_broadcast(request.hash, txid).unwrap();
+        assert_ok!(request.find_journaled_payment(&btc_rpc, &journal).await, Some(txid));
     }
 }
@@ -0,0 +1,235 @@
+//! Export of the bitcoin wallet history of a vault, for accounting and tax reporting.
+
+use crate::{issue::add_new_deposit_key, Error};
+use bitcoin::{Error as BitcoinError, Payload, TransactionExt, WalletTransaction};
+use runtime::{
+    InterBtcParachain, IssuePallet, PartialAddress, PrettyPrint, RedeemPallet, ReplacePallet, UtilFuncs, VaultId, H256,
+};
+use service::DynBitcoinCoreApi;
+use std::{collections::HashMap, fmt::Write, str::FromStr};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for HistoryFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(HistoryFormat::Csv),
+            "json" => Ok(HistoryFormat::Json),
+            _ => Err("unknown history format".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RequestType {
+    Issue,
+    Redeem,
+    Replace,
+}
+
+impl RequestType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RequestType::Issue => "issue",
+            RequestType::Redeem => "redeem",
+            RequestType::Replace => "replace",
+        }
+    }
+}
+
+/// A wallet transaction, joined with the parachain request it belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub vault_id: String,
+    pub transaction: WalletTransaction,
+    pub request: Option<(RequestType, H256)>,
+}
+
+const CSV_HEADER: &str = "vault_id,txid,block_height,block_hash,amount_sat,fee_sat,replaced,request_type,request_id\n";
+
+/// Collect the wallet history of the vault. Incoming payments are matched to issue
+/// requests by the deposit address, outgoing payments to redeem and replace requests
+/// by the request id in the OP_RETURN output. Unless `import_deposit_keys` is set, the
+/// wallet is not modified, so deposits to keys it does not know about yet are missing.
+pub async fn get_vault_history(
+    btc_rpc: &DynBitcoinCoreApi,
+    parachain_rpc: &InterBtcParachain,
+    vault_id: &VaultId,
+    import_deposit_keys: bool,
+) -> Result<Vec<HistoryEntry>, Error> {
+    let account_id = parachain_rpc.get_account_id().clone();
+
+    let issue_requests: Vec<_> = parachain_rpc
+        .get_vault_issue_requests(account_id.clone())
+        .await?
+        .into_iter()
+        .filter(|(_, request)| &request.vault == vault_id)
+        .collect();
+    // the light client only knows the deposit keys it has been told about
+    if import_deposit_keys {
+        for (issue_id, request) in issue_requests.iter() {
+            add_new_deposit_key(btc_rpc, *issue_id, request.btc_public_key.clone()).await?;
+        }
+    }
+    let deposit_addresses = issue_requests
+        .into_iter()
+        .map(|(issue_id, request)| Ok((request.btc_address.to_payload().map_err(BitcoinError::from)?, issue_id)))
+        .collect::<Result<Vec<(Payload, H256)>, Error>>()?;
+
+    let mut payments = HashMap::new();
+    for (redeem_id, request) in parachain_rpc.get_vault_redeem_requests(account_id.clone()).await? {
+        if &request.vault == vault_id {
+            payments.insert(redeem_id, RequestType::Redeem);
+        }
+    }
+    for (replace_id, request) in parachain_rpc.get_old_vault_replace_requests(account_id).await? {
+        if &request.old_vault == vault_id {
+            payments.insert(replace_id, RequestType::Replace);
+        }
+    }
+
+    let mut history = Vec::new();
+    for transaction in btc_rpc.list_wallet_transactions(None).await? {
+        // replaced transactions are not necessarily available anymore
+        let request = if transaction.replaced {
+            None
+        } else {
+            let tx = btc_rpc
+                .get_transaction(&transaction.txid, transaction.block.map(|(hash, _)| hash))
+                .await?;
+            match tx.get_op_return() {
+                Some(request_id) => payments
+                    .get(&request_id)
+                    .map(|request_type| (*request_type, request_id)),
+                None => deposit_addresses
+                    .iter()
+                    .find(|(payload, _)| tx.get_payment_amount_to(payload.clone()).is_some())
+                    .map(|(_, issue_id)| (RequestType::Issue, *issue_id)),
+            }
+        };
+        history.push(HistoryEntry {
+            vault_id: vault_id.pretty_print(),
+            transaction,
+            request,
+        });
+    }
+    Ok(history)
+}
+
+/// Serialize the history in the given format.
+pub fn format_history(history: &[HistoryEntry], format: HistoryFormat) -> Result<String, Error> {
+    match format {
+        HistoryFormat::Csv => Ok(format_csv(history)),
+        HistoryFormat::Json => {
+            let entries: Vec<_> = history
+                .iter()
+                .map(|entry| {
+                    let transaction = &entry.transaction;
+                    serde_json::json!({
+                        "vault_id": entry.vault_id,
+                        "txid": transaction.txid.to_string(),
+                        "block_height": transaction.block.map(|(_, height)| height),
+                        "block_hash": transaction.block.map(|(hash, _)| hash.to_string()),
+                        "amount_sat": transaction.amount.to_sat(),
+                        "fee_sat": transaction.fee.map(|fee| fee.to_sat()),
+                        "replaced": transaction.replaced,
+                        "request_type": entry.request.map(|(request_type, _)| request_type.as_str()),
+                        "request_id": entry.request.map(|(_, request_id)| format!("{request_id:?}")),
+                    })
+                })
+                .collect();
+            Ok(serde_json::to_string_pretty(&entries)?)
+        }
+    }
+}
+
+fn format_csv(history: &[HistoryEntry]) -> String {
+    fn optional<T: ToString>(value: Option<T>) -> String {
+        value.map(|value| value.to_string()).unwrap_or_default()
+    }
+
+    let mut csv = CSV_HEADER.to_string();
+    for entry in history {
+        let transaction = &entry.transaction;
+        // none of the fields can contain a comma or a quote, so no escaping is needed
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{}",
+            entry.vault_id,
+            transaction.txid,
+            optional(transaction.block.map(|(_, height)| height)),
+            optional(transaction.block.map(|(hash, _)| hash)),
+            transaction.amount.to_sat(),
+            optional(transaction.fee.map(|fee| fee.to_sat())),
+            transaction.replaced,
+            optional(entry.request.map(|(request_type, _)| request_type.as_str())),
+            optional(entry.request.map(|(_, request_id)| format!("{request_id:?}"))),
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{BlockHash, Hash, SignedAmount, Txid};
+
+    fn entry(block: Option<(BlockHash, u32)>, request: Option<(RequestType, H256)>) -> HistoryEntry {
+        HistoryEntry {
+            vault_id: "vault[KSM->KBTC]".to_string(),
+            transaction: WalletTransaction {
+                txid: Txid::all_zeros(),
+                block,
+                amount: SignedAmount::from_sat(-10_000),
+                fee: Some(SignedAmount::from_sat(-250)),
+                replaced: false,
+            },
+            request,
+        }
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let history = vec![
+            entry(
+                Some((BlockHash::all_zeros(), 42)),
+                Some((RequestType::Redeem, H256::from_low_u64_be(1))),
+            ),
+            entry(None, None),
+        ];
+        let csv = format_history(&history, HistoryFormat::Csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER.trim_end());
+        assert_eq!(
+            lines[1],
+            format!(
+                "vault[KSM->KBTC],{txid},42,{hash},-10000,-250,false,redeem,{request_id:?}",
+                txid = Txid::all_zeros(),
+                hash = BlockHash::all_zeros(),
+                request_id = H256::from_low_u64_be(1),
+            )
+        );
+        assert_eq!(
+            lines[2],
+            format!("vault[KSM->KBTC],{},,,-10000,-250,false,,", Txid::all_zeros())
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        let history = vec![entry(None, Some((RequestType::Issue, H256::zero())))];
+        let json = format_history(&history, HistoryFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value[0]["amount_sat"], -10_000);
+        assert_eq!(value[0]["block_height"], serde_json::Value::Null);
+        assert_eq!(value[0]["request_type"], "issue");
+    }
+}
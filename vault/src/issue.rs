@@ -237,7 +237,7 @@ async fn process_transaction_and_execute_issue(
 }
 
 /// Import the deposit key using the on-chain key derivation scheme
-async fn add_new_deposit_key(
+pub(crate) async fn add_new_deposit_key(
     bitcoin_core: &DynBitcoinCoreApi,
     secure_id: H256,
     public_key: BtcPublicKey,
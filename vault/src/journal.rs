@@ -0,0 +1,247 @@
+//! On-disk journal of outgoing bitcoin payments, used to avoid paying a redeem or
+//! replace request twice when the vault restarts while a payment is in flight.
+
+use crate::Error;
+use bitcoin::{BlockHash, Txid};
+use kv::{Bucket, Config, Error as KvError, Json, Store};
+use lazy_static::lazy_static;
+use runtime::H256;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+const BUCKET_NAME: &str = "payments";
+
+lazy_static! {
+    // the vault service is restarted in-process, but the database can only be opened once
+    static ref JOURNALS: Mutex<HashMap<PathBuf, PaymentJournal>> = Mutex::new(HashMap::new());
+}
+
+type PaymentBucket = Bucket<'static, String, Json<PaymentState>>;
+
+/// The progress of the payment of a single request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PaymentState {
+    /// The payment is about to be made - it may or may not have been broadcast.
+    Intent,
+    /// The payment has been broadcast with the given txid.
+    Broadcast { txid: Txid },
+    /// The payment has been included in the given block and relayed to the parachain.
+    Confirmed { txid: Txid, block_hash: BlockHash },
+}
+
+/// Handle of the journal, which is shared by all clones. The database is locked while it
+/// is open, so with a leader lease only the leader opens it.
+#[derive(Clone)]
+pub struct PaymentJournal {
+    path: PathBuf,
+    bucket: Arc<RwLock<Option<PaymentBucket>>>,
+}
+
+impl PaymentJournal {
+    /// Returns the journal at the given path, which must be opened before it can be used.
+    pub fn at(path: &Path) -> Self {
+        JOURNALS
+            .lock()
+            .expect("journal lock poisoned")
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Self::closed(path))
+            .clone()
+    }
+
+    fn closed(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            bucket: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Opens the journal, creating it if it does not exist yet. Fails while the journal
+    /// is held open by another instance.
+    pub fn open(&self) -> Result<(), Error> {
+        let mut bucket = self.bucket.write().expect("journal lock poisoned");
+        if bucket.is_none() {
+            *bucket = Some(Self::bucket(Store::new(Config::new(&self.path))?)?);
+        }
+        Ok(())
+    }
+
+    /// Closes the journal, so that another instance can open it.
+    pub fn close(&self) {
+        *self.bucket.write().expect("journal lock poisoned") = None;
+    }
+
+    /// Opens an empty journal that is deleted once dropped. Used for testing only.
+    pub fn temporary() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "vault-journal-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let bucket = Store::new(Config::new(&path).temporary(true))
+            .map_err(Error::from)
+            .and_then(Self::bucket)
+            .expect("failed to open temporary journal");
+        Self {
+            path,
+            bucket: Arc::new(RwLock::new(Some(bucket))),
+        }
+    }
+
+    fn bucket(store: Store) -> Result<PaymentBucket, Error> {
+        Ok(store.bucket(Some(BUCKET_NAME))?)
+    }
+
+    fn with_bucket<T>(&self, f: impl FnOnce(&PaymentBucket) -> Result<T, KvError>) -> Result<T, Error> {
+        match &*self.bucket.read().expect("journal lock poisoned") {
+            Some(bucket) => Ok(f(bucket)?),
+            None => Err(Error::JournalClosed),
+        }
+    }
+
+    fn key(hash: H256) -> String {
+        format!("{hash:?}")
+    }
+
+    fn set(&self, hash: H256, state: PaymentState) -> Result<(), Error> {
+        self.with_bucket(|bucket| {
+            bucket.set(Self::key(hash), Json(state))?;
+            // the entry must be on disk before the payment is made
+            bucket.flush()?;
+            Ok(())
+        })
+    }
+
+    pub fn get(&self, hash: H256) -> Result<Option<PaymentState>, Error> {
+        self.with_bucket(|bucket| Ok(bucket.get(Self::key(hash))?.map(|state| state.0)))
+    }
+
+    pub fn record_intent(&self, hash: H256) -> Result<(), Error> {
+        self.set(hash, PaymentState::Intent)
+    }
+
+    pub fn record_broadcast(&self, hash: H256, txid: Txid) -> Result<(), Error> {
+        self.set(hash, PaymentState::Broadcast { txid })
+    }
+
+    pub fn record_confirmed(&self, hash: H256, txid: Txid, block_hash: BlockHash) -> Result<(), Error> {
+        self.set(hash, PaymentState::Confirmed { txid, block_hash })
+    }
+
+    /// Removes the entry once the request has been executed.
+    pub fn remove(&self, hash: H256) -> Result<(), Error> {
+        self.with_bucket(|bucket| {
+            bucket.remove(Self::key(hash))?;
+            bucket.flush()?;
+            Ok(())
+        })
+    }
+
+    /// Removes the entries of all requests that are not `pending` anymore, e.g. because they
+    /// were cancelled or expired while the payment was in flight. Returns the number removed.
+    pub fn remove_unless_pending(&self, pending: &HashSet<H256>) -> Result<usize, Error> {
+        let pending: HashSet<_> = pending.iter().map(|hash| Self::key(*hash)).collect();
+        self.with_bucket(|bucket| {
+            let mut stale = Vec::new();
+            for item in bucket.iter() {
+                let key: String = item?.key()?;
+                if !pending.contains(&key) {
+                    stale.push(key);
+                }
+            }
+            let count = stale.len();
+            for key in stale {
+                bucket.remove(key)?;
+            }
+            bucket.flush()?;
+            Ok(count)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Hash;
+
+    #[test]
+    fn test_journal_transitions() {
+        let journal = PaymentJournal::temporary();
+        let hash = H256::from_low_u64_be(1);
+        let txid = Txid::from_slice(&[1; 32]).unwrap();
+
+        assert_eq!(journal.get(hash).unwrap(), None);
+
+        journal.record_intent(hash).unwrap();
+        assert_eq!(journal.get(hash).unwrap(), Some(PaymentState::Intent));
+
+        journal.record_broadcast(hash, txid).unwrap();
+        assert_eq!(journal.get(hash).unwrap(), Some(PaymentState::Broadcast { txid }));
+
+        journal.record_confirmed(hash, txid, BlockHash::all_zeros()).unwrap();
+        assert_eq!(
+            journal.get(hash).unwrap(),
+            Some(PaymentState::Confirmed {
+                txid,
+                block_hash: BlockHash::all_zeros()
+            })
+        );
+        // other requests are unaffected
+        assert_eq!(journal.get(H256::from_low_u64_be(2)).unwrap(), None);
+
+        journal.remove(hash).unwrap();
+        assert_eq!(journal.get(hash).unwrap(), None);
+    }
+
+    #[test]
+    fn test_journal_removes_entries_unless_pending() {
+        let journal = PaymentJournal::temporary();
+        let (pending, cancelled) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let txid = Txid::from_slice(&[1; 32]).unwrap();
+
+        journal.record_broadcast(pending, txid).unwrap();
+        journal.record_intent(cancelled).unwrap();
+
+        let pending_ids = vec![pending].into_iter().collect();
+        assert_eq!(journal.remove_unless_pending(&pending_ids).unwrap(), 1);
+        assert_eq!(journal.get(pending).unwrap(), Some(PaymentState::Broadcast { txid }));
+        assert_eq!(journal.get(cancelled).unwrap(), None);
+        // nothing left to remove
+        assert_eq!(journal.remove_unless_pending(&pending_ids).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_journal_takeover() {
+        let path = std::env::temp_dir().join(format!("vault-journal-takeover-{}", std::process::id()));
+        let hash = H256::from_low_u64_be(1);
+        let txid = Txid::from_slice(&[1; 32]).unwrap();
+
+        // two instances sharing the storage, without the in-process cache of `at`
+        let leader = PaymentJournal::closed(&path);
+        let standby = PaymentJournal::closed(&path);
+
+        leader.open().unwrap();
+        leader.record_broadcast(hash, txid).unwrap();
+        // the database is locked by the leader
+        assert!(standby.open().is_err());
+        assert!(matches!(standby.get(hash), Err(Error::JournalClosed)));
+
+        // the leader loses the lease
+        leader.close();
+        assert!(matches!(leader.record_intent(hash), Err(Error::JournalClosed)));
+
+        standby.open().unwrap();
+        assert_eq!(standby.get(hash).unwrap(), Some(PaymentState::Broadcast { txid }));
+        standby.close();
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
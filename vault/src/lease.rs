@@ -0,0 +1,370 @@
+//! Leader election for running vaults in active/standby mode. Instances compete for a
+//! lease, and only its holder runs the tasks that make payments and submit executions.
+//! Leases expire at a parachain block rather than at a wall-clock time, so that instances
+//! on different hosts agree on the expiry regardless of clock skew.
+
+use crate::error::Error;
+use async_trait::async_trait;
+use runtime::UtilFuncs;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::time::sleep;
+
+/// Interval in which standbys poll the lease and the leader renews it. Shorter than the
+/// parachain block time, so that the leader notices an expiring lease before a standby can
+/// take over.
+const LEASE_POLL_INTERVAL: Duration = Duration::from_secs(6);
+/// Lock files older than this are left behind by a crashed instance.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const LOCK_MAX_RETRIES: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub holder: String,
+    /// Parachain block at which the lease expires.
+    pub expires_at: u32,
+}
+
+impl Lease {
+    fn is_available_to(&self, holder: &str, height: u32) -> bool {
+        self.holder == holder || self.expires_at <= height
+    }
+}
+
+/// Shared storage of the lease.
+#[async_trait]
+pub trait LeaseStore: fmt::Debug + Send + Sync {
+    /// Acquires the lease for `holder` until the `expires_at` block, or renews it if the holder
+    /// already has it. Returns false if another holder's lease has not expired at `height`.
+    async fn try_acquire(&self, holder: &str, height: u32, expires_at: u32) -> Result<bool, Error>;
+
+    /// Gives up the lease, if it is held by `holder`.
+    async fn release(&self, holder: &str) -> Result<(), Error>;
+}
+
+/// Lease stored in a file on storage shared by all instances, e.g. an NFS mount.
+#[derive(Debug)]
+pub struct FileLease {
+    path: PathBuf,
+}
+
+impl FileLease {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn read(&self) -> Result<Option<Lease>, Error> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write(&self, lease: &Lease) -> Result<(), Error> {
+        // write to a temporary file first, so that a crash never leaves a partial lease
+        let tmp_path = self.path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_vec(lease)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LeaseStore for FileLease {
+    async fn try_acquire(&self, holder: &str, height: u32, expires_at: u32) -> Result<bool, Error> {
+        let _lock = FileLock::acquire(self.path.with_extension("lock")).await?;
+        if let Some(lease) = self.read()? {
+            if !lease.is_available_to(holder, height) {
+                return Ok(false);
+            }
+        }
+        self.write(&Lease {
+            holder: holder.to_string(),
+            expires_at,
+        })?;
+        Ok(true)
+    }
+
+    async fn release(&self, holder: &str) -> Result<(), Error> {
+        let _lock = FileLock::acquire(self.path.with_extension("lock")).await?;
+        if matches!(self.read()?, Some(lease) if lease.holder == holder) {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Guards the read-modify-write of the lease file. Removed on drop.
+struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    async fn acquire(path: PathBuf) -> Result<Self, Error> {
+        for _ in 0..LOCK_MAX_RETRIES {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&path, STALE_LOCK_AGE) {
+                        tracing::warn!("Removing stale lease lock at {}", path.display());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+            sleep(LOCK_RETRY_INTERVAL).await;
+        }
+        Err(io::Error::new(ErrorKind::TimedOut, format!("Could not lock {}", path.display())).into())
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_stale(path: &Path, max_age: Duration) -> bool {
+    File::open(path)
+        .and_then(|file| file.metadata())
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| SystemTime::now().duration_since(modified).unwrap_or_default() > max_age)
+        .unwrap_or(false)
+}
+
+#[derive(Serialize, Deserialize)]
+struct AcquireRequest {
+    holder: String,
+    height: u32,
+    expires_at: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AcquireResponse {
+    acquired: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReleaseRequest {
+    holder: String,
+}
+
+/// Lease held by a coordination service, which implements `POST /acquire` and `POST /release`
+/// with the semantics of [`LeaseStore`].
+#[derive(Debug)]
+pub struct HttpLease {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpLease {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseStore for HttpLease {
+    async fn try_acquire(&self, holder: &str, height: u32, expires_at: u32) -> Result<bool, Error> {
+        let response: AcquireResponse = self
+            .client
+            .post(format!("{}/acquire", self.url))
+            .json(&AcquireRequest {
+                holder: holder.to_string(),
+                height,
+                expires_at,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.acquired)
+    }
+
+    async fn release(&self, holder: &str) -> Result<(), Error> {
+        self.client
+            .post(format!("{}/release", self.url))
+            .json(&ReleaseRequest {
+                holder: holder.to_string(),
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderElection {
+    store: Arc<dyn LeaseStore>,
+    holder: String,
+    /// Number of parachain blocks a lease is valid for.
+    lease_blocks: u32,
+}
+
+impl LeaderElection {
+    pub fn new(store: Arc<dyn LeaseStore>, holder: String, lease_blocks: u32) -> Self {
+        Self {
+            store,
+            holder,
+            lease_blocks,
+        }
+    }
+
+    /// Waits until this instance holds the lease, and returns the block it expires at.
+    pub async fn acquire<P: UtilFuncs>(&self, parachain_rpc: &P) -> Result<u32, Error> {
+        loop {
+            let height = parachain_rpc.get_current_chain_height().await?;
+            let expires_at = height.saturating_add(self.lease_blocks);
+            match self.store.try_acquire(&self.holder, height, expires_at).await {
+                Ok(true) => {
+                    tracing::info!("{} acquired the leader lease until block {}", self.holder, expires_at);
+                    return Ok(expires_at);
+                }
+                Ok(false) => tracing::debug!("Leader lease is held by another instance"),
+                Err(err) => tracing::warn!("Failed to acquire the leader lease: {}", err),
+            }
+            sleep(LEASE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Keeps renewing the lease. Fails once the lease is lost, or is about to expire without
+    /// having been renewed, so that the leader stops before a standby can take over.
+    pub async fn hold<P: UtilFuncs>(self, parachain_rpc: P, mut expires_at: u32) -> Result<(), Error> {
+        loop {
+            sleep(LEASE_POLL_INTERVAL).await;
+            let height = parachain_rpc.get_current_chain_height().await?;
+            if height.saturating_add(1) >= expires_at {
+                return Err(Error::LeaseLost);
+            }
+            let renewed_until = height.saturating_add(self.lease_blocks);
+            match self.store.try_acquire(&self.holder, height, renewed_until).await {
+                Ok(true) => expires_at = renewed_until,
+                Ok(false) => return Err(Error::LeaseLost),
+                Err(err) => tracing::warn!("Failed to renew the leader lease: {}", err),
+            }
+        }
+    }
+
+    pub async fn release(&self) {
+        match self.store.release(&self.holder).await {
+            Ok(()) => tracing::info!("Released the leader lease"),
+            Err(err) => tracing::warn!("Failed to release the leader lease: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use service::warp::{self, Filter};
+    use std::sync::Mutex;
+
+    fn temporary_lease(name: &str) -> FileLease {
+        let path = std::env::temp_dir().join(format!("vault-lease-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        FileLease::new(path)
+    }
+
+    #[tokio::test]
+    async fn test_file_lease() {
+        let lease = temporary_lease("contention");
+
+        assert!(lease.try_acquire("a", 100, 110).await.unwrap());
+        // held by another instance until it expires
+        assert!(!lease.try_acquire("b", 105, 115).await.unwrap());
+        // renewed by the holder
+        assert!(lease.try_acquire("a", 105, 115).await.unwrap());
+        assert!(!lease.try_acquire("b", 114, 124).await.unwrap());
+        assert!(lease.try_acquire("b", 115, 125).await.unwrap());
+        assert!(!lease.try_acquire("a", 116, 126).await.unwrap());
+
+        fs::remove_file(&lease.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_lease_release() {
+        let lease = temporary_lease("release");
+
+        assert!(lease.try_acquire("a", 100, 110).await.unwrap());
+        // only the holder can release the lease
+        lease.release("b").await.unwrap();
+        assert!(!lease.try_acquire("b", 101, 111).await.unwrap());
+        lease.release("a").await.unwrap();
+        assert!(lease.try_acquire("b", 101, 111).await.unwrap());
+
+        fs::remove_file(&lease.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_is_stale() {
+        let path = std::env::temp_dir().join(format!("vault-lease-stale-{}.lock", std::process::id()));
+        File::create(&path).unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        assert!(!is_stale(&path, STALE_LOCK_AGE));
+        assert!(is_stale(&path, Duration::ZERO));
+        fs::remove_file(&path).unwrap();
+        // a lock that was just released is not stale
+        assert!(!is_stale(&path, Duration::ZERO));
+    }
+
+    /// Starts a stand-in for the coordination service, which keeps the lease in memory.
+    fn coordination_service() -> String {
+        let lease = Arc::new(Mutex::new(None::<Lease>));
+        let acquire = {
+            let lease = lease.clone();
+            warp::path("acquire")
+                .and(warp::body::json())
+                .map(move |request: AcquireRequest| {
+                    let mut lease = lease.lock().unwrap();
+                    let acquired = lease
+                        .as_ref()
+                        .map_or(true, |lease| lease.is_available_to(&request.holder, request.height));
+                    if acquired {
+                        *lease = Some(Lease {
+                            holder: request.holder,
+                            expires_at: request.expires_at,
+                        });
+                    }
+                    warp::reply::json(&AcquireResponse { acquired })
+                })
+        };
+        let release = warp::path("release")
+            .and(warp::body::json())
+            .map(move |request: ReleaseRequest| {
+                let mut lease = lease.lock().unwrap();
+                if matches!(&*lease, Some(current) if current.holder == request.holder) {
+                    *lease = None;
+                }
+                warp::reply()
+            });
+        let (addr, server) = warp::serve(warp::post().and(acquire.or(release))).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{addr}/")
+    }
+
+    #[tokio::test]
+    async fn test_http_lease() {
+        let lease = HttpLease::new(coordination_service());
+
+        assert!(lease.try_acquire("a", 100, 110).await.unwrap());
+        assert!(!lease.try_acquire("b", 105, 115).await.unwrap());
+        lease.release("a").await.unwrap();
+        assert!(lease.try_acquire("b", 105, 115).await.unwrap());
+    }
+}
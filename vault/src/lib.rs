@@ -1,17 +1,31 @@
 #![recursion_limit = "256"]
 #![feature(array_zip, int_log)]
 
+pub mod admin;
+mod api;
+pub mod backup;
 mod cancellation;
+mod collateral;
+mod deadline;
 pub mod delay;
+mod drain;
+mod dry_run;
 mod error;
 mod execution;
 mod faucet;
+pub mod history;
 mod issue;
+mod journal;
+mod lease;
 pub mod metrics;
+pub mod notify;
 pub mod process;
+pub mod reconcile;
 mod redeem;
 pub mod relay;
+mod reload;
 mod replace;
+mod replace_policy;
 mod system;
 mod types;
 
@@ -33,8 +47,11 @@ pub use system::{VaultService, VaultServiceConfig, ABOUT, AUTHORS, NAME, VERSION
 
 use runtime::{InterBtcParachain, VaultId, VaultRegistryPallet};
 
-pub use crate::{cancellation::Event, error::Error, types::IssueRequests};
+pub use crate::{
+    cancellation::Event, deadline::DeadlineEscalation, error::Error, execution::FeeCaps, types::IssueRequests,
+};
 pub use delay::{OrderedVaultsDelay, RandomDelay, ZeroDelay};
+pub use reload::{Reloadable, ReloadableSettings, SettingsSender};
 pub use system::VaultIdManager;
 
 pub(crate) async fn deposit_collateral(api: &InterBtcParachain, vault_id: &VaultId, amount: u128) -> Result<(), Error> {
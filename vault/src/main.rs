@@ -1,23 +1,38 @@
-use bitcoin::{Network, PrivateKey};
+use bitcoin::{
+    keystore::{self, Keystore, WIF_CONTENT, WIF_PASSWORD_ENV},
+    Network, PrivateKey,
+};
 use clap::Parser;
 use futures::Future;
-use runtime::{InterBtcSigner, KeyPair, Ss58Codec, DEFAULT_SPEC_NAME, SS58_PREFIX};
+use runtime::{
+    cli::{KEYFILE_CONTENT, KEYFILE_PASSWORD_ENV},
+    InterBtcParachain, KeyPair, Ss58Codec, UtilFuncs, VaultRegistryPallet, DEFAULT_SPEC_NAME, SS58_PREFIX,
+};
 use secp256k1::{rand::thread_rng, SecretKey};
-use service::{warp, warp::Filter, ConnectionManager, Error, MonitoringConfig, ServiceConfig};
+use service::{
+    vault_wallet_name, warp, warp::Filter, ConnectionManager, DynBitcoinCoreApi, Error, MonitoringConfig, ServiceConfig,
+};
 use signal_hook::consts::*;
 use signal_hook_tokio::Signals;
 use sp_core::crypto::Pair;
 use std::{
+    collections::HashSet,
+    ffi::OsString,
     io::Write,
     net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
 };
 use sysinfo::{System, SystemExt};
+use tokio::sync::watch;
 use tokio_stream::StreamExt;
 use vault::{
+    admin,
+    backup::{self, KeyBackup, BACKUP_CONTENT, BACKUP_PASSWORD_ENV},
+    history::{self, HistoryFormat},
     metrics::{self, increment_restart_counter},
     process::PidFile,
-    VaultService, VaultServiceConfig, ABOUT, AUTHORS, NAME, VERSION,
+    reconcile::{self, Reconciliation},
+    ReloadableSettings, SettingsSender, VaultService, VaultServiceConfig, ABOUT, AUTHORS, NAME, VERSION,
 };
 
 #[derive(Parser)]
@@ -39,6 +54,28 @@ enum Commands {
     /// Run the Vault client (default).
     #[clap(name = "run")]
     RunVault(Box<RunVaultOpts>),
+    /// Export the bitcoin wallet history of all vaults of the account.
+    ExportHistory(Box<ExportHistoryOpts>),
+    /// Export the encrypted bitcoin derivation key of the registered public key.
+    BackupKeys(Box<BackupKeysOpts>),
+    /// Import a bitcoin derivation key exported by `backup-keys`.
+    RestoreKeys(Box<RestoreKeysOpts>),
+    /// Run the Vault client in drain mode, until it is safe to stop.
+    Drain(Box<RunVaultOpts>),
+    /// Lock additional collateral in the vault.
+    DepositCollateral(Box<AdminCallOpts>),
+    /// Withdraw collateral from the vault.
+    WithdrawCollateral(Box<AdminCallOpts>),
+    /// Register a vault with another collateral currency.
+    Register(Box<AdminCallOpts>),
+    /// Request to be replaced for an amount of issued tokens.
+    RequestReplace(Box<AdminCallOpts>),
+    /// Withdraw an amount of tokens from a replace request.
+    WithdrawReplace(Box<AdminCallOpts>),
+    /// Print the registration and balances of the vaults of the account.
+    Status(Box<StatusOpts>),
+    /// Compare the bitcoin wallets with the vault state on the parachain.
+    Reconcile(Box<ReconcileOpts>),
 }
 
 // write the file to stdout or disk - fail if it already exists
@@ -65,6 +102,15 @@ struct GenerateBitcoinKeyOpts {
 
     #[clap(long)]
     network: Network,
+
+    /// Encrypt the key with a password, which is read from the `--password-file`,
+    /// BITCOIN_WIF_PASSWORD or prompted for.
+    #[clap(long)]
+    encrypt: bool,
+
+    /// File containing the password to encrypt the key with.
+    #[clap(long, requires = "encrypt", value_parser)]
+    password_file: Option<PathBuf>,
 }
 
 impl GenerateBitcoinKeyOpts {
@@ -72,9 +118,14 @@ impl GenerateBitcoinKeyOpts {
         let secret_key = SecretKey::new(&mut thread_rng());
         let private_key = PrivateKey::new(secret_key, self.network);
         let wif = private_key.to_wif();
-        let data = wif.as_bytes();
 
-        try_write_file(&self.output, data)
+        if self.encrypt {
+            let password = keystore::read_new_password(self.password_file.as_deref(), WIF_PASSWORD_ENV, "Password: ")?;
+            let keystore = Keystore::encrypt(WIF_CONTENT, wif.as_bytes(), &password)?;
+            try_write_file(&self.output, keystore.to_json()?)
+        } else {
+            try_write_file(&self.output, wif.as_bytes())
+        }
     }
 }
 
@@ -83,6 +134,15 @@ struct GenerateParachainKeyOpts {
     /// Output file name or stdout if unspecified.
     #[clap(long, value_parser)]
     output: Option<PathBuf>,
+
+    /// Encrypt the keyfile with a password, which is read from the `--password-file`,
+    /// KEYFILE_PASSWORD or prompted for.
+    #[clap(long)]
+    encrypt: bool,
+
+    /// File containing the password to encrypt the keyfile with.
+    #[clap(long, requires = "encrypt", value_parser)]
+    password_file: Option<PathBuf>,
 }
 
 impl GenerateParachainKeyOpts {
@@ -96,13 +156,313 @@ impl GenerateParachainKeyOpts {
         );
         let data = serde_json::to_vec(&keys)?;
 
+        if self.encrypt {
+            let password =
+                keystore::read_new_password(self.password_file.as_deref(), KEYFILE_PASSWORD_ENV, "Password: ")?;
+            let keystore = Keystore::encrypt(KEYFILE_CONTENT, &data, &password)?;
+            try_write_file(&self.output, keystore.to_json()?)
+        } else {
+            try_write_file(&self.output, data)
+        }
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ExportHistoryOpts {
+    /// Config file.
+    #[clap(flatten)]
+    config: runtime::cli::ConfigFileOpts,
+
+    /// Keyring / keyfile options.
+    #[clap(flatten)]
+    account_info: runtime::cli::ProviderUserOpts,
+
+    /// Connection settings for the BTC Parachain.
+    #[clap(flatten)]
+    parachain: runtime::cli::ConnectionOpts,
+
+    /// Connection settings for Bitcoin Core.
+    #[clap(flatten)]
+    bitcoin: bitcoin::cli::BitcoinOpts,
+
+    /// Output format, either `csv` or `json`.
+    #[clap(long, default_value = "csv")]
+    format: HistoryFormat,
+
+    /// Output file name or stdout if unspecified.
+    #[clap(long, value_parser)]
+    output: Option<PathBuf>,
+}
+
+impl ExportHistoryOpts {
+    async fn export_and_write(&self) -> Result<(), Error> {
+        let (signer, wallet_name) = self.account_info.get_signer()?;
+        let prefix = wallet_name.to_string();
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
+        let parachain_rpc = InterBtcParachain::from_url_and_config_with_retry(
+            &self.parachain.btc_parachain_url,
+            signer,
+            self.parachain.max_concurrent_requests,
+            self.parachain.max_notifs_per_subscription,
+            self.parachain.btc_parachain_connection_timeout_ms,
+            shutdown_tx,
+        )
+        .await?;
+
+        // the light client only keeps the deposit keys in memory, so it needs them to find
+        // the deposits, whereas the keys are not imported into the Bitcoin Core wallets again
+        let import_deposit_keys = self.bitcoin.is_light();
+
+        let mut entries = Vec::new();
+        for vault_id in parachain_rpc
+            .get_vaults_by_account_id(parachain_rpc.get_account_id())
+            .await?
+        {
+            let btc_rpc = self
+                .bitcoin
+                .new_existing_wallet_client(Some(vault_wallet_name(&prefix, &vault_id)?))
+                .await?;
+            entries.extend(
+                history::get_vault_history(&btc_rpc, &parachain_rpc, &vault_id, import_deposit_keys)
+                    .await
+                    .map_err(|err| Error::Other(err.to_string()))?,
+            );
+        }
+        let data = history::format_history(&entries, self.format).map_err(|err| Error::Other(err.to_string()))?;
+
         try_write_file(&self.output, data)
     }
 }
 
+#[derive(Debug, Parser, Clone)]
+struct ParachainAccountOpts {
+    /// Config file.
+    #[clap(flatten)]
+    config: runtime::cli::ConfigFileOpts,
+
+    /// Keyring / keyfile options.
+    #[clap(flatten)]
+    account_info: runtime::cli::ProviderUserOpts,
+
+    /// Connection settings for the BTC Parachain.
+    #[clap(flatten)]
+    parachain: runtime::cli::ConnectionOpts,
+}
+
+impl ParachainAccountOpts {
+    async fn connect(&self) -> Result<InterBtcParachain, Error> {
+        Ok(self.connect_with_wallet_name().await?.0)
+    }
+
+    /// Also returns the wallet name, which is the prefix of the per-currency wallets.
+    async fn connect_with_wallet_name(&self) -> Result<(InterBtcParachain, String), Error> {
+        let (signer, wallet_name) = self.account_info.get_signer()?;
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
+        let parachain_rpc = InterBtcParachain::from_url_and_config_with_retry(
+            &self.parachain.btc_parachain_url,
+            signer,
+            self.parachain.max_concurrent_requests,
+            self.parachain.max_notifs_per_subscription,
+            self.parachain.btc_parachain_connection_timeout_ms,
+            shutdown_tx,
+        )
+        .await?;
+        Ok((parachain_rpc, wallet_name))
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+struct AdminCallOpts {
+    #[clap(flatten)]
+    account: ParachainAccountOpts,
+
+    /// Collateral currency of the vault, e.g. `KSM`.
+    #[clap(long)]
+    collateral_currency: String,
+
+    /// Amount in the smallest unit of the collateral currency, or of the wrapped currency for
+    /// replace requests.
+    #[clap(long)]
+    amount: u128,
+
+    /// Print the result as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct StatusOpts {
+    #[clap(flatten)]
+    account: ParachainAccountOpts,
+
+    /// Print the status as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ReconcileOpts {
+    #[clap(flatten)]
+    account: ParachainAccountOpts,
+
+    /// Connection settings for Bitcoin Core.
+    #[clap(flatten)]
+    bitcoin: bitcoin::cli::BitcoinOpts,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+impl ReconcileOpts {
+    /// Prints the report, and fails if there are any discrepancies. The wallets are only read,
+    /// so they have to be loaded already, e.g. by the running vault.
+    async fn reconcile_and_print(&self) -> Result<(), Error> {
+        let (parachain_rpc, prefix) = self.account.connect_with_wallet_name().await?;
+        let network = self.bitcoin.get_network().await?;
+
+        let mut vaults = Vec::new();
+        for vault_id in parachain_rpc
+            .get_vaults_by_account_id(parachain_rpc.get_account_id())
+            .await?
+        {
+            let btc_rpc = self
+                .bitcoin
+                .new_client_with_network(Some(vault_wallet_name(&prefix, &vault_id)?), network)?;
+            vaults.push(
+                reconcile::reconcile_vault(&btc_rpc, &parachain_rpc, &vault_id, false, &HashSet::new())
+                    .await
+                    .map_err(|err| Error::Other(err.to_string()))?,
+            );
+        }
+        let reconciliation = Reconciliation { vaults };
+
+        let output = admin::format_output(&reconciliation, self.json).map_err(|err| Error::Other(err.to_string()))?;
+        println!("{}", output);
+        match reconciliation.discrepancy_count() {
+            0 => Ok(()),
+            count => Err(Error::Other(format!("Found {} discrepancies", count))),
+        }
+    }
+}
+
+fn print_output<T: serde::Serialize + std::fmt::Display>(
+    output: Result<T, vault::Error>,
+    json: bool,
+) -> Result<(), Error> {
+    let output = output
+        .and_then(|output| admin::format_output(&output, json))
+        .map_err(|err| Error::Other(err.to_string()))?;
+    println!("{}", output);
+    Ok(())
+}
+
+#[derive(Debug, Parser, Clone)]
+struct KeyBackupConnectionOpts {
+    /// Config file.
+    #[clap(flatten)]
+    config: runtime::cli::ConfigFileOpts,
+
+    /// Keyring / keyfile options.
+    #[clap(flatten)]
+    account_info: runtime::cli::ProviderUserOpts,
+
+    /// Connection settings for the BTC Parachain.
+    #[clap(flatten)]
+    parachain: runtime::cli::ConnectionOpts,
+
+    /// Connection settings for Bitcoin Core.
+    #[clap(flatten)]
+    bitcoin: bitcoin::cli::BitcoinOpts,
+}
+
+impl KeyBackupConnectionOpts {
+    /// Connects to the parachain and to the master wallet, which holds the derivation key.
+    async fn connect(&self) -> Result<(InterBtcParachain, DynBitcoinCoreApi), Error> {
+        let (signer, wallet_name) = self.account_info.get_signer()?;
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
+        let parachain_rpc = InterBtcParachain::from_url_and_config_with_retry(
+            &self.parachain.btc_parachain_url,
+            signer,
+            self.parachain.max_concurrent_requests,
+            self.parachain.max_notifs_per_subscription,
+            self.parachain.btc_parachain_connection_timeout_ms,
+            shutdown_tx,
+        )
+        .await?;
+        let master_wallet = self.bitcoin.new_client(Some(format!("{}-master", wallet_name))).await?;
+        Ok((parachain_rpc, master_wallet))
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+struct BackupKeysOpts {
+    #[clap(flatten)]
+    connection: KeyBackupConnectionOpts,
+
+    /// Output file name or stdout if unspecified.
+    #[clap(long, value_parser)]
+    output: Option<PathBuf>,
+
+    /// File containing the password to encrypt the backup with. If unspecified, the password
+    /// is read from KEY_BACKUP_PASSWORD or prompted for.
+    #[clap(long, value_parser)]
+    password_file: Option<PathBuf>,
+}
+
+impl BackupKeysOpts {
+    async fn backup_and_write(&self) -> Result<(), Error> {
+        let (parachain_rpc, master_wallet) = self.connection.connect().await?;
+        let backup = backup::backup_keys(&parachain_rpc, &master_wallet)
+            .await
+            .map_err(|err| Error::Other(err.to_string()))?;
+
+        let password = keystore::read_new_password(self.password_file.as_deref(), BACKUP_PASSWORD_ENV, "Password: ")?;
+        let keystore = Keystore::encrypt(BACKUP_CONTENT, &serde_json::to_vec(&backup)?, &password)?;
+        try_write_file(&self.output, keystore.to_json()?)
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+struct RestoreKeysOpts {
+    #[clap(flatten)]
+    connection: KeyBackupConnectionOpts,
+
+    /// Backup file written by `backup-keys`.
+    #[clap(long, value_parser)]
+    input: PathBuf,
+
+    /// File containing the password of the backup. If unspecified, the password is read from
+    /// KEY_BACKUP_PASSWORD or prompted for.
+    #[clap(long, value_parser)]
+    password_file: Option<PathBuf>,
+}
+
+impl RestoreKeysOpts {
+    async fn read_and_restore(&self) -> Result<(), Error> {
+        let data = std::fs::read(&self.input)?;
+        let keystore = Keystore::parse(&data)
+            .ok_or_else(|| Error::Other(format!("{} is not a key backup", self.input.display())))?;
+        keystore.expect_content(BACKUP_CONTENT)?;
+        let password = keystore::read_password(self.password_file.as_deref(), BACKUP_PASSWORD_ENV, "Password: ")?;
+        let backup: KeyBackup = serde_json::from_slice(&keystore.decrypt(&password)?)?;
+
+        let (parachain_rpc, master_wallet) = self.connection.connect().await?;
+        backup::restore_keys(&parachain_rpc, &master_wallet, &backup)
+            .await
+            .map_err(|err| Error::Other(err.to_string()))?;
+        tracing::info!("Restored the derivation key of {}", backup.public_key);
+        Ok(())
+    }
+}
+
 #[derive(Parser, Debug, Clone)]
 #[clap(name = NAME, version = VERSION, author = AUTHORS, about = ABOUT)]
 pub struct RunVaultOpts {
+    /// Config file.
+    #[clap(flatten)]
+    pub config: runtime::cli::ConfigFileOpts,
+
     /// Keyring / keyfile options.
     #[clap(flatten)]
     pub account_info: runtime::cli::ProviderUserOpts,
@@ -147,9 +507,37 @@ where
     Ok(())
 }
 
+/// Reloads the settings that can be changed without a restart whenever SIGHUP is received.
+async fn reload_on_hangup(mut signals: Signals, args: Vec<OsString>, settings: SettingsSender) {
+    while signals.next().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading the configuration...");
+        match reload_settings(&args) {
+            Ok(reloaded) => {
+                // delays, fee caps and the replace policy are applied, the other options need a restart
+                let _ = settings.send(reloaded);
+            }
+            Err(err) => tracing::error!(
+                "Keeping the current configuration, the reloaded one is invalid: {}",
+                err
+            ),
+        }
+    }
+}
+
+fn reload_settings(args: &[OsString]) -> Result<ReloadableSettings, Error> {
+    let cli: Cli =
+        runtime::cli::try_parse_with_config_file(args.iter().cloned()).map_err(|err| Error::Other(err.to_string()))?;
+    let opts = match cli.sub {
+        Some(Commands::Drain(drain_opts)) => *drain_opts,
+        _ => cli.opts,
+    };
+    ReloadableSettings::from_config(&opts.vault).map_err(|err| Error::Other(err.to_string()))
+}
+
 async fn start() -> Result<(), Error> {
-    let cli: Cli = Cli::parse();
-    let opts = cli.opts;
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let cli: Cli = runtime::cli::try_parse_with_config_file(args.clone()).unwrap_or_else(|err| err.exit());
+    let mut opts = cli.opts;
     opts.service.logging_format.init_subscriber();
 
     match cli.sub {
@@ -159,11 +547,61 @@ async fn start() -> Result<(), Error> {
         Some(Commands::GenerateParachainKey(opts)) => {
             return opts.generate_and_write();
         }
+        Some(Commands::ExportHistory(opts)) => {
+            return opts.export_and_write().await;
+        }
+        Some(Commands::DepositCollateral(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            let output = admin::deposit_collateral(&parachain_rpc, &opts.collateral_currency, opts.amount).await;
+            return print_output(output, opts.json);
+        }
+        Some(Commands::WithdrawCollateral(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            let output = admin::withdraw_collateral(&parachain_rpc, &opts.collateral_currency, opts.amount).await;
+            return print_output(output, opts.json);
+        }
+        Some(Commands::Register(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            let output = admin::register(&parachain_rpc, &opts.collateral_currency, opts.amount).await;
+            return print_output(output, opts.json);
+        }
+        Some(Commands::RequestReplace(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            let output = admin::request_replace(&parachain_rpc, &opts.collateral_currency, opts.amount).await;
+            return print_output(output, opts.json);
+        }
+        Some(Commands::WithdrawReplace(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            let output = admin::withdraw_replace(&parachain_rpc, &opts.collateral_currency, opts.amount).await;
+            return print_output(output, opts.json);
+        }
+        Some(Commands::Status(opts)) => {
+            let parachain_rpc = opts.account.connect().await?;
+            return print_output(admin::get_status(&parachain_rpc).await, opts.json);
+        }
+        Some(Commands::Reconcile(opts)) => {
+            return opts.reconcile_and_print().await;
+        }
+        Some(Commands::BackupKeys(opts)) => {
+            return opts.backup_and_write().await;
+        }
+        Some(Commands::RestoreKeys(opts)) => {
+            return opts.read_and_restore().await;
+        }
+        Some(Commands::Drain(drain_opts)) => {
+            opts = *drain_opts;
+            opts.vault.drain = true;
+        }
         _ => (),
     }
 
-    let (pair, wallet_name) = opts.account_info.get_key_pair()?;
-    let signer = InterBtcSigner::new(pair);
+    opts.vault.validate()?;
+    let (signer, wallet_name) = opts.account_info.get_signer()?;
+
+    let settings = ReloadableSettings::from_config(&opts.vault).map_err(|err| Error::Other(err.to_string()))?;
+    let (settings_tx, settings_rx) = watch::channel(settings);
+    opts.vault.reload = Some(settings_rx);
+    tokio::spawn(reload_on_hangup(Signals::new(&[SIGHUP])?, args, settings_tx));
 
     let vault_connection_manager = ConnectionManager::<_, VaultService, _>::new(
         signer.clone(),
@@ -209,7 +647,7 @@ async fn start() -> Result<(), Error> {
     // Unless termination signals are caught, the PID file is not dropped.
     let main_task = async move { vault_connection_manager.start().await };
     catch_signals(
-        Signals::new(&[SIGHUP, SIGTERM, SIGINT, SIGQUIT]).expect("Failed to set up signal listener."),
+        Signals::new(&[SIGTERM, SIGINT, SIGQUIT]).expect("Failed to set up signal listener."),
         main_task,
     )
     .await
@@ -235,7 +673,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_vault_termination_signal() {
-        let termination_signals = &[SIGHUP, SIGTERM, SIGINT, SIGQUIT];
+        let termination_signals = &[SIGTERM, SIGINT, SIGQUIT];
         for sig in termination_signals {
             let task = tokio::spawn(catch_signals(Signals::new(termination_signals).unwrap(), async {
                 tokio::time::sleep(Duration::from_millis(100_000)).await;
@@ -254,7 +692,7 @@ mod tests {
     async fn test_vault_pid_file() {
         let dummy_account_id = AccountId::new(Default::default());
         let dummy_spec_name = "kintsugi-testnet".to_string();
-        let termination_signals = &[SIGHUP, SIGTERM, SIGINT, SIGQUIT];
+        let termination_signals = &[SIGTERM, SIGINT, SIGQUIT];
         let mut sys = System::new_all();
 
         let task = tokio::spawn({
@@ -10,8 +10,8 @@ use futures::{try_join, StreamExt, TryFutureExt};
 use lazy_static::lazy_static;
 use runtime::{
     prometheus::{
-        gather, proto::MetricFamily, Encoder, Gauge, GaugeVec, IntCounter, IntGauge, IntGaugeVec, Opts, Registry,
-        TextEncoder,
+        gather, proto::MetricFamily, Encoder, Gauge, GaugeVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+        Registry, TextEncoder,
     },
     CollateralBalancesPallet, CurrencyId, CurrencyIdExt, CurrencyInfo, Error, FeedValuesEvent, FixedU128,
     InterBtcParachain, InterBtcRedeemRequest, IssuePallet, IssueRequestStatus, OracleKey, RedeemPallet,
@@ -19,7 +19,7 @@ use runtime::{
 };
 use service::{
     warp::{Rejection, Reply},
-    Error as ServiceError,
+    Error as ServiceError, HealthRegistry, TaskStatus,
 };
 use std::time::Duration;
 use tokio::{sync::RwLock, time::sleep};
@@ -32,6 +32,7 @@ const CURRENCY_LABEL: &str = "currency";
 const BTC_BALANCE_TYPE_LABEL: &str = "type";
 const REQUEST_STATUS_LABEL: &str = "status";
 const TASK_NAME: &str = "task";
+const FEE_CAP_REASON_LABEL: &str = "reason";
 const TOKIO_POLLING_INTERVAL_MS: u64 = 10000;
 
 // Metrics are stored under the [`CURRENCY_LABEL`] key so that multiple vaults can be easily
@@ -95,6 +96,19 @@ lazy_static! {
             .expect("Failed to create prometheus metric");
     pub static ref RESTART_COUNT: IntCounter =
         IntCounter::new("restart_count", "Number of service restarts").expect("Failed to create prometheus metric");
+    pub static ref TASK_RESTARTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("task_restarts", "Number of restarts of a failed task"),
+        &[TASK_NAME]
+    )
+    .expect("Failed to create prometheus metric");
+    pub static ref TASK_RUNNING: IntGaugeVec =
+        IntGaugeVec::new(Opts::new("task_running", "Whether a task is running"), &[TASK_NAME])
+            .expect("Failed to create prometheus metric");
+    pub static ref FEE_CAP_HITS: IntCounterVec = IntCounterVec::new(
+        Opts::new("fee_cap_hits", "Number of payments or fee bumps limited by a fee cap"),
+        &[FEE_CAP_REASON_LABEL]
+    )
+    .expect("Failed to create prometheus metric");
 }
 
 #[derive(Clone, Debug)]
@@ -300,6 +314,9 @@ pub fn register_custom_metrics() -> Result<(), Error> {
     REGISTRY.register(Box::new(MEAN_SCHEDULED_DURATION.clone()))?;
     REGISTRY.register(Box::new(REMAINING_TIME_TO_REDEEM_HOURS.clone()))?;
     REGISTRY.register(Box::new(RESTART_COUNT.clone()))?;
+    REGISTRY.register(Box::new(FEE_CAP_HITS.clone()))?;
+    REGISTRY.register(Box::new(TASK_RESTARTS.clone()))?;
+    REGISTRY.register(Box::new(TASK_RUNNING.clone()))?;
 
     Ok(())
 }
@@ -448,6 +465,10 @@ pub fn increment_restart_counter() {
     RESTART_COUNT.inc();
 }
 
+pub fn increment_fee_cap_counter(reason: &str) {
+    FEE_CAP_HITS.with_label_values(&[reason]).inc();
+}
+
 async fn publish_issue_count<V: VaultDataReader, P: IssuePallet + UtilFuncs>(parachain_rpc: &P, vault_id_manager: &V) {
     if let Ok(issues) = parachain_rpc
         .get_vault_issue_requests(parachain_rpc.get_account_id().clone())
@@ -647,6 +668,7 @@ pub async fn publish_expected_bitcoin_balance<P: VaultRegistryPallet>(
 
 pub async fn publish_tokio_metrics(
     mut metrics_iterators: HashMap<String, impl Iterator<Item = TaskMetrics>>,
+    health: HealthRegistry,
 ) -> Result<(), ServiceError> {
     let frequency = Duration::from_millis(TOKIO_POLLING_INTERVAL_MS);
     loop {
@@ -664,6 +686,13 @@ pub async fn publish_tokio_metrics(
                     .set(task_metrics.mean_scheduled_duration().as_millis() as i64);
             }
         }
+        for (key, task) in health.snapshot() {
+            let label = HashMap::<&str, &str>::from([(TASK_NAME, &key[..])]);
+            TASK_RESTARTS.with(&label).set(task.restarts as i64);
+            TASK_RUNNING
+                .with(&label)
+                .set((task.status == TaskStatus::Running) as i64);
+        }
         tokio::time::sleep(frequency).await;
     }
 }
@@ -671,10 +700,11 @@ pub async fn publish_tokio_metrics(
 #[cfg(all(test, feature = "parachain-metadata-kintsugi-testnet"))]
 mod tests {
     use super::*;
+    use crate::journal::PaymentJournal;
     use async_trait::async_trait;
     use bitcoin::{
         json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Network,
-        PrivateKey, PublicKey, SatPerVbyte, Transaction, TransactionMetadata, Txid,
+        PrivateKey, PublicKey, SatPerVbyte, Transaction, TransactionMetadata, Txid, WalletTransaction,
     };
     use jsonrpc_core::serde_json::{Map, Value};
     use runtime::{
@@ -728,6 +758,7 @@ mod tests {
             async fn register_vault(&self, vault_id: &VaultId, collateral: u128) -> Result<(), RuntimeError>;
             async fn deposit_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
             async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+            async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), RuntimeError>;
             async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, RuntimeError>;
             async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), RuntimeError>;
             async fn get_required_collateral_for_wrapped(&self, amount_btc: u128, collateral_currency: CurrencyId) -> Result<u128, RuntimeError>;
@@ -788,6 +819,7 @@ mod tests {
             async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
             fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
             fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
             async fn get_block_count(&self) -> Result<u64, BitcoinError>;
             async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
             async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
@@ -805,6 +837,7 @@ mod tests {
             async fn get_mempool_transactions<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError>;
             async fn wait_for_transaction_metadata(&self, txid: Txid, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
             async fn create_and_send_transaction(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, max_fee: u64, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
             async fn send_to_address(&self, address: Address, sat: u64, request_id: Option<H256>, fee_rate: SatPerVbyte, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
             async fn create_or_load_wallet(&self) -> Result<(), BitcoinError>;
             async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError>;
@@ -993,6 +1026,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         publish_expected_bitcoin_balance(&vault_data, parachain_rpc)
@@ -1024,6 +1058,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         update_bitcoin_metrics(&vault_data, Some(SignedAmount::from_sat(125)), Some(122))
@@ -1048,6 +1083,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
         publish_utxo_count(&vault_data);
 
@@ -1072,6 +1108,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         publish_locked_collateral(&vault_data, parachain_rpc).await.unwrap();
@@ -1094,6 +1131,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         publish_collateralization(&vault_data, parachain_rpc).await;
@@ -1123,6 +1161,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         publish_required_collateral(&vault_data, parachain_rpc).await.unwrap();
@@ -1190,6 +1229,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         let mut vault_id_manager = MockVaultIdManager::default();
@@ -1246,6 +1286,7 @@ mod tests {
             vault_id: dummy_vault_id(),
             btc_rpc,
             metrics: PerCurrencyMetrics::dummy(),
+            journal: PaymentJournal::temporary(),
         };
 
         let mut vault_id_manager = MockVaultIdManager::default();
@@ -0,0 +1,548 @@
+//! Reconciliation of the bitcoin wallets against the state of the vaults on the parachain.
+//! Differences that can not be explained by open requests, e.g. deposits to unknown
+//! addresses or payments without an OP_RETURN, are reported as discrepancies.
+
+use crate::{
+    error::Error,
+    history::{self, HistoryEntry},
+    notify::{Notification, Notifier},
+    system::VaultIdManager,
+};
+use bitcoin::TransactionExt;
+use runtime::{Error as RuntimeError, InterBtcParachain, PrettyPrint, VaultId, VaultRegistryPallet, H256};
+use serde::Serialize;
+use service::{DynBitcoinCoreApi, Error as ServiceError};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    time::Duration,
+};
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// The wallet holds less than the issued tokens which are not being redeemed, after
+    /// the fees the wallet paid.
+    BalanceBelowExpected { balance_sat: u64, expected_sat: u128 },
+    /// The wallet holds more than the issued tokens and the pending issues.
+    BalanceAboveExpected { balance_sat: u64, expected_sat: u128 },
+    /// An incoming payment to an address that does not belong to an issue request.
+    UnknownDeposit { txid: String, amount_sat: i64 },
+    /// An outgoing payment without an OP_RETURN output, so it does not belong to any request.
+    PaymentWithoutOpReturn { txid: String, amount_sat: i64 },
+    /// An outgoing payment whose OP_RETURN does not match a redeem or replace request.
+    PaymentForUnknownRequest {
+        txid: String,
+        amount_sat: i64,
+        request_id: H256,
+    },
+}
+
+impl Discrepancy {
+    /// The name of the variant, as in the JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Discrepancy::BalanceBelowExpected { .. } => "balance_below_expected",
+            Discrepancy::BalanceAboveExpected { .. } => "balance_above_expected",
+            Discrepancy::UnknownDeposit { .. } => "unknown_deposit",
+            Discrepancy::PaymentWithoutOpReturn { .. } => "payment_without_op_return",
+            Discrepancy::PaymentForUnknownRequest { .. } => "payment_for_unknown_request",
+        }
+    }
+
+    /// The transaction that caused the discrepancy, or `None` for the balance.
+    pub fn txid(&self) -> Option<&str> {
+        match self {
+            Discrepancy::BalanceBelowExpected { .. } | Discrepancy::BalanceAboveExpected { .. } => None,
+            Discrepancy::UnknownDeposit { txid, .. }
+            | Discrepancy::PaymentWithoutOpReturn { txid, .. }
+            | Discrepancy::PaymentForUnknownRequest { txid, .. } => Some(txid),
+        }
+    }
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::BalanceBelowExpected {
+                balance_sat,
+                expected_sat,
+            } => write!(
+                f,
+                "balance of {balance_sat} sat is below the expected {expected_sat} sat"
+            ),
+            Discrepancy::BalanceAboveExpected {
+                balance_sat,
+                expected_sat,
+            } => write!(
+                f,
+                "balance of {balance_sat} sat is above the expected {expected_sat} sat"
+            ),
+            Discrepancy::UnknownDeposit { txid, amount_sat } => {
+                write!(f, "deposit of {amount_sat} sat to an unknown address in {txid}")
+            }
+            Discrepancy::PaymentWithoutOpReturn { txid, amount_sat } => {
+                write!(f, "payment of {amount_sat} sat without OP_RETURN in {txid}")
+            }
+            Discrepancy::PaymentForUnknownRequest {
+                txid,
+                amount_sat,
+                request_id,
+            } => write!(
+                f,
+                "payment of {amount_sat} sat for unknown request #{request_id:?} in {txid}"
+            ),
+        }
+    }
+}
+
+/// The balance the wallet of a vault should hold according to the parachain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ExpectedBalance {
+    pub lowerbound_sat: u128,
+    pub upperbound_sat: u128,
+}
+
+impl ExpectedBalance {
+    /// Like `publish_expected_bitcoin_balance`, but the lower bound is reduced by the fees
+    /// the wallet paid, since those are not accounted for on the parachain.
+    pub fn new(
+        issued_tokens: u128,
+        to_be_issued_tokens: u128,
+        to_be_redeemed_tokens: u128,
+        fees_paid_sat: u64,
+    ) -> Self {
+        Self {
+            lowerbound_sat: issued_tokens
+                .saturating_sub(to_be_redeemed_tokens)
+                .saturating_sub(fees_paid_sat.into()),
+            upperbound_sat: issued_tokens.saturating_add(to_be_issued_tokens),
+        }
+    }
+
+    fn check(&self, balance_sat: u64) -> Option<Discrepancy> {
+        let balance = u128::from(balance_sat);
+        if balance < self.lowerbound_sat {
+            Some(Discrepancy::BalanceBelowExpected {
+                balance_sat,
+                expected_sat: self.lowerbound_sat,
+            })
+        } else if balance > self.upperbound_sat {
+            Some(Discrepancy::BalanceAboveExpected {
+                balance_sat,
+                expected_sat: self.upperbound_sat,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultReconciliation {
+    pub vault_id: String,
+    /// Not available once the vault is liquidated.
+    pub expected_balance: Option<ExpectedBalance>,
+    pub balance_sat: u64,
+    pub utxo_count: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Display for VaultReconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.vault_id)?;
+        writeln!(f, "  balance:   {} sat in {} utxos", self.balance_sat, self.utxo_count)?;
+        match &self.expected_balance {
+            Some(expected) => writeln!(
+                f,
+                "  expected:  {} to {} sat",
+                expected.lowerbound_sat, expected.upperbound_sat
+            )?,
+            None => writeln!(f, "  expected:  - (liquidated)")?,
+        }
+        if self.discrepancies.is_empty() {
+            writeln!(f, "  no discrepancies")?;
+        }
+        for discrepancy in &self.discrepancies {
+            writeln!(f, "  ALERT: {}", discrepancy)?;
+        }
+        Ok(())
+    }
+}
+
+/// The reconciliation of all vaults of the account.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reconciliation {
+    pub vaults: Vec<VaultReconciliation>,
+}
+
+impl Reconciliation {
+    pub fn discrepancy_count(&self) -> usize {
+        self.vaults.iter().map(|vault| vault.discrepancies.len()).sum()
+    }
+}
+
+impl Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, vault) in self.vaults.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", vault)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transactions of the history that do not belong to a request. Transfers within the wallet,
+/// replaced transactions and those in `reported_txids` are ignored.
+async fn find_unexplained_transactions(
+    btc_rpc: &DynBitcoinCoreApi,
+    history: &[HistoryEntry],
+    reported_txids: &HashSet<String>,
+) -> Result<Vec<Discrepancy>, Error> {
+    let mut discrepancies = Vec::new();
+    for entry in history {
+        let transaction = &entry.transaction;
+        if entry.request.is_some() || transaction.replaced || transaction.amount.to_sat() == 0 {
+            continue;
+        }
+        let txid = transaction.txid.to_string();
+        if reported_txids.contains(&txid) {
+            continue;
+        }
+        let amount_sat = transaction.amount.to_sat();
+        if transaction.amount.is_positive() {
+            discrepancies.push(Discrepancy::UnknownDeposit { txid, amount_sat });
+            continue;
+        }
+        let tx = btc_rpc
+            .get_transaction(&transaction.txid, transaction.block.map(|(hash, _)| hash))
+            .await?;
+        discrepancies.push(match tx.get_op_return() {
+            Some(request_id) => Discrepancy::PaymentForUnknownRequest {
+                txid,
+                amount_sat,
+                request_id,
+            },
+            None => Discrepancy::PaymentWithoutOpReturn { txid, amount_sat },
+        });
+    }
+    Ok(discrepancies)
+}
+
+/// Compares the wallet of the vault with its issued, to-be-issued and to-be-redeemed tokens,
+/// and lists the wallet transactions that do not belong to a request, except those in
+/// `reported_txids`. See [`history::get_vault_history`] for `import_deposit_keys`.
+pub async fn reconcile_vault(
+    btc_rpc: &DynBitcoinCoreApi,
+    parachain_rpc: &InterBtcParachain,
+    vault_id: &VaultId,
+    import_deposit_keys: bool,
+    reported_txids: &HashSet<String>,
+) -> Result<VaultReconciliation, Error> {
+    let history = history::get_vault_history(btc_rpc, parachain_rpc, vault_id, import_deposit_keys).await?;
+    let fees_paid_sat: u64 = history
+        .iter()
+        .filter(|entry| !entry.transaction.replaced)
+        .filter_map(|entry| entry.transaction.fee)
+        .map(|fee| fee.to_sat().unsigned_abs())
+        .sum();
+    let expected_balance = match parachain_rpc.get_vault(vault_id).await {
+        Ok(vault) => Some(ExpectedBalance::new(
+            vault.issued_tokens,
+            vault.to_be_issued_tokens,
+            vault.to_be_redeemed_tokens,
+            fees_paid_sat,
+        )),
+        Err(RuntimeError::VaultLiquidated) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let balance_sat = btc_rpc.get_balance(None)?.to_sat();
+
+    let mut discrepancies: Vec<_> = expected_balance
+        .and_then(|expected| expected.check(balance_sat))
+        .into_iter()
+        .collect();
+    discrepancies.extend(find_unexplained_transactions(btc_rpc, &history, reported_txids).await?);
+
+    Ok(VaultReconciliation {
+        vault_id: vault_id.pretty_print(),
+        expected_balance,
+        balance_sat,
+        utxo_count: btc_rpc.get_utxo_count()?,
+        discrepancies,
+    })
+}
+
+/// Reconciles all vaults at the given interval, and notifies about every discrepancy. The
+/// transactions in the history stay unexplained, so they are only reported once, while a
+/// wrong balance is reported again after the deduplication window of the notifier.
+pub async fn reconcile_periodically(
+    parachain_rpc: InterBtcParachain,
+    vault_id_manager: VaultIdManager,
+    notifier: Notifier,
+    interval: Duration,
+) -> Result<(), ServiceError> {
+    let mut reported_txids: HashMap<VaultId, HashSet<String>> = HashMap::new();
+    loop {
+        for (vault_id, btc_rpc) in vault_id_manager.get_vault_btc_rpcs().await {
+            let reported = reported_txids.entry(vault_id.clone()).or_default();
+            match reconcile_vault(&btc_rpc, &parachain_rpc, &vault_id, true, reported).await {
+                Ok(reconciliation) => {
+                    for discrepancy in reconciliation.discrepancies {
+                        let txid = discrepancy.txid().map(ToString::to_string);
+                        if let Some(txid) = &txid {
+                            reported.insert(txid.clone());
+                        }
+                        tracing::warn!("[{}] Reconciliation: {}", reconciliation.vault_id, discrepancy);
+                        notifier.notify(Notification::ReconciliationAlert {
+                            vault_id: reconciliation.vault_id.clone(),
+                            kind: discrepancy.kind(),
+                            txid,
+                            discrepancy: discrepancy.to_string(),
+                        });
+                    }
+                }
+                Err(err) => tracing::warn!("[{}] Failed to reconcile: {}", vault_id.pretty_print(), err),
+            }
+        }
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::RequestType;
+    use async_trait::async_trait;
+    use bitcoin::{
+        json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Network,
+        PackedLockTime, PrivateKey, PublicKey, SatPerVbyte, Script, SignedAmount, Transaction, TransactionMetadata,
+        TxOut, Txid, WalletTransaction,
+    };
+    use std::sync::Arc;
+
+    mockall::mock! {
+        Bitcoin {}
+
+        #[async_trait]
+        trait BitcoinCoreApi {
+            fn network(&self) -> Network;
+            async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
+            async fn get_block_count(&self) -> Result<u64, BitcoinError>;
+            fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
+            fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
+            async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
+            async fn get_proof(&self, txid: Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
+            async fn get_block_hash(&self, height: u32) -> Result<BlockHash, BitcoinError>;
+            async fn get_new_address(&self) -> Result<Address, BitcoinError>;
+            async fn get_new_public_key(&self) -> Result<PublicKey, BitcoinError>;
+            fn dump_derivation_key(&self, public_key: &PublicKey) -> Result<PrivateKey, BitcoinError>;
+            fn import_derivation_key(&self, private_key: &PrivateKey) -> Result<(), BitcoinError>;
+            async fn add_new_deposit_key(&self, public_key: PublicKey, secret_key: Vec<u8>) -> Result<(), BitcoinError>;
+            async fn get_best_block_hash(&self) -> Result<BlockHash, BitcoinError>;
+            async fn get_pruned_height(&self) -> Result<u64, BitcoinError>;
+            async fn get_block(&self, hash: &BlockHash) -> Result<Block, BitcoinError>;
+            async fn get_block_header(&self, hash: &BlockHash) -> Result<BlockHeader, BitcoinError>;
+            async fn get_mempool_transactions<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<Transaction, BitcoinError>> + Send + 'a>, BitcoinError>;
+            async fn wait_for_transaction_metadata(&self, txid: Txid, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn bump_fee(&self, txid: &Txid, address: Address, fee_rate: SatPerVbyte) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(&self, address: Address, sat: u64, fee_rate: SatPerVbyte, max_fee: u64, request_id: Option<H256>) -> Result<Txid, BitcoinError>;
+            async fn send_to_address(&self, address: Address, sat: u64, request_id: Option<H256>, fee_rate: SatPerVbyte, num_confirmations: u32) -> Result<TransactionMetadata, BitcoinError>;
+            async fn create_or_load_wallet(&self) -> Result<(), BitcoinError>;
+            async fn rescan_blockchain(&self, start_height: usize, end_height: usize) -> Result<(), BitcoinError>;
+            async fn rescan_electrs_for_addresses(&self, addresses: Vec<Address>) -> Result<(), BitcoinError>;
+            fn get_utxo_count(&self) -> Result<usize, BitcoinError>;
+            async fn is_in_mempool(&self, txid: Txid) -> Result<bool, BitcoinError>;
+            async fn fee_rate(&self, txid: Txid) -> Result<SatPerVbyte, BitcoinError>;
+        }
+    }
+
+    fn transaction(op_return: Option<H256>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: op_return
+                .map(|request_id| TxOut {
+                    value: 0,
+                    script_pubkey: Script::new_op_return(request_id.as_bytes()),
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn entry(txid: Txid, amount_sat: i64, request: Option<(RequestType, H256)>) -> HistoryEntry {
+        HistoryEntry {
+            vault_id: "vault[KSM->KBTC]".to_string(),
+            transaction: WalletTransaction {
+                txid,
+                block: None,
+                amount: SignedAmount::from_sat(amount_sat),
+                fee: None,
+                replaced: false,
+            },
+            request,
+        }
+    }
+
+    fn expect_transaction(btc_rpc: &mut MockBitcoin, tx: Transaction) {
+        let txid = tx.txid();
+        btc_rpc
+            .expect_get_transaction()
+            .withf(move |id, _| *id == txid)
+            .times(1)
+            .returning(move |_, _| Ok(tx.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_deposit() {
+        // deposits are recognized by their amount, without looking up the transaction
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(MockBitcoin::default());
+        let txid = transaction(None).txid();
+        let history = vec![entry(txid, 10_000, None)];
+
+        let discrepancies = find_unexplained_transactions(&btc_rpc, &history, &HashSet::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            discrepancies,
+            vec![Discrepancy::UnknownDeposit {
+                txid: txid.to_string(),
+                amount_sat: 10_000
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_without_op_return() {
+        let tx = transaction(None);
+        let txid = tx.txid();
+        let mut btc_rpc = MockBitcoin::default();
+        expect_transaction(&mut btc_rpc, tx);
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(btc_rpc);
+        let history = vec![entry(txid, -10_000, None)];
+
+        let discrepancies = find_unexplained_transactions(&btc_rpc, &history, &HashSet::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            discrepancies,
+            vec![Discrepancy::PaymentWithoutOpReturn {
+                txid: txid.to_string(),
+                amount_sat: -10_000
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_for_unknown_request() {
+        let request_id = H256::from_low_u64_be(1);
+        let tx = transaction(Some(request_id));
+        let txid = tx.txid();
+        let mut btc_rpc = MockBitcoin::default();
+        expect_transaction(&mut btc_rpc, tx);
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(btc_rpc);
+        // the payment of a known request is explained, so it is not looked up
+        let history = vec![
+            entry(txid, -10_000, None),
+            entry(
+                transaction(Some(H256::from_low_u64_be(2))).txid(),
+                -5_000,
+                Some((RequestType::Redeem, H256::from_low_u64_be(2))),
+            ),
+        ];
+
+        let discrepancies = find_unexplained_transactions(&btc_rpc, &history, &HashSet::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            discrepancies,
+            vec![Discrepancy::PaymentForUnknownRequest {
+                txid: txid.to_string(),
+                amount_sat: -10_000,
+                request_id,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reported_transactions_are_skipped() {
+        // get_transaction is not expected, so looking up the payment fails the test
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(MockBitcoin::default());
+        let txid = transaction(None).txid();
+        let history = vec![entry(txid, -10_000, None)];
+        let reported_txids: HashSet<_> = vec![txid.to_string()].into_iter().collect();
+
+        let discrepancies = find_unexplained_transactions(&btc_rpc, &history, &reported_txids)
+            .await
+            .unwrap();
+        assert!(discrepancies.is_empty());
+    }
+
+    #[test]
+    fn test_expected_balance() {
+        // 1000 issued, 200 being issued, 300 being redeemed, 10 paid in fees
+        let expected = ExpectedBalance::new(1000, 200, 300, 10);
+        assert_eq!(expected.lowerbound_sat, 690);
+        assert_eq!(expected.upperbound_sat, 1200);
+
+        assert_eq!(expected.check(690), None);
+        assert_eq!(expected.check(1200), None);
+        assert_eq!(
+            expected.check(689),
+            Some(Discrepancy::BalanceBelowExpected {
+                balance_sat: 689,
+                expected_sat: 690
+            })
+        );
+        assert_eq!(
+            expected.check(1201),
+            Some(Discrepancy::BalanceAboveExpected {
+                balance_sat: 1201,
+                expected_sat: 1200
+            })
+        );
+    }
+
+    #[test]
+    fn test_format_reconciliation() {
+        let reconciliation = Reconciliation {
+            vaults: vec![VaultReconciliation {
+                vault_id: "vault[KSM->KBTC]".to_string(),
+                expected_balance: Some(ExpectedBalance::new(1000, 0, 0, 0)),
+                balance_sat: 900,
+                utxo_count: 2,
+                discrepancies: vec![
+                    Discrepancy::BalanceBelowExpected {
+                        balance_sat: 900,
+                        expected_sat: 1000,
+                    },
+                    Discrepancy::PaymentWithoutOpReturn {
+                        txid: "txid".to_string(),
+                        amount_sat: -100,
+                    },
+                ],
+            }],
+        };
+        assert_eq!(reconciliation.discrepancy_count(), 2);
+
+        let text = reconciliation.to_string();
+        assert!(text.contains("balance:   900 sat in 2 utxos"));
+        assert!(text.contains("ALERT: payment of -100 sat without OP_RETURN in txid"));
+
+        let json = serde_json::to_value(&reconciliation).unwrap();
+        for (index, discrepancy) in reconciliation.vaults[0].discrepancies.iter().enumerate() {
+            assert_eq!(json["vaults"][0]["discrepancies"][index]["kind"], discrepancy.kind());
+        }
+        assert_eq!(reconciliation.vaults[0].discrepancies[0].txid(), None);
+        assert_eq!(reconciliation.vaults[0].discrepancies[1].txid(), Some("txid"));
+        assert_eq!(json["vaults"][0]["expected_balance"]["lowerbound_sat"], 1000);
+    }
+}
@@ -1,5 +1,12 @@
-use crate::{execution::*, metrics::publish_expected_bitcoin_balance, system::VaultIdManager};
-use runtime::{InterBtcParachain, RedeemPallet, RequestRedeemEvent};
+use crate::{
+    deadline::DeadlineEscalation,
+    execution::*,
+    metrics::publish_expected_bitcoin_balance,
+    notify::{Notification, Notifier},
+    reload::Reloadable,
+    system::VaultIdManager,
+};
+use runtime::{InterBtcParachain, PrettyPrint, RedeemPallet, RequestRedeemEvent};
 use service::{spawn_cancelable, Error as ServiceError, ShutdownSender};
 use std::time::Duration;
 
@@ -12,6 +19,9 @@ use std::time::Duration;
 /// * `btc_rpc` - the bitcoin RPC handle
 /// * `network` - network the bitcoin network used (i.e. regtest/testnet/mainnet)
 /// * `num_confirmations` - the number of bitcoin confirmation to await
+/// * `notifier` - notifies the operator about received, paid and failed requests
+/// * `escalation` - when to escalate payments that are not confirming in time
+#[allow(clippy::too_many_arguments)]
 pub async fn listen_for_redeem_requests(
     shutdown_tx: ShutdownSender,
     parachain_rpc: InterBtcParachain,
@@ -19,6 +29,9 @@ pub async fn listen_for_redeem_requests(
     num_confirmations: u32,
     payment_margin: Duration,
     auto_rbf: bool,
+    fee_caps: Reloadable<FeeCaps>,
+    notifier: Notifier,
+    escalation: DeadlineEscalation,
 ) -> Result<(), ServiceError> {
     parachain_rpc
         .on_event::<RequestRedeemEvent, _, _, _>(
@@ -29,6 +42,11 @@ pub async fn listen_for_redeem_requests(
                 };
 
                 tracing::info!("Received redeem request: {:?}", event);
+                notifier.notify(Notification::RedeemReceived {
+                    vault_id: event.vault_id.pretty_print(),
+                    redeem_id: event.redeem_id,
+                    amount: event.amount,
+                });
 
                 let _ = publish_expected_bitcoin_balance(&vault, parachain_rpc.clone()).await;
 
@@ -36,6 +54,10 @@ pub async fn listen_for_redeem_requests(
                 // by reference. Since spawn requires static lifetimes, we will need to capture the
                 // arguments by value rather than by reference, so clone these:
                 let parachain_rpc = parachain_rpc.clone();
+                let notifier = notifier.clone();
+                let escalation = escalation.clone();
+                // reloaded fee caps apply to the payments started afterwards
+                let fee_caps = fee_caps.get();
                 // Spawn a new task so that we handle these events concurrently
                 spawn_cancelable(shutdown_tx.subscribe(), async move {
                     tracing::info!("Executing redeem #{:?}", event.redeem_id);
@@ -46,22 +68,40 @@ pub async fn listen_for_redeem_requests(
                             payment_margin,
                         )?;
                         request
-                            .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf)
+                            .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf, fee_caps, escalation)
                             .await
                     }
                     .await;
 
                     match result {
-                        Ok(_) => tracing::info!(
-                            "Completed redeem request #{} with amount {}",
-                            event.redeem_id,
-                            event.amount
-                        ),
-                        Err(e) => tracing::error!(
-                            "Failed to process redeem request #{}: {}",
-                            event.redeem_id,
-                            e.to_string()
-                        ),
+                        Ok(_) => {
+                            tracing::info!(
+                                "Completed redeem request #{} with amount {}",
+                                event.redeem_id,
+                                event.amount
+                            );
+                            notifier.notify(Notification::RedeemPaid {
+                                vault_id: event.vault_id.pretty_print(),
+                                redeem_id: event.redeem_id,
+                            });
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Failed to process redeem request #{}: {}",
+                                event.redeem_id,
+                                e.to_string()
+                            );
+                            if let Some(notification) =
+                                Notification::from_payment_error(&event.vault_id, event.redeem_id, &e)
+                            {
+                                notifier.notify(notification);
+                            }
+                            notifier.notify(Notification::RedeemFailed {
+                                vault_id: event.vault_id.pretty_print(),
+                                redeem_id: event.redeem_id,
+                                reason: e.to_string(),
+                            });
+                        }
                     }
                 });
             },
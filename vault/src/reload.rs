@@ -0,0 +1,188 @@
+//! Settings that are reloaded on SIGHUP without restarting the vault. Connection settings
+//! are not reloaded, since that requires reconnecting to the parachain and bitcoin core.
+
+use crate::{
+    delay::{OrderedVaultsDelay, RandomDelay, ZeroDelay},
+    execution::FeeCaps,
+    replace_policy::{ReplaceCandidate, ReplacePolicy},
+    Error, VaultServiceConfig,
+};
+use async_trait::async_trait;
+use runtime::{Error as RuntimeError, InterBtcParachain, VaultId};
+use service::Error as ServiceError;
+use std::{
+    fmt,
+    sync::{Arc, PoisonError, RwLock},
+};
+use tokio::sync::watch;
+
+/// A value that is shared with the running tasks and can be replaced while they use it.
+pub struct Reloadable<T>(Arc<RwLock<T>>);
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Reloadable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).fmt(f)
+    }
+}
+
+impl<T: Clone> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+
+    pub fn get(&self) -> T {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = value;
+    }
+}
+
+impl<T: Clone> From<T> for Reloadable<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl ReplacePolicy for Reloadable<Arc<Box<dyn ReplacePolicy + Send + Sync>>> {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        self.get().check(candidate)
+    }
+
+    fn vault_priority(&self, vault_id: &VaultId) -> usize {
+        self.get().vault_priority(vault_id)
+    }
+}
+
+#[async_trait]
+impl RandomDelay for Reloadable<Arc<Box<dyn RandomDelay + Send + Sync>>> {
+    async fn delay(&self, seed_data: &[u8; 32]) -> Result<(), RuntimeError> {
+        let delay = self.get();
+        delay.delay(seed_data).await
+    }
+}
+
+/// The subset of the [`VaultServiceConfig`] that is reloaded on SIGHUP.
+#[derive(Debug, Clone)]
+pub struct ReloadableSettings {
+    pub no_random_delay: bool,
+    pub fee_caps: FeeCaps,
+    pub replace_policy: Arc<Box<dyn ReplacePolicy + Send + Sync>>,
+}
+
+impl ReloadableSettings {
+    /// Validates the reloadable settings of `config`.
+    pub fn from_config(config: &VaultServiceConfig) -> Result<Self, Error> {
+        Ok(Self {
+            no_random_delay: config.no_random_delay,
+            fee_caps: config.fee_caps(),
+            replace_policy: Arc::new(config.replace_policy()?),
+        })
+    }
+}
+
+pub type SettingsSender = watch::Sender<ReloadableSettings>;
+pub type SettingsReceiver = watch::Receiver<ReloadableSettings>;
+
+/// The settings in use by the running tasks.
+#[derive(Debug, Clone)]
+pub(crate) struct ActiveSettings {
+    pub(crate) random_delay: Reloadable<Arc<Box<dyn RandomDelay + Send + Sync>>>,
+    pub(crate) fee_caps: Reloadable<FeeCaps>,
+    pub(crate) replace_policy: Reloadable<Arc<Box<dyn ReplacePolicy + Send + Sync>>>,
+    no_random_delay: Reloadable<bool>,
+}
+
+impl ActiveSettings {
+    pub(crate) async fn new(
+        parachain_rpc: &InterBtcParachain,
+        settings: ReloadableSettings,
+    ) -> Result<Self, RuntimeError> {
+        Ok(Self {
+            random_delay: Reloadable::new(random_delay(parachain_rpc, settings.no_random_delay).await?),
+            fee_caps: Reloadable::new(settings.fee_caps),
+            replace_policy: Reloadable::new(settings.replace_policy),
+            no_random_delay: Reloadable::new(settings.no_random_delay),
+        })
+    }
+
+    async fn apply(&self, parachain_rpc: &InterBtcParachain, settings: ReloadableSettings) -> Result<(), RuntimeError> {
+        if settings.no_random_delay != self.no_random_delay.get() {
+            self.random_delay
+                .set(random_delay(parachain_rpc, settings.no_random_delay).await?);
+            self.no_random_delay.set(settings.no_random_delay);
+        }
+        self.fee_caps.set(settings.fee_caps);
+        self.replace_policy.set(settings.replace_policy);
+        Ok(())
+    }
+}
+
+async fn random_delay(
+    parachain_rpc: &InterBtcParachain,
+    no_random_delay: bool,
+) -> Result<Arc<Box<dyn RandomDelay + Send + Sync>>, RuntimeError> {
+    if no_random_delay {
+        Ok(Arc::new(Box::new(ZeroDelay)))
+    } else {
+        Ok(Arc::new(Box::new(
+            OrderedVaultsDelay::new(parachain_rpc.clone()).await?,
+        )))
+    }
+}
+
+/// Applies the settings sent on `receiver` to the running tasks.
+pub(crate) async fn apply_reloaded_settings(
+    parachain_rpc: InterBtcParachain,
+    mut receiver: SettingsReceiver,
+    active: ActiveSettings,
+) -> Result<(), ServiceError> {
+    while receiver.changed().await.is_ok() {
+        let settings = receiver.borrow().clone();
+        tracing::info!("Applying reloaded settings: {:?}", settings);
+        active.apply(&parachain_rpc, settings).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replace_policy::AmountLimits;
+    use runtime::{AccountId, Token, DOT, IBTC};
+
+    fn candidate(amount: u128) -> ReplaceCandidate {
+        let vault_id = VaultId::new(AccountId::new([1; 32]), Token(DOT), Token(IBTC));
+        ReplaceCandidate {
+            old_vault_id: vault_id.clone(),
+            new_vault_id: vault_id,
+            amount,
+            required_collateral: 0,
+            total_collateral: 0,
+        }
+    }
+
+    #[test]
+    fn test_reloaded_policy_applies_to_clones() {
+        let initial: Arc<Box<dyn ReplacePolicy + Send + Sync>> = Arc::new(Box::new(AmountLimits {
+            min: None,
+            max: Some(100),
+        }));
+        let policy = Reloadable::new(initial);
+        let in_use = policy.clone();
+        assert!(in_use.check(&candidate(1000)).is_err());
+
+        policy.set(Arc::new(Box::new(AmountLimits {
+            min: None,
+            max: Some(10_000),
+        })));
+        assert!(in_use.check(&candidate(1000)).is_ok());
+    }
+}
@@ -1,5 +1,12 @@
 use crate::{
-    cancellation::Event, error::Error, execution::Request, metrics::publish_expected_bitcoin_balance,
+    cancellation::Event,
+    deadline::DeadlineEscalation,
+    error::Error,
+    execution::{FeeCaps, Request},
+    metrics::publish_expected_bitcoin_balance,
+    notify::{Notification, Notifier},
+    reload::Reloadable,
+    replace_policy::{ReplaceCandidate, ReplacePolicy},
     system::VaultIdManager,
 };
 use bitcoin::Error as BitcoinError;
@@ -9,7 +16,7 @@ use runtime::{
     PrettyPrint, ReplacePallet, RequestReplaceEvent, UtilFuncs, VaultId, VaultRegistryPallet,
 };
 use service::{spawn_cancelable, DynBitcoinCoreApi, Error as ServiceError, ShutdownSender};
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 /// Listen for AcceptReplaceEvent directed at this vault and continue the replacement
 /// procedure by transferring bitcoin and calling execute_replace
@@ -19,6 +26,9 @@ use std::time::Duration;
 /// * `parachain_rpc` - the parachain RPC handle
 /// * `btc_rpc` - the bitcoin RPC handle
 /// * `num_confirmations` - the number of bitcoin confirmation to await
+/// * `notifier` - notifies the operator about paid and failed requests
+/// * `escalation` - when to escalate payments that are not confirming in time
+#[allow(clippy::too_many_arguments)]
 pub async fn listen_for_accept_replace(
     shutdown_tx: ShutdownSender,
     parachain_rpc: InterBtcParachain,
@@ -26,10 +36,16 @@ pub async fn listen_for_accept_replace(
     num_confirmations: u32,
     payment_margin: Duration,
     auto_rbf: bool,
+    fee_caps: Reloadable<FeeCaps>,
+    notifier: Notifier,
+    escalation: DeadlineEscalation,
 ) -> Result<(), ServiceError> {
     let parachain_rpc = &parachain_rpc;
     let vault_id_manager = &vault_id_manager;
     let shutdown_tx = &shutdown_tx;
+    let notifier = &notifier;
+    let escalation = &escalation;
+    let fee_caps = &fee_caps;
     parachain_rpc
         .on_event::<AcceptReplaceEvent, _, _, _>(
             |event| async move {
@@ -45,6 +61,10 @@ pub async fn listen_for_accept_replace(
                 // by reference. Since spawn requires static lifetimes, we will need to capture the
                 // arguments by value rather than by reference, so clone these:
                 let parachain_rpc = parachain_rpc.clone();
+                let notifier = notifier.clone();
+                let escalation = escalation.clone();
+                // reloaded fee caps apply to the payments started afterwards
+                let fee_caps = fee_caps.get();
                 // Spawn a new task so that we handle these events concurrently
                 spawn_cancelable(shutdown_tx.subscribe(), async move {
                     tracing::info!("Executing accept replace #{:?}", event.replace_id);
@@ -56,22 +76,40 @@ pub async fn listen_for_accept_replace(
                             payment_margin,
                         )?;
                         request
-                            .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf)
+                            .pay_and_execute(parachain_rpc, vault, num_confirmations, auto_rbf, fee_caps, escalation)
                             .await
                     }
                     .await;
 
                     match result {
-                        Ok(_) => tracing::info!(
-                            "Completed accept replace request #{} with amount {}",
-                            event.replace_id,
-                            event.amount
-                        ),
-                        Err(e) => tracing::error!(
-                            "Failed to process accept replace request #{}: {}",
-                            event.replace_id,
-                            e.to_string()
-                        ),
+                        Ok(_) => {
+                            tracing::info!(
+                                "Completed accept replace request #{} with amount {}",
+                                event.replace_id,
+                                event.amount
+                            );
+                            notifier.notify(Notification::ReplacePaid {
+                                vault_id: event.old_vault_id.pretty_print(),
+                                replace_id: event.replace_id,
+                            });
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Failed to process accept replace request #{}: {}",
+                                event.replace_id,
+                                e.to_string()
+                            );
+                            if let Some(notification) =
+                                Notification::from_payment_error(&event.old_vault_id, event.replace_id, &e)
+                            {
+                                notifier.notify(notification);
+                            }
+                            notifier.notify(Notification::ReplaceFailed {
+                                vault_id: event.old_vault_id.pretty_print(),
+                                replace_id: event.replace_id,
+                                reason: e.to_string(),
+                            });
+                        }
                     }
                 });
             },
@@ -88,15 +126,18 @@ pub async fn listen_for_accept_replace(
 /// * `parachain_rpc` - the parachain RPC handle
 /// * `event_channel` - the channel over which to signal events
 /// * `accept_replace_requests` - if true, we attempt to accept replace requests
+/// * `policy` - decides which requests to accept, and with which vault
 pub async fn listen_for_replace_requests(
     parachain_rpc: InterBtcParachain,
     btc_rpc: VaultIdManager,
     event_channel: Sender<Event>,
     accept_replace_requests: bool,
+    policy: Arc<Box<dyn ReplacePolicy + Send + Sync>>,
 ) -> Result<(), ServiceError> {
     let parachain_rpc = &parachain_rpc;
     let btc_rpc = &btc_rpc;
     let event_channel = &event_channel;
+    let policy = &policy;
     parachain_rpc
         .on_event::<RequestReplaceEvent, _, _, _>(
             |event| async move {
@@ -112,8 +153,18 @@ pub async fn listen_for_replace_requests(
                 );
 
                 if accept_replace_requests {
-                    for (vault_id, btc_rpc) in btc_rpc.get_vault_btc_rpcs().await {
-                        match handle_replace_request(parachain_rpc.clone(), btc_rpc.clone(), &event, &vault_id).await {
+                    let mut vaults = btc_rpc.get_vault_btc_rpcs().await;
+                    vaults.sort_by_key(|(vault_id, _)| policy.vault_priority(vault_id));
+                    for (vault_id, btc_rpc) in vaults {
+                        match handle_replace_request(
+                            parachain_rpc.clone(),
+                            btc_rpc.clone(),
+                            &event,
+                            &vault_id,
+                            &***policy,
+                        )
+                        .await
+                        {
                             Ok(_) => {
                                 tracing::info!(
                                     "[{}] Accepted replace request from {}",
@@ -142,13 +193,14 @@ pub async fn listen_for_replace_requests(
     Ok(())
 }
 
-/// Attempts to accept a replace request. Does not retry RPC calls upon
-/// failure, since nothing is at stake at this point
+/// Attempts to accept a replace request, if the policy allows it. Does not retry
+/// RPC calls upon failure, since nothing is at stake at this point
 pub async fn handle_replace_request<'a, P: CollateralBalancesPallet + ReplacePallet + VaultRegistryPallet>(
     parachain_rpc: P,
     btc_rpc: DynBitcoinCoreApi,
     event: &'a RequestReplaceEvent,
     vault_id: &'a VaultId,
+    policy: &'a (dyn ReplacePolicy + Send + Sync),
 ) -> Result<(), Error> {
     let collateral_currency = vault_id.collateral_currency();
 
@@ -162,18 +214,28 @@ pub async fn handle_replace_request<'a, P: CollateralBalancesPallet + ReplacePal
     let total_required_collateral = required_replace_collateral.saturating_add(used_collateral);
 
     if current_collateral < total_required_collateral {
-        Err(Error::InsufficientFunds)
-    } else {
-        Ok(parachain_rpc
-            .accept_replace(
-                vault_id,
-                &event.old_vault_id,
-                event.amount,
-                0, // do not lock any additional collateral
-                BtcAddress::from_address(btc_rpc.get_new_address().await?).map_err(BitcoinError::ConversionError)?,
-            )
-            .await?)
+        return Err(Error::InsufficientFunds);
     }
+
+    policy
+        .check(&ReplaceCandidate {
+            old_vault_id: event.old_vault_id.clone(),
+            new_vault_id: vault_id.clone(),
+            amount: event.amount,
+            required_collateral: total_required_collateral,
+            total_collateral: current_collateral,
+        })
+        .map_err(Error::ReplaceRejected)?;
+
+    Ok(parachain_rpc
+        .accept_replace(
+            vault_id,
+            &event.old_vault_id,
+            event.amount,
+            0, // do not lock any additional collateral
+            BtcAddress::from_address(btc_rpc.get_new_address().await?).map_err(BitcoinError::ConversionError)?,
+        )
+        .await?)
 }
 
 /// Listen for ExecuteReplaceEvent directed at this vault and continue the replacement
@@ -207,16 +269,17 @@ pub async fn listen_for_execute_replace(
 #[cfg(all(test, feature = "parachain-metadata-kintsugi-testnet"))]
 mod tests {
     use super::*;
+    use crate::replace_policy::{AcceptAll, AmountLimits, MaxCollateralUsage};
     use async_trait::async_trait;
     use bitcoin::{
         json, Address, Amount, BitcoinCoreApi, Block, BlockHash, BlockHeader, Error as BitcoinError, Network,
-        PrivateKey, PublicKey, SatPerVbyte, Transaction, TransactionMetadata, Txid,
+        PrivateKey, PublicKey, SatPerVbyte, Transaction, TransactionMetadata, Txid, WalletTransaction,
     };
     use runtime::{
-        AccountId, Balance, BtcAddress, BtcPublicKey, CurrencyId, Error as RuntimeError, InterBtcReplaceRequest,
-        InterBtcVault, Token, DOT, H256, IBTC,
+        AccountId, Balance, BtcAddress, BtcPublicKey, CurrencyId, Error as RuntimeError, FixedPointNumber, FixedU128,
+        InterBtcReplaceRequest, InterBtcVault, Token, DOT, H256, IBTC,
     };
-    use std::{str::FromStr, sync::Arc};
+    use std::str::FromStr;
 
     macro_rules! assert_err {
         ($result:expr, $err:pat) => {{
@@ -237,6 +300,7 @@ mod tests {
             async fn wait_for_block(&self, height: u32, num_confirmations: u32) -> Result<Block, BitcoinError>;
             fn get_balance(&self, min_confirmations: Option<u32>) -> Result<Amount, BitcoinError>;
             fn list_transactions(&self, max_count: Option<usize>) -> Result<Vec<json::ListTransactionResult>, BitcoinError>;
+            async fn list_wallet_transactions(&self, since_height: Option<u32>) -> Result<Vec<WalletTransaction>, BitcoinError>;
             async fn get_block_count(&self) -> Result<u64, BitcoinError>;
             async fn get_raw_tx(&self, txid: &Txid, block_hash: &BlockHash) -> Result<Vec<u8>, BitcoinError>;
             async fn get_transaction(&self, txid: &Txid, block_hash: Option<BlockHash>) -> Result<Transaction, BitcoinError>;
@@ -270,6 +334,14 @@ mod tests {
                 fee_rate: SatPerVbyte,
                 request_id: Option<H256>,
             ) -> Result<Txid, BitcoinError>;
+            async fn create_and_send_transaction_with_max_fee(
+                &self,
+                address: Address,
+                sat: u64,
+                fee_rate: SatPerVbyte,
+                max_fee: u64,
+                request_id: Option<H256>,
+            ) -> Result<Txid, BitcoinError>;
             async fn send_to_address(
                 &self,
                 address: Address,
@@ -311,6 +383,7 @@ mod tests {
         async fn register_vault(&self, vault_id: &VaultId, collateral: u128) -> Result<(), RuntimeError>;
         async fn deposit_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
         async fn withdraw_collateral(&self, vault_id: &VaultId, amount: u128) -> Result<(), RuntimeError>;
+        async fn set_accept_new_issues(&self, vault_id: &VaultId, accept_new_issues: bool) -> Result<(), RuntimeError>;
         async fn get_public_key(&self) -> Result<Option<BtcPublicKey>, RuntimeError>;
         async fn register_public_key(&self, public_key: BtcPublicKey) -> Result<(), RuntimeError>;
         async fn get_required_collateral_for_wrapped(&self, amount_btc: u128, collateral_currency: CurrencyId) -> Result<u128, RuntimeError>;
@@ -379,7 +452,7 @@ mod tests {
             griefing_collateral: Default::default(),
         };
         assert_err!(
-            handle_replace_request(parachain_rpc, btc_rpc, &event, &dummy_vault_id()).await,
+            handle_replace_request(parachain_rpc, btc_rpc, &event, &dummy_vault_id(), &AcceptAll).await,
             Error::InsufficientFunds
         );
     }
@@ -407,8 +480,64 @@ mod tests {
             amount: Default::default(),
             griefing_collateral: Default::default(),
         };
-        handle_replace_request(parachain_rpc, btc_rpc, &event, &dummy_vault_id())
+        handle_replace_request(parachain_rpc, btc_rpc, &event, &dummy_vault_id(), &AcceptAll)
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn test_handle_replace_request_rejected_by_policy() {
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(MockBitcoin::default());
+
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_get_required_collateral_for_wrapped()
+            .returning(|_, _| Ok(50));
+        parachain_rpc
+            .expect_get_required_collateral_for_vault()
+            .returning(|_| Ok(40));
+        parachain_rpc.expect_get_vault_total_collateral().returning(|_| Ok(100));
+        // no expectation on accept_replace: the mock panics if it is called
+
+        let event = RequestReplaceEvent {
+            old_vault_id: dummy_vault_id(),
+            amount: 1000,
+            griefing_collateral: Default::default(),
+        };
+        assert_err!(
+            handle_replace_request(
+                parachain_rpc,
+                btc_rpc.clone(),
+                &event,
+                &dummy_vault_id(),
+                &MaxCollateralUsage(FixedU128::from_float(0.8))
+            )
+            .await,
+            Error::ReplaceRejected(_)
+        );
+
+        let mut parachain_rpc = MockProvider::default();
+        parachain_rpc
+            .expect_get_required_collateral_for_wrapped()
+            .returning(|_, _| Ok(50));
+        parachain_rpc
+            .expect_get_required_collateral_for_vault()
+            .returning(|_| Ok(40));
+        parachain_rpc.expect_get_vault_total_collateral().returning(|_| Ok(100));
+
+        assert_err!(
+            handle_replace_request(
+                parachain_rpc,
+                btc_rpc,
+                &event,
+                &dummy_vault_id(),
+                &AmountLimits {
+                    min: Some(5000),
+                    max: None
+                }
+            )
+            .await,
+            Error::ReplaceRejected(_)
+        );
+    }
 }
@@ -0,0 +1,201 @@
+//! Policies deciding which replace requests our vaults accept.
+
+use runtime::{AccountId, CurrencyId, FixedPointNumber, FixedU128, PrettyPrint, VaultId};
+use std::fmt;
+
+/// A replace request, as it would be accepted by one of our vaults.
+#[derive(Debug, Clone)]
+pub struct ReplaceCandidate {
+    pub old_vault_id: VaultId,
+    pub new_vault_id: VaultId,
+    /// Amount of wrapped tokens to replace.
+    pub amount: u128,
+    /// Collateral the new vault requires after accepting the request.
+    pub required_collateral: u128,
+    /// Collateral currently locked by the new vault.
+    pub total_collateral: u128,
+}
+
+pub trait ReplacePolicy: fmt::Debug {
+    /// Returns the reason to reject the candidate, if any.
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String>;
+
+    /// Our vaults attempt to accept a request in ascending order of priority.
+    fn vault_priority(&self, _vault_id: &VaultId) -> usize {
+        0
+    }
+}
+
+/// Accepts any request that the vault can collateralize.
+#[derive(Clone, Debug)]
+pub struct AcceptAll;
+
+impl ReplacePolicy for AcceptAll {
+    fn check(&self, _candidate: &ReplaceCandidate) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AmountLimits {
+    pub min: Option<u128>,
+    pub max: Option<u128>,
+}
+
+impl ReplacePolicy for AmountLimits {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        match (self.min, self.max) {
+            (Some(min), _) if candidate.amount < min => Err(format!("amount is below the minimum of {min}")),
+            (_, Some(max)) if candidate.amount > max => Err(format!("amount is above the maximum of {max}")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Limits the fraction of its collateral the vault uses after accepting a request.
+#[derive(Clone, Debug)]
+pub struct MaxCollateralUsage(pub FixedU128);
+
+impl ReplacePolicy for MaxCollateralUsage {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        let usage = FixedU128::checked_from_rational(candidate.required_collateral, candidate.total_collateral)
+            .ok_or_else(|| "vault has no collateral".to_string())?;
+        if usage > self.0 {
+            Err(format!(
+                "collateral usage of {:.2}% exceeds the maximum of {:.2}%",
+                usage.to_float() * 100.0,
+                self.0.to_float() * 100.0
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum OldVaultFilter {
+    Allow(Vec<AccountId>),
+    Deny(Vec<AccountId>),
+}
+
+impl ReplacePolicy for OldVaultFilter {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        let account_id = &candidate.old_vault_id.account_id;
+        match self {
+            OldVaultFilter::Allow(accounts) if !accounts.contains(account_id) => Err(format!(
+                "{} is not on the allowlist",
+                candidate.old_vault_id.pretty_print()
+            )),
+            OldVaultFilter::Deny(accounts) if accounts.contains(account_id) => {
+                Err(format!("{} is on the denylist", candidate.old_vault_id.pretty_print()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Accepts requests only with the listed collateral currencies, trying them in order.
+#[derive(Clone, Debug)]
+pub struct CurrencyPreference(pub Vec<CurrencyId>);
+
+impl ReplacePolicy for CurrencyPreference {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        if self.0.contains(&candidate.new_vault_id.collateral_currency()) {
+            Ok(())
+        } else {
+            Err("collateral currency is not preferred".to_string())
+        }
+    }
+
+    fn vault_priority(&self, vault_id: &VaultId) -> usize {
+        let collateral_currency = vault_id.collateral_currency();
+        self.0
+            .iter()
+            .position(|currency_id| currency_id == &collateral_currency)
+            .unwrap_or(self.0.len())
+    }
+}
+
+/// Accepts requests that all of the given policies accept.
+#[derive(Debug, Default)]
+pub struct CombinedPolicy(pub Vec<Box<dyn ReplacePolicy + Send + Sync>>);
+
+impl ReplacePolicy for CombinedPolicy {
+    fn check(&self, candidate: &ReplaceCandidate) -> Result<(), String> {
+        self.0.iter().try_for_each(|policy| policy.check(candidate))
+    }
+
+    fn vault_priority(&self, vault_id: &VaultId) -> usize {
+        self.0
+            .iter()
+            .map(|policy| policy.vault_priority(vault_id))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::{Token, DOT, IBTC, KSM};
+
+    fn vault_id(account: u8, collateral: CurrencyId) -> VaultId {
+        VaultId::new(AccountId::new([account; 32]), collateral, Token(IBTC))
+    }
+
+    fn candidate(amount: u128, required_collateral: u128) -> ReplaceCandidate {
+        ReplaceCandidate {
+            old_vault_id: vault_id(1, Token(DOT)),
+            new_vault_id: vault_id(2, Token(DOT)),
+            amount,
+            required_collateral,
+            total_collateral: 100,
+        }
+    }
+
+    #[test]
+    fn test_amount_limits() {
+        let policy = AmountLimits {
+            min: Some(10),
+            max: Some(20),
+        };
+        assert!(policy.check(&candidate(9, 0)).is_err());
+        assert!(policy.check(&candidate(10, 0)).is_ok());
+        assert!(policy.check(&candidate(20, 0)).is_ok());
+        assert!(policy.check(&candidate(21, 0)).is_err());
+    }
+
+    #[test]
+    fn test_max_collateral_usage() {
+        let policy = MaxCollateralUsage(FixedU128::from_float(0.8));
+        assert!(policy.check(&candidate(10, 80)).is_ok());
+        assert!(policy.check(&candidate(10, 81)).is_err());
+    }
+
+    #[test]
+    fn test_old_vault_filter() {
+        let account = AccountId::new([1; 32]);
+        assert!(OldVaultFilter::Allow(vec![account.clone()])
+            .check(&candidate(10, 0))
+            .is_ok());
+        assert!(OldVaultFilter::Allow(vec![]).check(&candidate(10, 0)).is_err());
+        assert!(OldVaultFilter::Deny(vec![account]).check(&candidate(10, 0)).is_err());
+        assert!(OldVaultFilter::Deny(vec![]).check(&candidate(10, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_currency_preference() {
+        let policy = CombinedPolicy(vec![
+            Box::new(AmountLimits { min: None, max: None }),
+            Box::new(CurrencyPreference(vec![Token(KSM), Token(DOT)])),
+        ]);
+        assert!(policy.check(&candidate(10, 0)).is_ok());
+        assert_eq!(policy.vault_priority(&vault_id(2, Token(KSM))), 0);
+        assert_eq!(policy.vault_priority(&vault_id(2, Token(DOT))), 1);
+        assert_eq!(policy.vault_priority(&vault_id(2, Token(IBTC))), 2);
+
+        let mut new_candidate = candidate(10, 0);
+        new_candidate.new_vault_id = vault_id(2, Token(IBTC));
+        assert!(policy.check(&new_candidate).is_err());
+    }
+}
@@ -7,15 +7,18 @@ use futures::{
     Future, FutureExt, SinkExt, TryStreamExt,
 };
 use runtime::{
-    integration::*, types::*, BtcAddress, CurrencyId, FixedPointNumber, FixedU128, InterBtcParachain,
-    InterBtcRedeemRequest, IssuePallet, PartialAddress, RedeemPallet, ReplacePallet, SudoPallet, UtilFuncs, VaultId,
-    VaultRegistryPallet,
+    integration::*, types::*, BtcAddress, BtcRelayPallet, CurrencyId, FixedPointNumber, FixedU128, H256Le,
+    InterBtcParachain, InterBtcRedeemRequest, IssuePallet, PartialAddress, RedeemPallet, ReplacePallet, SudoPallet,
+    UtilFuncs, VaultId, VaultRegistryPallet,
 };
 use service::DynBitcoinCoreApi;
 use sp_core::{H160, H256};
 use sp_keyring::AccountKeyring;
 use std::{sync::Arc, time::Duration};
-use vault::{self, Event as CancellationEvent, IssueRequests, VaultIdManager, ZeroDelay};
+use vault::{
+    self, notify::Notifier, DeadlineEscalation, Event as CancellationEvent, FeeCaps, IssueRequests, VaultIdManager,
+    ZeroDelay,
+};
 
 const TIMEOUT: Duration = Duration::from_secs(90);
 
@@ -114,6 +117,9 @@ async fn test_redeem_succeeds() {
                     0,
                     Duration::from_secs(0),
                     true,
+                    FeeCaps::default().into(),
+                    Notifier::default(),
+                    DeadlineEscalation::default(),
                 ),
                 periodically_produce_blocks(user_provider.clone()),
             ),
@@ -203,6 +209,9 @@ async fn test_replace_succeeds() {
                     0,
                     Duration::from_secs(0),
                     true,
+                    FeeCaps::default().into(),
+                    Notifier::default(),
+                    DeadlineEscalation::default(),
                 ),
                 periodically_produce_blocks(old_vault_provider.clone()),
             ),
@@ -828,6 +837,8 @@ async fn test_execute_open_requests_succeeds() {
                 0,
                 Duration::from_secs(0),
                 true,
+                FeeCaps::default(),
+                DeadlineEscalation::default(),
             )
             .map(Result::unwrap),
             assert_redeem_event(TIMEOUT, user_provider.clone(), redeem_ids[0]),
@@ -846,6 +857,227 @@ async fn test_execute_open_requests_succeeds() {
     .await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_open_requests_succeeds_after_reorg() {
+    test_with_vault(|client, vault_id, vault_provider| async move {
+        let relayer_provider = setup_provider(client.clone(), AccountKeyring::Bob).await;
+        let user_provider = setup_provider(client.clone(), AccountKeyring::Dave).await;
+
+        let mock_bitcoin_core = MockBitcoinCore::new(relayer_provider.clone()).await;
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin_core.clone());
+
+        let btc_rpcs = vec![(vault_id.clone(), btc_rpc.clone())].into_iter().collect();
+        let btc_rpc_master_wallet = btc_rpc.clone();
+        let vault_id_manager = VaultIdManager::from_map(vault_provider.clone(), btc_rpc_master_wallet, btc_rpcs);
+
+        let issue_amount = 100000;
+        let vault_collateral =
+            get_required_vault_collateral_for_issue(&vault_provider, issue_amount, vault_id.collateral_currency())
+                .await;
+        assert_ok!(
+            vault_provider
+                .register_vault_with_public_key(
+                    &vault_id,
+                    vault_collateral,
+                    btc_rpc.get_new_public_key().await.unwrap().inner.serialize().into(),
+                )
+                .await
+        );
+
+        assert_issue(&user_provider, &btc_rpc, &vault_id, issue_amount).await;
+
+        let address = BtcAddress::P2PKH(H160::from_slice(&[2; 20]));
+        let redeem_id = user_provider.request_redeem(10000, address, &vault_id).await.unwrap();
+        let redeem = user_provider.get_redeem_request(redeem_id).await.unwrap();
+
+        // pay the redeem, then replace the block that included the payment by a longer branch
+        let metadata = btc_rpc
+            .send_to_address(
+                address.to_address(btc_rpc.network()).unwrap(),
+                redeem.amount_btc as u64,
+                Some(redeem_id),
+                SatPerVbyte(1000),
+                0,
+            )
+            .await
+            .unwrap();
+        let mut branch = mock_bitcoin_core.create_branch(metadata.block_height - 1).await;
+        for _ in 0..3 {
+            mock_bitcoin_core.extend_branch(&mut branch, vec![]).await;
+        }
+        mock_bitcoin_core.reorg_to(&branch).await;
+        assert_eq!(
+            btc_rpc.get_best_block_hash().await.unwrap(),
+            branch.blocks()[2].block_hash()
+        );
+        assert!(btc_rpc.is_in_mempool(metadata.txid).await.unwrap());
+
+        // the vault must find the payment in the mempool and wait for it to be included again
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
+        vault::service::execute_open_requests(
+            shutdown_tx.clone(),
+            vault_provider,
+            vault_id_manager,
+            btc_rpc.clone(),
+            0,
+            Duration::from_secs(0),
+            true,
+            FeeCaps::default(),
+            DeadlineEscalation::default(),
+        )
+        .await
+        .unwrap();
+
+        mock_bitcoin_core.flush_mempool().await;
+        test_service(
+            periodically_produce_blocks(user_provider.clone()),
+            assert_redeem_event(TIMEOUT, user_provider, redeem_id),
+        )
+        .await;
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_open_requests_retries_when_payment_is_on_fork() {
+    test_with_vault(|client, vault_id, vault_provider| async move {
+        let relayer_provider = setup_provider(client.clone(), AccountKeyring::Bob).await;
+        let user_provider = setup_provider(client.clone(), AccountKeyring::Dave).await;
+
+        let mock_bitcoin_core = MockBitcoinCore::new(relayer_provider.clone()).await;
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin_core.clone());
+
+        let btc_rpcs = vec![(vault_id.clone(), btc_rpc.clone())].into_iter().collect();
+        let btc_rpc_master_wallet = btc_rpc.clone();
+        let vault_id_manager = VaultIdManager::from_map(vault_provider.clone(), btc_rpc_master_wallet, btc_rpcs);
+
+        let issue_amount = 100000;
+        let vault_collateral =
+            get_required_vault_collateral_for_issue(&vault_provider, issue_amount, vault_id.collateral_currency())
+                .await;
+        assert_ok!(
+            vault_provider
+                .register_vault_with_public_key(
+                    &vault_id,
+                    vault_collateral,
+                    btc_rpc.get_new_public_key().await.unwrap().inner.serialize().into(),
+                )
+                .await
+        );
+
+        assert_issue(&user_provider, &btc_rpc, &vault_id, issue_amount).await;
+
+        let address = BtcAddress::P2PKH(H160::from_slice(&[2; 20]));
+        let redeem_id = user_provider.request_redeem(10000, address, &vault_id).await.unwrap();
+        let redeem = user_provider.get_redeem_request(redeem_id).await.unwrap();
+
+        let metadata = btc_rpc
+            .send_to_address(
+                address.to_address(btc_rpc.network()).unwrap(),
+                redeem.amount_btc as u64,
+                Some(redeem_id),
+                SatPerVbyte(1000),
+                0,
+            )
+            .await
+            .unwrap();
+
+        // relay a competing branch until the parachain switches to it, while our best chain
+        // still includes the payment
+        let mut branch = mock_bitcoin_core.create_branch(metadata.block_height - 1).await;
+        for _ in 0..10 {
+            let block = mock_bitcoin_core.extend_branch(&mut branch, vec![]).await;
+            if branch.blocks().len() >= 2
+                && relayer_provider.get_best_block().await.unwrap() == H256Le::from_bytes_le(&block.block_hash())
+            {
+                break;
+            }
+        }
+        assert_eq!(
+            relayer_provider.get_best_block().await.unwrap(),
+            H256Le::from_bytes_le(&branch.blocks().last().unwrap().block_hash())
+        );
+        assert!(relayer_provider
+            .verify_block_header_inclusion(H256Le::from_bytes_le(&metadata.block_hash))
+            .await
+            .unwrap_err()
+            .is_invalid_chain_id());
+
+        // the vault finds the payment in a block that is on a fork of the relay
+        let (shutdown_tx, _) = tokio::sync::broadcast::channel(16);
+        vault::service::execute_open_requests(
+            shutdown_tx.clone(),
+            vault_provider,
+            vault_id_manager,
+            btc_rpc.clone(),
+            0,
+            Duration::from_secs(0),
+            true,
+            FeeCaps::default(),
+            DeadlineEscalation::default(),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        // once we follow the relay, the payment is included again and the vault retries with it
+        mock_bitcoin_core.reorg_to(&branch).await;
+        mock_bitcoin_core.flush_mempool().await;
+        test_service(
+            periodically_produce_blocks(user_provider.clone()),
+            assert_redeem_event(TIMEOUT, user_provider, redeem_id),
+        )
+        .await;
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_relayer_submits_blocks_of_reorg() {
+    test_with(|client| async move {
+        let relayer_provider = setup_provider(client.clone(), AccountKeyring::Bob).await;
+
+        let mock_bitcoin_core = MockBitcoinCore::new(relayer_provider.clone()).await;
+        let btc_rpc: DynBitcoinCoreApi = Arc::new(mock_bitcoin_core.clone());
+        mock_bitcoin_core.mine_blocks(2).await;
+
+        // replace the last two relayed blocks by a longer branch that is not relayed
+        let height = btc_rpc.get_block_count().await.unwrap() as u32;
+        let mut branch = mock_bitcoin_core.create_branch(height - 2).await;
+        for _ in 0..4 {
+            mock_bitcoin_core.extend_branch_unrelayed(&mut branch, vec![]).await;
+        }
+        mock_bitcoin_core.reorg_to(&branch).await;
+
+        let runner = vault::service::Runner::new(
+            btc_rpc.clone(),
+            relayer_provider.clone(),
+            vault::service::Config {
+                start_height: None,
+                max_batch_size: 1,
+                interval: Some(Duration::from_secs(1)),
+                btc_confirmations: 0,
+            },
+            Arc::new(Box::new(ZeroDelay)),
+        );
+        // the relayer has to find the common ancestor, and skip the blocks of the branch that
+        // it submitted while the branch was still a fork of the relay
+        for _ in branch.blocks() {
+            runner.submit_next().await.unwrap();
+        }
+
+        assert_eq!(
+            relayer_provider.get_best_block_height().await.unwrap(),
+            branch.tip_height()
+        );
+        assert_eq!(
+            relayer_provider.get_best_block().await.unwrap(),
+            H256Le::from_bytes_le(&btc_rpc.get_best_block_hash().await.unwrap())
+        );
+    })
+    .await;
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_off_chain_liquidation() {
     test_with_vault(|client, vault_id, vault_provider| async move {
@@ -1247,6 +1479,9 @@ mod test_with_bitcoind {
                     0,
                     Duration::from_secs(0),
                     true,
+                    FeeCaps::default().into(),
+                    Notifier::default(),
+                    DeadlineEscalation::default(),
                 ),
                 vault_provider.listen_for_fee_rate_changes(),
             );